@@ -0,0 +1,583 @@
+//! The thread arenas of [`RustyMalloc`](super::RustyMalloc).
+//!
+//! Each thread allocates from its own arena, a [`RawMalloc`] whose heap is made of chunks
+//! allocated from the main heap. Arenas are created lazily on the first allocation of a thread
+//! and are reached through a thread local, which holds the arenas of up to [`THREAD_ARENA_SLOTS`]
+//! allocators. Threads using more allocators than that allocate from the main heap
+//! of the others.
+//!
+//! Chunks start at [`ARENA_CHUNK_MIN_SIZE`] bytes and double in size up to
+//! [`ARENA_CHUNK_MAX_SIZE`] bytes, unless an object needs a bigger one. Each chunk is a region
+//! of its arena's heap (see [`Grower::grow_region`]) and starts with a header linking it
+//! to the previous chunk:
+//! ```text
+//! | chunk header | region | foot |
+//! ```
+//! All chunks are kept in a table sorted by their addresses, so the arena owning an object
+//! is found with a binary search, no matter which thread frees the object.
+//!
+//! When a thread exits (see [`arm_exit_hook`]) its arenas become unowned and are handed over to the next thread
+//! needing one. Unowned arenas give their chunks back to the main heap once their last object
+//! is freed. The arenas and the chunk table are allocated from the internal heap shared by
+//! all allocators (see [`internal_heap`]), which is never unmapped, so threads can release
+//! their arenas even after the allocator has been dropped.
+
+use crate::allocators::internal::internal_heap;
+use crate::allocators::raw_malloc::{augment_layout, Placement};
+use crate::allocators::tcache;
+use crate::allocators::RawMalloc;
+use crate::error::MallocError;
+use crate::growers::{Grower, REGION_FOOT_SIZE};
+use crate::header::HEADER_ALIGN;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::ffi::c_void;
+use core::mem::{replace, size_of};
+use core::ptr::{self, null, null_mut, NonNull};
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, RwLock};
+
+use libc::{pthread_key_create, pthread_key_t, pthread_setspecific};
+use static_assertions::const_assert;
+
+/// The number of allocators a thread can have an arena of at the same time.
+pub const THREAD_ARENA_SLOTS: usize = 4;
+
+/// The size of the first chunk of an arena.
+pub const ARENA_CHUNK_MIN_SIZE: usize = 1 << 20;
+
+/// The size chunks stop doubling at.
+pub const ARENA_CHUNK_MAX_SIZE: usize = 64 << 20;
+
+/// The number of chunks the chunk table has room for when it's first allocated.
+const REGION_TABLE_MIN_CAPACITY: usize = 64;
+
+/// Hands out the ids allocators are told apart by in the thread-local arena slots.
+static NEXT_ALLOCATOR_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// The key whose destructor releases the arenas and flushes the thread cache
+/// of exiting threads, `None` if it couldn't be created.
+static EXIT_KEY: OnceLock<Option<pthread_key_t>> = OnceLock::new();
+
+/// The arena a thread allocates from for one allocator.
+#[derive(Clone, Copy)]
+struct ThreadArena {
+    /// The id of the allocator, 0 for free slots.
+    allocator: usize,
+    arena: *const (),
+    owned: *const AtomicBool,
+}
+
+const NO_ARENA: ThreadArena = ThreadArena {
+    allocator: 0,
+    arena: null(),
+    owned: null(),
+};
+
+thread_local! {
+    // Const-initialized thread locals without destructors never allocate,
+    // which makes them safe to use from within a global allocator.
+    // The arenas are released by the destructor of `EXIT_KEY` instead.
+    static THREAD_ARENAS: Cell<[ThreadArena; THREAD_ARENA_SLOTS]> =
+        const { Cell::new([NO_ARENA; THREAD_ARENA_SLOTS]) };
+}
+
+/// Marks the arenas of the exiting thread as unowned and flushes its cache.
+/// The arenas are released first, so they can give their chunks back
+/// once the cache has freed their last objects.
+unsafe extern "C" fn on_thread_exit(_: *mut c_void) {
+    let _ = THREAD_ARENAS.try_with(|slots| {
+        for slot in slots.replace([NO_ARENA; THREAD_ARENA_SLOTS]) {
+            if !slot.owned.is_null() {
+                (*slot.owned).store(false, Ordering::Release);
+            }
+        }
+    });
+    tcache::flush_at_exit();
+}
+
+/// Makes sure the arenas and the cache of the current thread are released when it exits.
+/// If that's not possible the arenas stay owned by the thread forever
+/// and the cached objects are never freed.
+pub(crate) fn arm_exit_hook() {
+    let key = EXIT_KEY.get_or_init(|| unsafe {
+        let mut key = 0;
+        (pthread_key_create(&mut key, Some(on_thread_exit)) == 0).then_some(key)
+    });
+    // Destructors are only called for keys with a non-null value.
+    if let Some(key) = *key {
+        unsafe { pthread_setspecific(key, NonNull::<c_void>::dangling().as_ptr()) };
+    }
+}
+
+/// The header at the start of every chunk.
+#[repr(C)]
+struct ChunkHeader {
+    /// The previous chunk of the arena, null for the first one.
+    prev: *mut ChunkHeader,
+    /// The size of the chunk, including its header.
+    size: usize,
+}
+
+/// The size of chunk headers, which keeps the regions after them aligned.
+pub(crate) const CHUNK_HEADER_SIZE: usize = size_of::<ChunkHeader>();
+
+const_assert!(CHUNK_HEADER_SIZE.is_multiple_of(HEADER_ALIGN));
+
+/// Returns the layout chunks of `size` bytes are allocated from the main heap with.
+fn chunk_layout(size: usize) -> Result<Layout, MallocError> {
+    Layout::from_size_align(size, HEADER_ALIGN).map_err(|_| MallocError::LayoutTooLarge)
+}
+
+/// The grower of an arena, which bumps the heap end through chunks allocated from the main heap.
+/// Every chunk is a new region, so objects bigger than the rest of the last chunk
+/// go through [`grow_region`](Grower::grow_region). The last [`REGION_FOOT_SIZE`] bytes
+/// of every chunk are kept past the end of its region. Chunks are never shrunk,
+/// arenas give them back as a whole once they are empty, see [`release_chunks`].
+#[derive(Debug)]
+pub(crate) struct ChunkGrower<T: Grower, P: Placement> {
+    // The main heap and the chunk table, which are set whenever the arena is locked
+    // since the allocator may have moved.
+    main: *const Mutex<RawMalloc<T, P>>,
+    regions: *const RwLock<RegionTable>,
+    arena: *const (),
+    // The last chunk, null before the first one.
+    chunk: *mut ChunkHeader,
+    heap_end: *mut u8,
+    chunk_bytes: usize,
+    // The size of the chunk objects after layout augmentation.
+    chunk_object_bytes: usize,
+    chunk_count: usize,
+}
+
+impl<T: Grower, P: Placement> ChunkGrower<T, P> {
+    const fn new(arena: *const ()) -> Self {
+        ChunkGrower {
+            main: null(),
+            regions: null(),
+            arena,
+            chunk: null_mut(),
+            heap_end: null_mut(),
+            chunk_bytes: 0,
+            chunk_object_bytes: 0,
+            chunk_count: 0,
+        }
+    }
+
+    /// Returns the total size of the chunks, including their headers.
+    pub fn chunk_bytes(&self) -> usize {
+        self.chunk_bytes
+    }
+
+    /// Returns the total size of the chunk objects of the main heap after layout augmentation,
+    /// that is including their redzones and trailing records.
+    pub fn chunk_object_bytes(&self) -> usize {
+        self.chunk_object_bytes
+    }
+
+    /// Returns the number of chunks.
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_count
+    }
+}
+
+unsafe impl<T: Grower, P: Placement> Grower for ChunkGrower<T, P> {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        let Some(heap_end) = NonNull::new(self.heap_end) else {
+            return Err(MallocError::OutOfMemory);
+        };
+        let region_end = self.chunk.cast::<u8>().add((*self.chunk).size - REGION_FOOT_SIZE);
+        if size > region_end as usize - heap_end.as_ptr() as usize {
+            return Err(MallocError::OutOfMemory);
+        }
+        self.heap_end = heap_end.as_ptr().add(size);
+        Ok((heap_end, size))
+    }
+
+    unsafe fn grow_region(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        let needed = size
+            .checked_add(CHUNK_HEADER_SIZE + REGION_FOOT_SIZE)
+            .ok_or(MallocError::LayoutTooLarge)?;
+        let last_size = match self.chunk.is_null() {
+            true => 0,
+            false => (*self.chunk).size,
+        };
+        let preferred = (last_size * 2)
+            .clamp(ARENA_CHUNK_MIN_SIZE, ARENA_CHUNK_MAX_SIZE)
+            .max(needed);
+
+        let main = &*self.main;
+        let (chunk, chunk_size) = {
+            let main = main.lock().unwrap();
+            match main.try_alloc_detailed(chunk_layout(preferred)?) {
+                Ok(chunk) => (chunk.as_ptr(), preferred),
+                // Fall back to a chunk just big enough for the object.
+                Err(_) if preferred > needed => (
+                    main.try_alloc_detailed(chunk_layout(needed)?)?.as_ptr(),
+                    needed,
+                ),
+                Err(e) => return Err(e),
+            }
+        };
+        let region = Region {
+            start: chunk,
+            end: chunk.add(chunk_size),
+            arena: self.arena,
+        };
+        if let Err(e) = (*self.regions).write().unwrap().insert(region) {
+            main.lock()
+                .unwrap()
+                .dealloc(chunk, chunk_layout(chunk_size)?);
+            return Err(e);
+        }
+
+        let header: *mut ChunkHeader = chunk.cast();
+        *header = ChunkHeader {
+            prev: self.chunk,
+            size: chunk_size,
+        };
+        self.chunk = header;
+        self.chunk_bytes += chunk_size;
+        self.chunk_object_bytes += augment_layout(chunk_layout(chunk_size)?)?.size();
+        self.chunk_count += 1;
+        let region_start = chunk.add(CHUNK_HEADER_SIZE);
+        self.heap_end = region_start.add(size);
+        Ok((NonNull::new_unchecked(region_start), size))
+    }
+}
+
+/// The heap of an arena.
+pub(crate) type ArenaHeap<T, P> = RawMalloc<ChunkGrower<T, P>, P>;
+
+/// Gives the chunks of `heap` back to the main heap and resets the heap, unless the heap
+/// still has objects. Returns the total size of the chunks that were given back.
+pub(crate) fn release_chunks<T: Grower, P: Placement>(heap: &mut ArenaHeap<T, P>) -> usize {
+    if heap.stats().in_use_bytes != 0 || heap.grower_mut().chunk.is_null() {
+        return 0;
+    }
+    // Records of the old heap are written before those of the new one.
+    heap.flush_trace();
+    let grower = heap.grower_mut();
+    let fresh_grower = ChunkGrower {
+        main: grower.main,
+        regions: grower.regions,
+        ..ChunkGrower::new(grower.arena)
+    };
+    let fresh =
+        unsafe { RawMalloc::with_grower_and_placement(fresh_grower, heap.placement().clone()) };
+    fresh.set_quarantine_budget(heap.quarantine_budget());
+    fresh.trace_to(heap.trace_fd());
+    let mut old = replace(heap, fresh);
+    let grower = old.grower_mut();
+
+    unsafe {
+        // The chunk table is never locked while holding the main heap's lock.
+        let mut regions = (*grower.regions).write().unwrap();
+        let mut chunk = grower.chunk;
+        while !chunk.is_null() {
+            regions.remove(chunk.cast());
+            chunk = (*chunk).prev;
+        }
+        drop(regions);
+
+        let main = (*grower.main).lock().unwrap();
+        let mut chunk = grower.chunk;
+        while !chunk.is_null() {
+            let ChunkHeader { prev, size } = chunk.read();
+            main.dealloc(
+                chunk.cast(),
+                Layout::from_size_align_unchecked(size, HEADER_ALIGN),
+            );
+            chunk = prev;
+        }
+    }
+    grower.chunk_bytes
+}
+
+/// A chunk of an arena.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    start: *mut u8,
+    end: *mut u8,
+    arena: *const (),
+}
+
+/// The chunks of all arenas of an allocator sorted by address.
+#[derive(Debug)]
+pub(crate) struct RegionTable {
+    regions: *mut Region,
+    len: usize,
+    capacity: usize,
+}
+
+// The table is only accessed through its lock.
+unsafe impl Send for RegionTable {}
+unsafe impl Sync for RegionTable {}
+
+impl RegionTable {
+    const fn new() -> Self {
+        RegionTable {
+            regions: null_mut(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[Region] {
+        match self.regions.is_null() {
+            true => &[],
+            false => unsafe { slice::from_raw_parts(self.regions, self.len) },
+        }
+    }
+
+    /// Returns the chunk containing `ptr`, if any.
+    fn find(&self, ptr: *const u8) -> Option<&Region> {
+        let regions = self.as_slice();
+        let i = regions.partition_point(|region| region.start.cast_const() <= ptr);
+        regions[..i]
+            .last()
+            .filter(|region| ptr < region.end.cast_const())
+    }
+
+    fn insert(&mut self, region: Region) -> Result<(), MallocError> {
+        if self.len == self.capacity {
+            let capacity = (self.capacity * 2).max(REGION_TABLE_MIN_CAPACITY);
+            let layout =
+                Layout::array::<Region>(capacity).map_err(|_| MallocError::LayoutTooLarge)?;
+            let heap = internal_heap();
+            let regions = match self.regions.is_null() {
+                true => heap.try_alloc_detailed(layout)?,
+                false => unsafe {
+                    let old_layout = Layout::array::<Region>(self.capacity).unwrap();
+                    heap.try_realloc_detailed(
+                        self.regions.cast(),
+                        old_layout,
+                        layout.size(),
+                    )?
+                },
+            };
+            self.regions = regions.as_ptr().cast();
+            self.capacity = capacity;
+        }
+        let i = self.as_slice().partition_point(|r| r.start < region.start);
+        unsafe {
+            ptr::copy(self.regions.add(i), self.regions.add(i + 1), self.len - i);
+            self.regions.add(i).write(region);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, start: *mut u8) {
+        let i = self.as_slice().partition_point(|r| r.start < start);
+        debug_assert_eq!(self.as_slice()[i].start, start);
+        unsafe {
+            ptr::copy(
+                self.regions.add(i + 1),
+                self.regions.add(i),
+                self.len - i - 1,
+            )
+        };
+        self.len -= 1;
+    }
+
+}
+
+impl Drop for RegionTable {
+    fn drop(&mut self) {
+        if !self.regions.is_null() {
+            let layout = Layout::array::<Region>(self.capacity).unwrap();
+            unsafe { internal_heap().dealloc(self.regions.cast(), layout) };
+        }
+    }
+}
+
+/// Moves `value` to the internal heap, where it stays for good.
+fn leak_internal<V>(value: V) -> Option<*mut V> {
+    let ptr = internal_heap().try_alloc_detailed(Layout::new::<V>()).ok()?;
+    let ptr = ptr.as_ptr().cast::<V>();
+    unsafe { ptr.write(value) };
+    Some(ptr)
+}
+
+/// A thread arena.
+#[derive(Debug)]
+pub(crate) struct Arena<T: Grower, P: Placement> {
+    /// Whether a thread allocates from the arena.
+    owned: AtomicBool,
+    heap: Mutex<ArenaHeap<T, P>>,
+    /// The arena created before this one, if any.
+    next: *const Arena<T, P>,
+}
+
+impl<T: Grower, P: Placement> Arena<T, P> {
+    /// Locks the arena's heap, which allocates its chunks from `main`.
+    pub fn lock<'a>(
+        &'a self,
+        arenas: &Arenas<T, P>,
+        main: &Mutex<RawMalloc<T, P>>,
+    ) -> MutexGuard<'a, ArenaHeap<T, P>> {
+        let mut heap = self.heap.lock().unwrap();
+        let grower = heap.grower_mut();
+        grower.main = main;
+        grower.regions = &arenas.regions;
+        heap
+    }
+
+    /// Returns whether a thread allocates from the arena.
+    pub fn is_owned(&self) -> bool {
+        self.owned.load(Ordering::Acquire)
+    }
+
+    /// Returns the arena created before this one, if any.
+    pub fn next(&self) -> Option<&Arena<T, P>> {
+        unsafe { self.next.as_ref() }
+    }
+}
+
+/// The thread arenas of an allocator.
+#[derive(Debug)]
+pub(crate) struct Arenas<T: Grower, P: Placement> {
+    /// The id of the allocator in the thread-local arena slots, 0 until first needed.
+    id: AtomicUsize,
+    /// The most recently created arena.
+    head: AtomicPtr<Arena<T, P>>,
+    /// Held while creating arenas, so the settings they copy from the main heap
+    /// can't change in the meantime.
+    create_lock: Mutex<()>,
+    regions: RwLock<RegionTable>,
+}
+
+impl<T: Grower, P: Placement> Arenas<T, P> {
+    pub const fn new() -> Self {
+        Arenas {
+            id: AtomicUsize::new(0),
+            head: AtomicPtr::new(null_mut()),
+            create_lock: Mutex::new(()),
+            regions: RwLock::new(RegionTable::new()),
+        }
+    }
+
+    /// Returns the most recently created arena, if any.
+    pub fn head(&self) -> Option<&Arena<T, P>> {
+        unsafe { self.head.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Returns an iterator over all arenas, from the most recently created one.
+    pub fn iter(&self) -> impl Iterator<Item = &Arena<T, P>> {
+        core::iter::successors(self.head(), |arena| arena.next())
+    }
+
+    /// Prevents new arenas from being created until the returned guard is dropped.
+    pub fn lock_creation(&self) -> MutexGuard<'_, ()> {
+        self.create_lock.lock().unwrap()
+    }
+
+    /// Returns the arena owning `ptr` or `None` if `ptr` was allocated from the main heap.
+    pub fn owner(&self, ptr: *const u8) -> Option<&Arena<T, P>> {
+        let regions = self.regions.read().unwrap();
+        let arena = regions.find(ptr)?.arena.cast::<Arena<T, P>>();
+        Some(unsafe { &*arena })
+    }
+
+    /// Returns whether `ptr` points to a chunk of an arena.
+    pub fn is_chunk(&self, ptr: *const u8) -> bool {
+        self.regions.read().unwrap().find(ptr).is_some()
+    }
+
+    /// Returns the arena of the current thread, acquiring one if the thread doesn't have any yet.
+    /// Returns `None` if the thread has to allocate from the main heap.
+    pub fn thread_arena(&self, main: &Mutex<RawMalloc<T, P>>) -> Option<&Arena<T, P>> {
+        let id = self.id();
+        THREAD_ARENAS
+            .try_with(|slots| {
+                let mut arenas = slots.get();
+                if let Some(slot) = arenas.iter().find(|slot| slot.allocator == id) {
+                    return Some(unsafe { &*slot.arena.cast::<Arena<T, P>>() });
+                }
+                // Slots of dropped allocators are free again.
+                let free = arenas.iter_mut().find(|slot| {
+                    slot.owned.is_null() || unsafe { !(*slot.owned).load(Ordering::Acquire) }
+                })?;
+                let arena = self.acquire(main)?;
+                *free = ThreadArena {
+                    allocator: id,
+                    arena: (arena as *const Arena<T, P>).cast(),
+                    owned: &arena.owned,
+                };
+                // The slot is taken before arming the hook, which might allocate.
+                slots.set(arenas);
+                arm_exit_hook();
+                Some(arena)
+            })
+            .ok()
+            .flatten()
+    }
+
+    /// Returns the allocator's id, assigning it if necessary.
+    fn id(&self) -> usize {
+        let id = self.id.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new_id = NEXT_ALLOCATOR_ID.fetch_add(1, Ordering::Relaxed);
+        match self
+            .id
+            .compare_exchange(0, new_id, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => new_id,
+            Err(id) => id,
+        }
+    }
+
+    /// Takes over an unowned arena or creates a new one with the settings of `main`.
+    /// Returns `None` if the arena couldn't be allocated.
+    fn acquire(&self, main: &Mutex<RawMalloc<T, P>>) -> Option<&Arena<T, P>> {
+        let unowned = self.iter().find(|arena| {
+            arena
+                .owned
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        });
+        if unowned.is_some() {
+            return unowned;
+        }
+
+        let _creating = self.lock_creation();
+        let heap = {
+            let main = main.lock().unwrap();
+            let heap = unsafe {
+                RawMalloc::with_grower_and_placement(
+                    ChunkGrower::new(null()),
+                    main.placement().clone(),
+                )
+            };
+            heap.set_quarantine_budget(main.quarantine_budget());
+            heap.trace_to(main.trace_fd());
+            heap
+        };
+        let arena = leak_internal(Arena {
+            owned: AtomicBool::new(true),
+            heap: Mutex::new(heap),
+            next: self.head.load(Ordering::Relaxed),
+        })?;
+        unsafe {
+            let grower = (*arena).heap.get_mut().unwrap().grower_mut();
+            grower.arena = arena.cast_const().cast();
+            // Arenas are only ever pushed while holding the creation lock.
+            self.head.store(arena, Ordering::Release);
+            Some(&*arena)
+        }
+    }
+}
+
+impl<T: Grower, P: Placement> Drop for Arenas<T, P> {
+    fn drop(&mut self) {
+        // Frees the thread-local slots of the arenas, which stay allocated.
+        for arena in self.iter() {
+            arena.owned.store(false, Ordering::Release);
+        }
+    }
+}
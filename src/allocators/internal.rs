@@ -0,0 +1,28 @@
+//! The internal heap shared by all allocators of the process.
+//!
+//! Bookkeeping that must not recurse into the allocator it's kept for, like the thread arenas
+//! and their chunk table or the samples of the heap profiler, is allocated from this heap.
+//! Its address range is reserved once for the whole process instead of once per allocator,
+//! and it's never unmapped, so memory that outlives its allocator stays valid.
+
+use crate::allocators::RawMalloc;
+use crate::growers::MmapGrower;
+
+use std::sync::{Mutex, MutexGuard};
+
+/// The size of the address range reserved for the internal heap.
+const INTERNAL_HEAP_RESERVE_SIZE: usize = 1 << 30;
+
+struct InternalHeap(Mutex<RawMalloc<MmapGrower>>);
+
+// The heap is only accessed through its lock.
+unsafe impl Sync for InternalHeap {}
+
+static INTERNAL_HEAP: InternalHeap = InternalHeap(Mutex::new(unsafe {
+    RawMalloc::with_grower(MmapGrower::new(INTERNAL_HEAP_RESERVE_SIZE, 1 << 16))
+}));
+
+/// Locks the internal heap. No other lock may be taken while holding it.
+pub(crate) fn internal_heap() -> MutexGuard<'static, RawMalloc<MmapGrower>> {
+    INTERNAL_HEAP.0.lock().unwrap()
+}
@@ -1,7 +1,11 @@
 //! The [`RawMalloc`] and [`RustyMalloc`] allocators.
 
+mod arenas;
+mod internal;
+pub mod profile;
 pub mod raw_malloc;
 pub mod rusty_malloc;
+mod tcache;
 
 pub use raw_malloc::RawMalloc;
 pub use rusty_malloc::RustyMalloc;
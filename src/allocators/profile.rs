@@ -0,0 +1,291 @@
+//! Sampling heap profiler, see [`RustyMalloc::with_profiling`].
+//!
+//! Like the profilers of jemalloc and tcmalloc, allocations are sampled once every
+//! `sample_interval` bytes on average. Each thread counts down the bytes it allocates,
+//! and the allocation that brings the count to zero is sampled, after which a new count
+//! is drawn from an exponential distribution. This makes the chance of an allocation
+//! being sampled proportional to its size, while keeping the sampling free of
+//! periodic patterns in the workload.
+//!
+//! The call stacks of sampled allocations are kept in a hash table keyed by the object
+//! pointers, whose nodes are allocated from the internal heap shared by all allocators
+//! so recording a sample never recurses into the profiled allocator.
+//!
+//! [`RustyMalloc::with_profiling`]: super::RustyMalloc::with_profiling
+
+use crate::allocators::internal::internal_heap;
+use crate::allocators::raw_malloc::callsites::{capture_frames, CALLSITE_DEPTH};
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::ptr::{null_mut, NonNull};
+use core::slice;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::io::{self, Write};
+
+/// The default sampling interval in bytes, the same as the one of jemalloc.
+pub const DEFAULT_SAMPLE_INTERVAL: usize = 1 << 19;
+
+/// The number of buckets of the sample table is `1 << BUCKET_BITS`.
+const BUCKET_BITS: u32 = 10;
+const BUCKET_COUNT: usize = 1 << BUCKET_BITS;
+
+thread_local! {
+    // The number of bytes the current thread allocates before the next sample,
+    // 0 before its first allocation.
+    static BYTES_UNTIL_SAMPLE: Cell<usize> = const { Cell::new(0) };
+    static RNG_STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A sampled live object and the call stack it was allocated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileSample {
+    /// The object pointer.
+    pub ptr: *const u8,
+    /// The size the object was allocated (or last reallocated) with.
+    pub size: usize,
+    frames: [usize; CALLSITE_DEPTH],
+    depth: usize,
+}
+
+impl ProfileSample {
+    /// Returns the return addresses of the call stack, innermost first.
+    /// The innermost frames belong to the allocator itself.
+    pub fn frames(&self) -> &[usize] {
+        &self.frames[..self.depth]
+    }
+}
+
+/// A node of the sample table.
+struct Node {
+    next: AtomicPtr<Node>,
+    sample: ProfileSample,
+}
+
+/// The live samples of a [`RustyMalloc`](super::RustyMalloc).
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    // The mean number of bytes between samples, 0 if profiling is disabled.
+    sample_interval: usize,
+    // Singly-linked lists of nodes whose objects hash to the same bucket.
+    // They are only modified while holding the internal heap's lock, but the heads are read
+    // without it so objects that certainly weren't sampled are freed without locking.
+    buckets: [AtomicPtr<Node>; BUCKET_COUNT],
+    samples: AtomicUsize,
+}
+
+/// Returns the bucket of `ptr`.
+#[inline(always)]
+fn bucket(ptr: *const u8) -> usize {
+    // Fibonacci hashing, which takes the bucket from the well-mixed upper bits.
+    (ptr as usize).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize) >> (usize::BITS - BUCKET_BITS)
+}
+
+/// Returns a uniformly distributed number in `(0, 1]`, drawn with the xorshift64*
+/// generator of the current thread.
+fn random_unit(state: &Cell<u64>) -> f64 {
+    let mut x = state.get();
+    if x == 0 {
+        // Seed each thread differently, with the address of its state.
+        x = state as *const Cell<u64> as u64 | 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.set(x);
+    let bits = x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+    (bits + 1) as f64 / (1_u64 << 53) as f64
+}
+
+/// Accounts for an allocation of `size` bytes by the current thread and returns whether
+/// it's sampled. Threads that are being torn down never sample.
+#[inline(always)]
+fn should_sample(size: usize, sample_interval: usize) -> bool {
+    if sample_interval == 1 {
+        return true;
+    }
+    BYTES_UNTIL_SAMPLE
+        .try_with(|remaining| {
+            if remaining.get() > size {
+                remaining.set(remaining.get() - size);
+                return false;
+            }
+            // Exponentially distributed intervals with the given mean.
+            let interval = RNG_STATE
+                .try_with(|state| -random_unit(state).ln() * sample_interval as f64)
+                .unwrap_or(sample_interval as f64);
+            remaining.set((interval as usize).max(1));
+            true
+        })
+        .unwrap_or(false)
+}
+
+impl Profiler {
+    pub const fn new(sample_interval: usize) -> Self {
+        Profiler {
+            sample_interval,
+            buckets: [const { AtomicPtr::new(null_mut()) }; BUCKET_COUNT],
+            samples: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the mean number of bytes between samples, 0 disables profiling.
+    pub const fn set_sample_interval(&mut self, sample_interval: usize) {
+        self.sample_interval = sample_interval;
+    }
+
+    /// Returns the mean number of bytes between samples, 0 if profiling is disabled.
+    #[inline(always)]
+    pub fn sample_interval(&self) -> usize {
+        self.sample_interval
+    }
+
+    /// Accounts for the allocation of the object pointed to by `ptr`,
+    /// which is sampled with a chance proportional to its `size`.
+    #[inline(always)]
+    pub fn record_alloc(&self, ptr: *const u8, size: usize) {
+        if self.sample_interval != 0 && !ptr.is_null() && should_sample(size, self.sample_interval)
+        {
+            self.insert(ptr, size);
+        }
+    }
+
+    /// Drops the sample of the object pointed to by `ptr`, if it was sampled.
+    /// Has to be called before the object is freed, since its memory might be
+    /// handed out to another thread right after.
+    #[inline(always)]
+    pub fn record_dealloc(&self, ptr: *const u8) {
+        if self.sample_interval != 0 && !self.buckets[bucket(ptr)].load(Ordering::Acquire).is_null()
+        {
+            self.remove(ptr);
+        }
+    }
+
+    #[inline(never)]
+    fn insert(&self, ptr: *const u8, size: usize) {
+        // The call stack is captured before locking, in case the unwinder allocates.
+        let mut frames = [0; CALLSITE_DEPTH];
+        let depth = capture_frames(&mut frames);
+
+        let heap = internal_heap();
+        let node: *mut Node = unsafe { heap.alloc(Layout::new::<Node>()) }.cast();
+        if node.is_null() {
+            // The sample is dropped if the internal heap is exhausted.
+            return;
+        }
+        let head = &self.buckets[bucket(ptr)];
+        let sample = ProfileSample {
+            ptr,
+            size,
+            frames,
+            depth,
+        };
+        unsafe {
+            node.write(Node {
+                next: AtomicPtr::new(head.load(Ordering::Relaxed)),
+                sample,
+            })
+        };
+        head.store(node, Ordering::Release);
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(never)]
+    fn remove(&self, ptr: *const u8) {
+        let heap = internal_heap();
+        let mut link = &self.buckets[bucket(ptr)];
+        loop {
+            let node = link.load(Ordering::Relaxed);
+            if node.is_null() {
+                return;
+            }
+            let node_ref = unsafe { &*node };
+            if node_ref.sample.ptr == ptr {
+                link.store(node_ref.next.load(Ordering::Relaxed), Ordering::Release);
+                unsafe { heap.dealloc(node.cast(), Layout::new::<Node>()) };
+                self.samples.fetch_sub(1, Ordering::Relaxed);
+                return;
+            }
+            link = &node_ref.next;
+        }
+    }
+
+    /// Calls `f` with a copy of the live samples, in no particular order.
+    /// The copy is taken from the internal heap, so `f` is free to allocate.
+    /// If the internal heap is exhausted `f` gets no samples.
+    pub fn with_samples<R>(&self, f: impl FnOnce(&[ProfileSample]) -> R) -> R {
+        let heap = internal_heap();
+        let count = self.samples.load(Ordering::Relaxed);
+        let layout = Layout::array::<ProfileSample>(count).unwrap();
+        let copy: *mut ProfileSample = match count {
+            0 => NonNull::dangling().as_ptr(),
+            _ => unsafe { heap.alloc(layout) }.cast(),
+        };
+        let mut copied = 0;
+        if !copy.is_null() {
+            for head in &self.buckets {
+                let mut node = head.load(Ordering::Relaxed);
+                while let Some(node_ref) = unsafe { node.as_ref() } {
+                    unsafe { copy.add(copied).write(node_ref.sample) };
+                    copied += 1;
+                    node = node_ref.next.load(Ordering::Relaxed);
+                }
+            }
+        }
+        debug_assert!(copy.is_null() || copied == count);
+        drop(heap);
+
+        let samples = match copy.is_null() {
+            true => &[][..],
+            false => unsafe { slice::from_raw_parts(copy, copied) },
+        };
+        let result = f(samples);
+        if count != 0 && !copy.is_null() {
+            unsafe { internal_heap().dealloc(copy.cast(), layout) };
+        }
+        result
+    }
+
+    /// Writes the live samples in the legacy heap profile format of gperftools,
+    /// see [`RustyMalloc::dump_profile`](super::RustyMalloc::dump_profile).
+    pub fn dump(&self, out: &mut impl Write) -> io::Result<()> {
+        self.with_samples(|samples| {
+            let bytes: usize = samples.iter().map(|sample| sample.size).sum();
+            writeln!(
+                out,
+                "heap profile: {}: {} [{}: {}] @ heap_v2/{}",
+                samples.len(),
+                bytes,
+                samples.len(),
+                bytes,
+                self.sample_interval
+            )?;
+            for sample in samples {
+                write!(out, "1: {} [1: {}] @", sample.size, sample.size)?;
+                for frame in sample.frames() {
+                    write!(out, " {frame:#x}")?;
+                }
+                writeln!(out)?;
+            }
+            // Lets offline tools map the frames back to object files.
+            if let Ok(maps) = std::fs::read_to_string("/proc/self/maps") {
+                write!(out, "\nMAPPED_LIBRARIES:\n{maps}")?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        let heap = internal_heap();
+        for head in &self.buckets {
+            let mut node = head.swap(null_mut(), Ordering::Relaxed);
+            while !node.is_null() {
+                let next = unsafe { (*node).next.load(Ordering::Relaxed) };
+                unsafe { heap.dealloc(node.cast(), Layout::new::<Node>()) };
+                node = next;
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Iteration over the blocks of the heap, see [`RawMalloc::blocks`].
+
+use super::placement::Placement;
+use super::regions::{RegionHeader, REGION_HEADER_SIZE};
+use super::{RawMalloc, BLOCK_CONTENT_MIN_SIZE};
+use crate::growers::Grower;
+use crate::header::{Header, HEADER_ALIGN, HEADER_SIZE};
+use crate::util::raw_ptr;
+
+/// A block of the heap as decoded from its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    /// The address of the block header.
+    pub start: *const u8,
+    /// The size of the block contents, excluding the header.
+    pub content_size: usize,
+    /// Whether the block is free or occupied by an object.
+    pub is_free: bool,
+}
+
+impl Block {
+    /// Returns the address of the block contents,
+    /// which for occupied blocks is the address of the object
+    /// (or of its left redzone if the `redzones` feature is enabled).
+    #[inline]
+    pub fn content_start(&self) -> *const u8 {
+        self.start.wrapping_add(HEADER_SIZE)
+    }
+
+    /// Returns the size of the whole block, including the header.
+    #[inline]
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.content_size
+    }
+}
+
+/// An iterator over the blocks of a [`RawMalloc`] heap, region by region
+/// and in increasing address order within each region, created by [`RawMalloc::blocks`].
+#[derive(Debug)]
+pub struct Blocks<'a, T: Grower, P: Placement> {
+    allocator: &'a RawMalloc<T, P>,
+    next: *const u8,
+    /// The header of the region after the one being walked, null while walking the last region.
+    next_region: *mut RegionHeader,
+}
+
+impl<T: Grower, P: Placement> Iterator for Blocks<'_, T, P> {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Block> {
+        let (start, remaining) = loop {
+            // The heap end is queried on every step since the allocator might be used
+            // while iterating.
+            let region_end = match self.next_region.is_null() {
+                true => unsafe { raw_ptr(self.allocator.heap_end()) },
+                false => unsafe { (*self.next_region).prev_end },
+            };
+            let start = self.next;
+            let remaining = (region_end as usize).saturating_sub(start as usize);
+            if start.is_null() || !(start as usize).is_multiple_of(HEADER_ALIGN) {
+                return None;
+            }
+            if remaining >= HEADER_SIZE {
+                break (start, remaining);
+            }
+            if self.next_region.is_null() {
+                return None;
+            }
+            unsafe {
+                self.next = self.next_region.cast::<u8>().add(REGION_HEADER_SIZE);
+                self.next_region = (*self.next_region).next;
+            }
+        };
+
+        let header: &Header = unsafe { &*start.cast() };
+        let content_size = header.content_size();
+        if content_size < BLOCK_CONTENT_MIN_SIZE || content_size > remaining - HEADER_SIZE {
+            // The heap was changed under the iterator.
+            self.next = core::ptr::null();
+            return None;
+        }
+
+        self.next = start.wrapping_add(HEADER_SIZE + content_size);
+        Some(Block {
+            start,
+            content_size,
+            is_free: header.is_tagged(),
+        })
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Returns an iterator over all blocks of the heap, from the start to the end of each
+    /// of its regions.
+    ///
+    /// The iterator reads the heap lazily, so if the allocator is used while iterating
+    /// the remaining blocks reflect the changed heap. Adjacent free blocks that weren't
+    /// merged yet are yielded separately.
+    pub fn blocks(&self) -> Blocks<'_, T, P> {
+        Blocks {
+            allocator: self,
+            next: raw_ptr(self.heap_start.get()),
+            next_region: self.regions.first.get(),
+        }
+    }
+}
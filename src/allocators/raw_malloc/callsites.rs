@@ -0,0 +1,227 @@
+//! Allocation call stacks for leak reports, recorded when the `callsites` feature is enabled.
+//!
+//! Call stacks are captured with the unwinder of the platform and deduplicated
+//! in a fixed-size global table, so recording them never allocates. Occupied blocks keep
+//! the index of their call stack in the word preceding their layout record.
+//! Once the table is full new call stacks are no longer recorded.
+//!
+//! The unwinding itself is shared with the [heap profiler](crate::allocators::profile).
+
+use super::hardened::LAYOUT_RECORD_SIZE;
+use super::util::content_size;
+use crate::header::HEADER_SIZE;
+
+use core::ffi::{c_int, c_void, CStr};
+use core::fmt::{self, Display};
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The maximal number of frames recorded per call stack.
+pub const CALLSITE_DEPTH: usize = 32;
+
+/// The number of distinct call stacks that can be recorded, 0 if the `callsites` feature
+/// is disabled.
+pub const CALLSITE_CAPACITY: usize = match cfg!(feature = "callsites") {
+    true => 1024,
+    false => 0,
+};
+
+/// The size of the call site record of occupied blocks.
+pub(super) const CALLSITE_RECORD_SIZE: usize = match cfg!(feature = "callsites") {
+    true => HEADER_SIZE,
+    false => 0,
+};
+
+/// The number of slots probed before giving up on recording a call stack.
+const MAX_PROBES: usize = 32;
+
+/// A recorded call stack, keyed by a nonzero hash of its frames.
+struct Slot {
+    hash: AtomicUsize,
+    frames: [AtomicUsize; CALLSITE_DEPTH],
+}
+
+static CALLSITES: [Slot; CALLSITE_CAPACITY] = [const {
+    Slot {
+        hash: AtomicUsize::new(0),
+        frames: [const { AtomicUsize::new(0) }; CALLSITE_DEPTH],
+    }
+}; CALLSITE_CAPACITY];
+
+/// The call stack an object was allocated (or last reallocated) from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Callsite {
+    index: usize,
+}
+
+impl Callsite {
+    /// Returns the return addresses of the recorded frames, innermost first.
+    /// The innermost frames belong to the allocator itself.
+    pub fn frames(&self) -> impl Iterator<Item = usize> + '_ {
+        CALLSITES[self.index]
+            .frames
+            .iter()
+            .map(|frame| frame.load(Ordering::Relaxed))
+            .take_while(|&frame| frame != 0)
+    }
+}
+
+/// Writes one frame per line, with the object file it belongs to and the offset into it
+/// (which is what `addr2line` expects), followed by the symbol name if it's exported.
+impl Display for Callsite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, frame) in self.frames().enumerate() {
+            write!(f, "    #{i} {frame:#x}")?;
+            let mut info = MaybeUninit::<libc::Dl_info>::uninit();
+            if unsafe { libc::dladdr(frame as *const c_void, info.as_mut_ptr()) } != 0 {
+                let info = unsafe { info.assume_init() };
+                if !info.dli_fname.is_null() {
+                    let file = unsafe { CStr::from_ptr(info.dli_fname) };
+                    let offset = frame - info.dli_fbase as usize;
+                    write!(f, " in {}+{offset:#x}", file.to_str().unwrap_or("?"))?;
+                }
+                if !info.dli_sname.is_null() {
+                    let symbol = unsafe { CStr::from_ptr(info.dli_sname) };
+                    write!(f, " ({})", symbol.to_str().unwrap_or("?"))?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+struct UnwindContext {
+    _private: [u8; 0],
+}
+
+/// `_URC_NO_REASON`, which continues the backtrace.
+const URC_NO_REASON: c_int = 0;
+/// `_URC_NORMAL_STOP`, which stops the backtrace.
+const URC_NORMAL_STOP: c_int = 4;
+
+type UnwindTraceFn = extern "C" fn(*mut UnwindContext, *mut c_void) -> c_int;
+
+extern "C" {
+    fn _Unwind_Backtrace(trace: UnwindTraceFn, arg: *mut c_void) -> c_int;
+    fn _Unwind_GetIP(context: *mut UnwindContext) -> usize;
+}
+
+/// The frames captured so far by [`capture_frames`].
+struct Frames<'a> {
+    frames: &'a mut [usize; CALLSITE_DEPTH],
+    len: usize,
+}
+
+extern "C" fn trace_frame(context: *mut UnwindContext, arg: *mut c_void) -> c_int {
+    let frames = unsafe { &mut *arg.cast::<Frames>() };
+    let ip = unsafe { _Unwind_GetIP(context) };
+    if ip == 0 || frames.len == CALLSITE_DEPTH {
+        return URC_NORMAL_STOP;
+    }
+    frames.frames[frames.len] = ip;
+    frames.len += 1;
+    URC_NO_REASON
+}
+
+/// Stores the return addresses of the current call stack in `frames`, innermost first,
+/// and returns how many were stored. Works whether or not the `callsites` feature is enabled.
+#[inline(never)]
+pub(crate) fn capture_frames(frames: &mut [usize; CALLSITE_DEPTH]) -> usize {
+    let mut captured = Frames { frames, len: 0 };
+    unsafe { _Unwind_Backtrace(trace_frame, (&mut captured as *mut Frames).cast()) };
+    captured.len
+}
+
+/// Captures the current call stack and returns the record for it,
+/// 0 if the `callsites` feature is disabled or the call stack couldn't be recorded.
+#[inline(never)]
+fn capture() -> usize {
+    if CALLSITE_CAPACITY == 0 {
+        return 0;
+    }
+    let mut frames = [0; CALLSITE_DEPTH];
+    let len = capture_frames(&mut frames);
+    let frames = &frames[..len];
+
+    // FNV-1a over the frames, 0 marks empty slots.
+    let hash = frames
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &frame| {
+            (hash ^ frame as u64).wrapping_mul(0x100_0000_01b3)
+        })
+        .max(1) as usize;
+
+    for probe in 0..MAX_PROBES {
+        let index = hash.wrapping_add(probe) % CALLSITE_CAPACITY;
+        let slot = &CALLSITES[index];
+        match slot
+            .hash
+            .compare_exchange(0, hash, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                for (recorded, &frame) in slot.frames.iter().zip(frames) {
+                    recorded.store(frame, Ordering::Relaxed);
+                }
+                return index + 1;
+            }
+            Err(recorded) if recorded == hash => return index + 1,
+            Err(_) => continue,
+        }
+    }
+    0
+}
+
+/// Returns the call site recorded by [`capture`], if any.
+pub(super) fn callsite(record: usize) -> Option<Callsite> {
+    record.checked_sub(1).map(|index| Callsite { index })
+}
+
+/// Records the current call stack as the call site of the occupied block
+/// whose contents start at `obj_start`.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+#[inline(always)]
+pub(super) unsafe fn record_callsite(obj_start: *mut u8) {
+    if CALLSITE_RECORD_SIZE == 0 {
+        return;
+    }
+    *callsite_record(obj_start) = capture();
+}
+
+/// Stores the call site `record` in the occupied block whose contents start at `obj_start`.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+#[inline(always)]
+pub(super) unsafe fn write_callsite_record(obj_start: *mut u8, record: usize) {
+    if CALLSITE_RECORD_SIZE == 0 {
+        return;
+    }
+    *callsite_record(obj_start) = record;
+}
+
+/// Returns the call site record of the occupied block whose contents start at `obj_start`,
+/// 0 if the `callsites` feature is disabled.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+#[inline(always)]
+pub(super) unsafe fn read_callsite_record(obj_start: *const u8) -> usize {
+    if CALLSITE_RECORD_SIZE == 0 {
+        return 0;
+    }
+    *callsite_record(obj_start.cast_mut())
+}
+
+/// Returns a pointer to the call site record, which precedes the layout record.
+#[inline(always)]
+unsafe fn callsite_record(obj_start: *mut u8) -> *mut usize {
+    let record_start = content_size(obj_start) - LAYOUT_RECORD_SIZE - CALLSITE_RECORD_SIZE;
+    obj_start.add(record_start).cast()
+}
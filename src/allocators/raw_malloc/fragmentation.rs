@@ -0,0 +1,113 @@
+//! Fragmentation metrics, see [`RawMalloc::fragmentation`].
+
+use super::placement::Placement;
+use super::RawMalloc;
+use crate::freelist::{Node, SIZE_CLASS_COUNT};
+use crate::growers::Grower;
+use crate::header::Header;
+use crate::util::raw_ptr;
+
+use core::ptr::null;
+
+/// The number of buckets of [`Fragmentation::histogram`].
+pub const HISTOGRAM_BUCKET_COUNT: usize = usize::BITS as usize;
+
+/// A summary of the free blocks of a heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// The number of free blocks.
+    pub free_blocks: usize,
+    /// The total content size of all free blocks.
+    pub free_bytes: usize,
+    /// The content size of the largest free block or 0 if there are no free blocks.
+    pub largest_free_block: usize,
+    /// The number of free blocks that directly follow another free block in the same region,
+    /// that is blocks that weren't coalesced with their predecessor yet.
+    ///
+    /// This is only meaningful without the `footers` feature, with it freed blocks
+    /// are coalesced immediately and this is always 0.
+    pub unmerged_free_blocks: usize,
+    /// Free block counts by content size, bucket `i` counts blocks
+    /// with a content size in `[2^i, 2^(i+1))`.
+    pub histogram: [usize; HISTOGRAM_BUCKET_COUNT],
+}
+
+impl Fragmentation {
+    /// Returns the external fragmentation of the heap, that is
+    /// `1 - largest_free_block / free_bytes`, or 0 if there are no free blocks.
+    ///
+    /// A value close to 0 means that most free memory is usable by a single allocation,
+    /// while a value close to 1 means that free memory is scattered across many small blocks.
+    pub fn external(&self) -> f64 {
+        match self.free_bytes {
+            0 => 0.0,
+            free_bytes => 1.0 - self.largest_free_block as f64 / free_bytes as f64,
+        }
+    }
+}
+
+impl Default for Fragmentation {
+    fn default() -> Self {
+        Fragmentation {
+            free_blocks: 0,
+            free_bytes: 0,
+            largest_free_block: 0,
+            unmerged_free_blocks: 0,
+            histogram: [0; HISTOGRAM_BUCKET_COUNT],
+        }
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Computes fragmentation metrics of the heap.
+    ///
+    /// This walks every block of the heap (see [`blocks`](RawMalloc::blocks)),
+    /// so it takes time linear in the size of the heap. It never allocates.
+    pub fn fragmentation(&self) -> Fragmentation {
+        let mut fragmentation = Fragmentation::default();
+        let mut prev_free = false;
+        let mut prev_end = null();
+        for block in self.blocks() {
+            // Blocks are never merged across region ends.
+            if block.start != prev_end {
+                prev_free = false;
+            }
+            if block.is_free {
+                fragmentation.free_blocks += 1;
+                fragmentation.free_bytes += block.content_size;
+                fragmentation.largest_free_block =
+                    fragmentation.largest_free_block.max(block.content_size);
+                fragmentation.unmerged_free_blocks += prev_free as usize;
+                fragmentation.histogram[block.content_size.ilog2() as usize] += 1;
+            }
+            prev_free = block.is_free;
+            prev_end = block.content_start().wrapping_add(block.content_size);
+        }
+        fragmentation
+    }
+
+    /// Returns the content size of the largest free block or 0 if there are no free blocks,
+    /// that is the size of the largest object that can be allocated without growing the heap
+    /// (provided its alignment doesn't require padding).
+    ///
+    /// Only the freelist of the largest nonempty size class is walked.
+    pub fn largest_free_block(&self) -> usize {
+        unsafe {
+            let freelists = &mut *self.freelists.get();
+            for class in (0..SIZE_CLASS_COUNT).rev() {
+                let mut largest = 0;
+                let mut p: *mut Node = raw_ptr(freelists.list(class).head());
+                while !p.is_null() {
+                    let block_header: &Header = &*p.cast::<Header>().sub(1);
+                    largest = largest.max(block_header.content_size());
+                    p = (*p).next;
+                }
+                // Blocks of a class are larger than all blocks of lower classes.
+                if largest != 0 {
+                    return largest;
+                }
+            }
+            0
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Validation of objects and layouts passed to `dealloc` and `realloc`,
+//! which is enforced when the `hardened` feature is enabled.
+//!
+//! Without these checks freeing an object twice pushes its block onto the freelists twice,
+//! which silently corrupts the heap. Instead hardened builds abort with a diagnostic.
+//!
+//! To catch objects that are freed with a different layout than they were allocated with,
+//! hardened builds reserve the last word of every occupied block's contents
+//! (where free blocks keep their footer if they have one) for a record of the object's layout.
+//! Builds with the `redzones` or the `cabi` feature keep this record as well,
+//! the latter since C callers free objects without passing their layouts.
+
+use super::placement::Placement;
+use super::redzones::{left_redzone_size, object_contents, try_object_contents};
+use super::util::{content_size, usable_size};
+use super::{RawMalloc, BLOCK_CONTENT_MIN_SIZE};
+use crate::growers::Grower;
+use crate::header::{Header, HEADER_ALIGN, HEADER_SIZE};
+use crate::util::raw_ptr;
+
+use core::alloc::Layout;
+use core::fmt::{self, Write};
+
+/// The size of the layout record at the end of occupied blocks.
+pub(super) const LAYOUT_RECORD_SIZE: usize = match cfg!(any(
+    feature = "hardened",
+    feature = "redzones",
+    feature = "cabi"
+)) {
+    true => HEADER_SIZE,
+    false => 0,
+};
+
+/// The number of low bits of a layout record that hold the base-2 logarithm of the alignment,
+/// the remaining bits hold the size.
+const ALIGN_BITS: u32 = usize::BITS.ilog2();
+
+/// The reason an object pointer was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum InvalidObject {
+    /// The pointer doesn't have header alignment.
+    Misaligned,
+    /// The pointer doesn't point inside the heap.
+    OutsideHeap,
+    /// The pointer points to a free block or inside one.
+    DoubleFree,
+    /// The header preceding the pointer is not a valid block header.
+    CorruptedHeader,
+}
+
+impl InvalidObject {
+    fn description(self) -> &'static str {
+        match self {
+            InvalidObject::Misaligned => "misaligned pointer",
+            InvalidObject::OutsideHeap => "pointer outside of the heap",
+            InvalidObject::DoubleFree => "double free",
+            InvalidObject::CorruptedHeader => "corrupted block header",
+        }
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Checks that `obj_start` points to a live object of this allocator.
+    ///
+    /// Besides the object's own header the neighbouring blocks are consulted,
+    /// so that stale headers left inside coalesced free blocks are also rejected.
+    /// Objects in quarantine are rejected as well.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    pub(super) unsafe fn check_object(&self, obj_start: *const u8) -> Result<(), InvalidObject> {
+        let (region_start, region_end) = self.check_bounds(obj_start)?;
+        let (region_start, region_end) = (region_start as usize, region_end as usize);
+        let addr = obj_start as usize;
+
+        let block_start = addr - HEADER_SIZE;
+        let block_header: &Header = &*(block_start as *const Header);
+        if block_header.is_tagged() {
+            return Err(InvalidObject::DoubleFree);
+        }
+        let content_size = block_header.content_size();
+        if content_size < BLOCK_CONTENT_MIN_SIZE || content_size > region_end - addr {
+            return Err(InvalidObject::CorruptedHeader);
+        }
+        if self.is_quarantined(block_start as *const u8) {
+            return Err(InvalidObject::DoubleFree);
+        }
+
+        // The successive block should know that this block is occupied.
+        // Nothing records it at the end of a sealed region.
+        let block_end = addr + content_size;
+        let next_prev_free = match block_end == region_end {
+            true => block_end == raw_ptr(self.heap_end()) as usize && self.tail_free.get(),
+            false => (*(block_end as *const Header)).prev_free(),
+        };
+        if next_prev_free {
+            return Err(InvalidObject::DoubleFree);
+        }
+
+        // A free predecessor should end right where this block starts.
+        if block_header.prev_free() {
+            if block_start - region_start < BLOCK_CONTENT_MIN_SIZE + HEADER_SIZE {
+                return Err(InvalidObject::CorruptedHeader);
+            }
+            let prev_footer: &Header = &*((block_start - HEADER_SIZE) as *const Header);
+            let prev_content_size = prev_footer.content_size();
+            if !prev_footer.is_tagged()
+                || prev_content_size > block_start - region_start - HEADER_SIZE
+            {
+                return Err(InvalidObject::DoubleFree);
+            }
+            let prev_block_start = block_start - HEADER_SIZE - prev_content_size;
+            let prev_block_header: &Header = &*(prev_block_start as *const Header);
+            if prev_block_header.with_prev_free(false) != *prev_footer {
+                return Err(InvalidObject::DoubleFree);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `ptr` has header alignment and points past the first header of a region
+    /// of the heap. Returns the bounds of that region.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    unsafe fn check_bounds(&self, ptr: *const u8) -> Result<(*mut u8, *mut u8), InvalidObject> {
+        if !(ptr as usize).is_multiple_of(HEADER_ALIGN) {
+            return Err(InvalidObject::Misaligned);
+        }
+        match self.region_of(ptr) {
+            Some((start, end)) if ptr as usize >= start as usize + HEADER_SIZE => Ok((start, end)),
+            _ => Err(InvalidObject::OutsideHeap),
+        }
+    }
+
+    /// Returns the start of the block contents of the object pointed to by `ptr`
+    /// like [`object_contents`], but if the `hardened` feature is enabled as well,
+    /// pointers outside of the heap are rejected before their left redzone is read
+    /// and an undecodable left redzone inside a free or quarantined block
+    /// is reported as a double free.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    #[inline(always)]
+    pub(super) unsafe fn enforce_object_contents(&self, ptr: *const u8, op: &str) -> *mut u8 {
+        if !cfg!(feature = "hardened") || !cfg!(feature = "redzones") {
+            return object_contents(ptr, op);
+        }
+        let in_free_block = || {
+            self.blocks().any(|block| {
+                let contents = block.content_start()..block.start.wrapping_add(block.size());
+                (block.is_free || self.is_quarantined(block.start)) && contents.contains(&ptr)
+            })
+        };
+        let invalid = match self.check_bounds(ptr) {
+            Ok(_) => match try_object_contents(ptr) {
+                Some(obj_start) => return obj_start,
+                None if in_free_block() => InvalidObject::DoubleFree,
+                None => return object_contents(ptr, op),
+            },
+            Err(invalid) => invalid,
+        };
+        abort(format_args!("{} of {:?} in {}", invalid.description(), ptr, op))
+    }
+
+    /// Aborts the process if `obj_start` isn't a live object of this allocator
+    /// and the `hardened` feature is enabled. `op` names the operation for the diagnostic.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    #[inline(always)]
+    pub(super) unsafe fn enforce_valid_object(&self, obj_start: *const u8, op: &str) {
+        if !cfg!(feature = "hardened") {
+            return;
+        }
+        if let Err(invalid) = self.check_object(obj_start) {
+            abort(format_args!(
+                "{} of {:?} in {}",
+                invalid.description(),
+                obj_start,
+                op
+            ));
+        }
+    }
+
+    /// Aborts the process if `layout` doesn't fit the object whose block contents
+    /// start at `obj_start` and the `hardened` feature is enabled.
+    /// `op` names the operation for the diagnostic.
+    ///
+    /// A layout fits an object if it has the alignment the object was allocated with
+    /// and a size between the allocated size and the object's usable size,
+    /// which is what [`Allocator`](core::alloc::Allocator) requires.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `obj_start` is the start of the contents
+    /// of an occupied block.
+    #[inline(always)]
+    pub(super) unsafe fn enforce_layout(obj_start: *const u8, layout: Layout, op: &str) {
+        if !cfg!(feature = "hardened") {
+            return;
+        }
+        let allocated = read_layout_record(obj_start).unwrap_unchecked();
+        if layout.align() != allocated.align()
+            || layout.size() < allocated.size()
+            || layout.size() > usable_size(obj_start)
+        {
+            abort(format_args!(
+                "layout mismatch of {:?} in {}, allocated with size {} and align {} \
+                 but given size {} and align {}",
+                obj_start.add(left_redzone_size(allocated.align())),
+                op,
+                allocated.size(),
+                allocated.align(),
+                layout.size(),
+                layout.align()
+            ));
+        }
+    }
+
+    /// Returns the layout the object pointed to by `ptr` was allocated
+    /// (or last reallocated) with, or `None` if none of the `hardened`,
+    /// `redzones` and `cabi` features is enabled.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `ptr` points to an object
+    /// that was allocated by a [`RawMalloc`] and hasn't been freed yet.
+    pub unsafe fn allocated_layout(ptr: *const u8) -> Option<Layout> {
+        read_layout_record(object_contents(ptr, "allocated_layout"))
+    }
+}
+
+/// Records `layout` as the layout of the object whose block contents start at `obj_start`,
+/// if layouts are recorded.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block which `layout` fits in.
+#[inline(always)]
+pub(super) unsafe fn write_layout_record(obj_start: *mut u8, layout: Layout) {
+    if LAYOUT_RECORD_SIZE == 0 {
+        return;
+    }
+    debug_assert!(layout.size() <= usize::MAX >> ALIGN_BITS);
+    let record: *mut usize = obj_start
+        .add(content_size(obj_start) - LAYOUT_RECORD_SIZE)
+        .cast();
+    *record = layout.size() << ALIGN_BITS | layout.align().ilog2() as usize;
+}
+
+/// Returns the recorded layout of the object whose block contents start at `obj_start`
+/// or `None` if layouts aren't recorded.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+#[inline(always)]
+pub(super) unsafe fn read_layout_record(obj_start: *const u8) -> Option<Layout> {
+    if LAYOUT_RECORD_SIZE == 0 {
+        return None;
+    }
+    let record = *obj_start
+        .add(content_size(obj_start) - LAYOUT_RECORD_SIZE)
+        .cast::<usize>();
+    let size = record >> ALIGN_BITS;
+    // The alignment is clamped so that even corrupted records decode to valid layouts,
+    // no real object can have such an alignment anyway.
+    let align = 1 << (record & ((1 << ALIGN_BITS) - 1)).min(usize::BITS as usize - 8);
+    Some(Layout::from_size_align_unchecked(size, align))
+}
+
+/// A fixed-size buffer for formatting diagnostics without allocating.
+struct MessageBuf {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl Write for MessageBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Writes a diagnostic to stderr and aborts.
+/// The heap might be corrupted so nothing is allocated on the way.
+#[cold]
+#[inline(never)]
+pub(super) fn abort(diagnostic: fmt::Arguments<'_>) -> ! {
+    let mut message = MessageBuf {
+        buf: [0; 256],
+        len: 0,
+    };
+    let _ = writeln!(message, "rusty_malloc: {diagnostic}, aborting");
+    unsafe { libc::write(libc::STDERR_FILENO, message.buf.as_ptr().cast(), message.len) };
+    std::process::abort()
+}
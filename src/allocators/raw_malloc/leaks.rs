@@ -0,0 +1,205 @@
+//! Leak reports, see [`RawMalloc::leak_report`].
+
+use super::callsites::{callsite, read_callsite_record, Callsite};
+use super::hardened::read_layout_record;
+use super::placement::Placement;
+use super::redzones::left_redzone_size;
+use super::util::usable_size;
+use super::RawMalloc;
+use crate::growers::Grower;
+
+use core::fmt::{self, Display, Write};
+
+/// The maximal number of groups kept by a [`LeakReport`].
+pub const LEAK_GROUP_COUNT: usize = 64;
+
+/// Live objects of the same size that were allocated from the same call site.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LeakGroup {
+    /// The size of each object, that is the size it was allocated with if layouts are recorded
+    /// (see [`RawMalloc::allocated_layout`]) and its usable size otherwise.
+    pub size: usize,
+    /// The call site of the objects or `None` if call sites aren't recorded
+    /// (see the `callsites` feature).
+    pub callsite: Option<Callsite>,
+    /// The number of objects in the group.
+    pub objects: usize,
+}
+
+impl LeakGroup {
+    /// Returns the total size of the objects in the group.
+    pub fn bytes(&self) -> usize {
+        self.size * self.objects
+    }
+}
+
+/// A summary of the live objects of an allocator, grouped by size and call site.
+///
+/// Only the first [`LEAK_GROUP_COUNT`] groups are kept, objects belonging to other groups
+/// are only counted, so building a report never allocates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeakReport {
+    /// The number of live objects.
+    pub objects: usize,
+    /// The total size of the live objects.
+    pub bytes: usize,
+    groups: [LeakGroup; LEAK_GROUP_COUNT],
+    group_count: usize,
+}
+
+impl LeakReport {
+    /// Creates an empty report.
+    pub const fn new() -> Self {
+        LeakReport {
+            objects: 0,
+            bytes: 0,
+            groups: [LeakGroup {
+                size: 0,
+                callsite: None,
+                objects: 0,
+            }; LEAK_GROUP_COUNT],
+            group_count: 0,
+        }
+    }
+
+    /// Returns whether there are no live objects.
+    pub fn is_empty(&self) -> bool {
+        self.objects == 0
+    }
+
+    /// Returns the groups of live objects in the order they were first found.
+    pub fn groups(&self) -> &[LeakGroup] {
+        &self.groups[..self.group_count]
+    }
+
+    /// Adds an object of `size` bytes allocated from `callsite` to the report.
+    pub fn add_object(&mut self, size: usize, callsite: Option<Callsite>) {
+        self.objects += 1;
+        self.bytes += size;
+        let groups = &mut self.groups[..self.group_count];
+        if let Some(group) = groups
+            .iter_mut()
+            .find(|group| group.size == size && group.callsite == callsite)
+        {
+            group.objects += 1;
+        } else if self.group_count < LEAK_GROUP_COUNT {
+            self.groups[self.group_count] = LeakGroup {
+                size,
+                callsite,
+                objects: 1,
+            };
+            self.group_count += 1;
+        }
+    }
+
+    /// Writes the report to stderr without allocating,
+    /// which makes it safe to call from within a global allocator.
+    pub fn print(&self) {
+        let _ = write!(Stderr, "{self}");
+    }
+}
+
+impl Default for LeakReport {
+    fn default() -> Self {
+        LeakReport::new()
+    }
+}
+
+/// Lists the groups with the most bytes first, followed by the call stacks
+/// of their objects if call sites are recorded.
+impl Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "rusty_malloc: no live allocations");
+        }
+        writeln!(
+            f,
+            "rusty_malloc: {} live allocations taking {} bytes",
+            self.objects, self.bytes
+        )?;
+
+        let mut groups = self.groups;
+        let groups = &mut groups[..self.group_count];
+        groups.sort_unstable_by_key(|group| (usize::MAX - group.bytes(), group.size));
+        for group in groups.iter() {
+            writeln!(
+                f,
+                "  {} x {} bytes ({} bytes)",
+                group.objects,
+                group.size,
+                group.bytes()
+            )?;
+            if let Some(callsite) = group.callsite {
+                write!(f, "{callsite}")?;
+            }
+        }
+
+        let grouped_objects: usize = groups.iter().map(|group| group.objects).sum();
+        let grouped_bytes: usize = groups.iter().map(LeakGroup::bytes).sum();
+        if grouped_objects < self.objects {
+            writeln!(
+                f,
+                "  {} more allocations ({} bytes) in other groups",
+                self.objects - grouped_objects,
+                self.bytes - grouped_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes directly to the stderr file descriptor, bypassing the buffering of `std`.
+struct Stderr;
+
+impl Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            let n = unsafe { libc::write(libc::STDERR_FILENO, bytes.as_ptr().cast(), bytes.len()) };
+            if n <= 0 {
+                return Err(fmt::Error);
+            }
+            bytes = &bytes[n as usize..];
+        }
+        Ok(())
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Returns a report of the live objects of the allocator, grouped by size
+    /// and by call site if the `callsites` feature is enabled. Objects in quarantine
+    /// aren't live.
+    ///
+    /// This walks every block of the heap (see [`blocks`](RawMalloc::blocks)),
+    /// so it takes time linear in the size of the heap. It never allocates.
+    pub fn leak_report(&self) -> LeakReport {
+        let mut report = LeakReport::new();
+        self.collect_leaks(&mut report, |_| true);
+        report
+    }
+
+    /// Adds the live objects of the allocator for which `is_leak` returns true
+    /// to `report`. `is_leak` is called with the object pointers.
+    pub(crate) fn collect_leaks(
+        &self,
+        report: &mut LeakReport,
+        mut is_leak: impl FnMut(*const u8) -> bool,
+    ) {
+        for block in self.blocks() {
+            if block.is_free || unsafe { self.is_quarantined(block.start) } {
+                continue;
+            }
+            let obj_start = block.content_start();
+            let (ptr, size) = match unsafe { read_layout_record(obj_start) } {
+                Some(layout) => (
+                    obj_start.wrapping_add(left_redzone_size(layout.align())),
+                    layout.size(),
+                ),
+                None => (obj_start, unsafe { usable_size(obj_start) }),
+            };
+            if is_leak(ptr) {
+                report.add_object(size, callsite(unsafe { read_callsite_record(obj_start) }));
+            }
+        }
+    }
+}
@@ -15,118 +15,351 @@
 // [`HEADER_ALIGN`]: HEADER_ALIGN
 // [`HEADER_SIZE`]: HEADER_SIZE
 
-use self::util::{augment_layout, augment_size, find_place, to_nonnull_slice};
-use crate::freelist::{Freelist, Node, NODE_ALIGN, NODE_SIZE};
+use self::callsites::{read_callsite_record, record_callsite, write_callsite_record};
+use self::hardened::write_layout_record;
+use self::oom::{retry_on_oom, OomHandler};
+use self::quarantine::Quarantine;
+use self::redzones::{enforce_redzones, fill_redzones, object_contents};
+use self::regions::{is_region_full, RegionList};
+use self::stats::{dec, inc, Counters};
+use self::trace::Tracer;
+use self::util::{augment_size, find_place, to_nonnull_slice};
+use crate::freelist::{size_class, Freelist, Node, SegregatedFreelist};
+use crate::freelist::{NODE_ALIGN, NODE_SIZE, SIZE_CLASS_COUNT};
+use crate::error::MallocError;
 use crate::growers::Grower;
-use crate::header::{Header, HEADER_ALIGN, HEADER_SIZE};
+use crate::header::{Header, FOOTER_SIZE, HEADER_ALIGN, HEADER_SIZE};
 use crate::util::{checked_add, raw_ptr};
 
 use core::alloc::{Allocator, GlobalAlloc, Layout};
-use core::cell::UnsafeCell;
-use core::ptr::NonNull;
+use core::cell::{Cell, UnsafeCell};
+use core::ptr::{null, NonNull};
 use std::alloc::AllocError;
 use std::fmt::Debug;
-use std::intrinsics::copy_nonoverlapping;
+use core::ptr::copy_nonoverlapping;
 
 use static_assertions::const_assert;
 use tracing::{debug, error, instrument, Level};
 
+pub mod blocks;
+pub mod callsites;
+pub mod fragmentation;
+mod hardened;
+pub mod leaks;
+pub mod oom;
+pub mod placement;
+pub mod quarantine;
+pub mod redzones;
+mod regions;
+pub mod stats;
+pub mod trace;
+pub mod validate;
 mod util;
 
-pub(crate) const BLOCK_CONTENT_MIN_SIZE: usize = NODE_SIZE;
+pub use blocks::{Block, Blocks};
+pub use callsites::Callsite;
+pub use fragmentation::Fragmentation;
+pub use leaks::{LeakGroup, LeakReport};
+pub use oom::{AllocFailure, OomAction};
+pub use placement::{BestFit, FirstFit, NextFit, Placement};
+pub use redzones::RedzoneSide;
+pub use stats::Stats;
+pub use trace::{TraceOp, TraceReader, TraceRecord};
+pub use validate::ValidationError;
+
+pub(crate) use self::util::augment_layout;
+
+/// Whether free blocks have footers and headers keep the "previous block free" bit,
+/// see the `footers` feature.
+pub(crate) const FOOTERS: bool = cfg!(feature = "footers");
+
+// Free blocks have to fit both a freelist node and a footer.
+pub(crate) const BLOCK_CONTENT_MIN_SIZE: usize = NODE_SIZE + FOOTER_SIZE;
 pub(crate) const BLOCK_CONTENT_MIN_ALIGN: usize = NODE_ALIGN;
 
 pub(crate) const BLOCK_MIN_SIZE: usize = HEADER_SIZE + BLOCK_CONTENT_MIN_SIZE;
 
-// Header-tagging requires block content to be at least 2-byte-aligned.
-const_assert!(BLOCK_CONTENT_MIN_ALIGN >= 2);
+// Header-tagging uses two bits so block content has to be at least 4-byte-aligned.
+const_assert!(BLOCK_CONTENT_MIN_ALIGN >= 4);
 const_assert!(NODE_ALIGN <= HEADER_ALIGN);
 
 /// A single threaded memory allocator.
+///
+/// The `P` parameter selects how free blocks are chosen for allocations,
+/// see the [`placement`] module for the available policies.
 #[repr(C)]
-pub struct RawMalloc<T: Grower> {
-    freelist: UnsafeCell<Freelist>,
+pub struct RawMalloc<T: Grower, P: Placement = FirstFit> {
+    freelists: UnsafeCell<SegregatedFreelist>,
     grower: UnsafeCell<T>,
+    placement: P,
+    /// The start of the heap, that is the heap end before the first growth.
+    heap_start: Cell<Option<NonNull<u8>>>,
+    /// Whether the last block of the heap is free.
+    /// This acts as the "previous block free" bit of the (nonexistent) block at the heap end.
+    tail_free: Cell<bool>,
+    /// The regions after the first one, see the [`regions`] module.
+    regions: RegionList,
+    counters: Counters,
+    quarantine: Quarantine,
+    tracer: Tracer,
+    oom_handler: Cell<Option<OomHandler>>,
 }
 
-impl<T: Grower> Debug for RawMalloc<T> {
+impl<T: Grower, P: Placement> Debug for RawMalloc<T, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RawMalloc")
             .field("grower", &self.grower)
+            .field("placement", &self.placement)
             .finish()
     }
 }
 
 impl<T: Grower> RawMalloc<T> {
-    /// Creates an allocator instance with the specified grower.
+    /// Creates an allocator instance with the specified grower
+    /// and the default [`FirstFit`] placement policy.
     ///
     /// # Safety
     /// Callers must make sure that the provided grower will be the only object
     /// managing it's underlying buffer for the lifetime of the returned allocator.
     pub const unsafe fn with_grower(grower: T) -> Self {
+        RawMalloc::with_grower_and_placement(grower, FirstFit)
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Creates an allocator instance with the specified grower and placement policy.
+    ///
+    /// # Safety
+    /// Callers must make sure that the provided grower will be the only object
+    /// managing it's underlying buffer for the lifetime of the returned allocator.
+    pub const unsafe fn with_grower_and_placement(grower: T, placement: P) -> Self {
         RawMalloc {
-            freelist: UnsafeCell::new(Freelist::new()),
+            freelists: UnsafeCell::new(SegregatedFreelist::new()),
             grower: UnsafeCell::new(grower),
+            placement,
+            heap_start: Cell::new(None),
+            tail_free: Cell::new(false),
+            regions: RegionList::new(),
+            counters: Counters::new(),
+            quarantine: Quarantine::new(),
+            tracer: Tracer::new(),
+            oom_handler: Cell::new(None),
+        }
+    }
+
+    /// Returns the allocator's placement policy.
+    pub fn placement(&self) -> &P {
+        &self.placement
+    }
+
+    /// Returns the allocator's grower.
+    pub(crate) fn grower_mut(&mut self) -> &mut T {
+        self.grower.get_mut()
+    }
+
+    /// Returns the number of usable bytes of the object pointed to by `ptr`,
+    /// which is at least the size it was allocated with.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `ptr` points to an object
+    /// that was allocated by a [`RawMalloc`] and hasn't been freed yet.
+    pub unsafe fn usable_size(ptr: *const u8) -> usize {
+        util::usable_size(object_contents(ptr, "usable_size"))
+    }
+
+    /// Allocates an object with `layout` like [`alloc`](GlobalAlloc::alloc)
+    /// but returns the reason of a failure instead of a null pointer.
+    pub fn try_alloc_detailed(&self, layout: Layout) -> Result<NonNull<u8>, MallocError> {
+        let ptr = retry_on_oom(
+            || self.oom_handler(),
+            layout,
+            || unsafe { self.__alloc(layout) },
+            || self.stats(),
+        );
+        self.trace(TraceOp::Alloc, layout, raw_ptr(ptr.ok()), null(), 0);
+        ptr
+    }
+
+    /// Like [`realloc`](GlobalAlloc::realloc) but returns the reason of a failure
+    /// instead of a null pointer.
+    ///
+    /// # Safety
+    /// Callers must uphold the safety contract of [`realloc`](GlobalAlloc::realloc).
+    pub unsafe fn try_realloc_detailed(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, MallocError> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = retry_on_oom(
+            || self.oom_handler(),
+            new_layout,
+            || self.__realloc(ptr, layout, new_size),
+            || self.stats(),
+        );
+        self.trace(TraceOp::Realloc, layout, ptr, raw_ptr(new_ptr.ok()), new_size);
+        new_ptr
+    }
+
+    /// Frees an object like [`dealloc`](GlobalAlloc::dealloc) but doesn't record it
+    /// in the trace, for objects whose deallocation was recorded when they were cached.
+    ///
+    /// # Safety
+    /// Callers must uphold the safety contract of [`dealloc`](GlobalAlloc::dealloc).
+    pub(crate) unsafe fn dealloc_untraced(&self, ptr: *mut u8, layout: Layout) {
+        let obj_start = self.enforce_object_contents(ptr, "dealloc");
+        self.enforce_valid_object(obj_start, "dealloc");
+        Self::enforce_layout(obj_start, layout, "dealloc");
+        enforce_redzones(obj_start, "dealloc");
+        let block_start = obj_start.sub(HEADER_SIZE);
+        let block_header: &Header = &*block_start.cast();
+
+        debug_assert_eq!(
+            obj_start as usize % HEADER_ALIGN,
+            0,
+            "All allocations should have header alignment."
+        );
+
+        debug_assert!(
+            !block_header.is_tagged(),
+            "Allocations should be preceded by untagged headers."
+        );
+        debug_assert!(
+            block_header.content_size() >= BLOCK_CONTENT_MIN_SIZE,
+            "Allocation size should be at least {BLOCK_MIN_SIZE}."
+        );
+
+        self.record_dealloc(obj_start, layout);
+        self.retire_block(block_start);
+    }
+
+    /// Releases free memory at the end of the heap back to the allocator's grower,
+    /// leaving at most `keep` bytes (rounded up to a valid block size) of free space at the top.
+    /// Returns the number of bytes that were released.
+    ///
+    /// Nothing is released if the block touching the heap end is occupied or
+    /// if the grower does not support shrinking (see [`Grower::shrink`]).
+    #[instrument(level = "info", ret(level = Level::INFO))]
+    pub fn trim(&self, keep: usize) -> usize {
+        unsafe {
+            let Some(node) = self.find_last_free_node() else {
+                debug!("The block at the heap end isn't free, nothing to trim.");
+                return 0;
+            };
+            let block_header: *mut Header = node.cast::<Header>().sub(1);
+            let block_content_size = (*block_header).content_size();
+
+            let release = match keep {
+                0 => HEADER_SIZE + block_content_size,
+                _ => match augment_size(keep) {
+                    Ok(keep) => block_content_size.saturating_sub(keep),
+                    Err(_) => 0,
+                },
+            };
+            if release == 0 {
+                return 0;
+            }
+
+            // The grower may unmap the released memory, so the block is unlinked beforehand.
+            let prev_free = (*block_header).prev_free();
+            (*self.freelists.get()).remove(node, block_content_size);
+            if (*self.grower.get()).shrink(release).is_err() {
+                debug!("Grower refused to shrink.");
+                (*self.freelists.get()).push_front(node, block_content_size);
+                return 0;
+            }
+            inc(&self.counters.released, release);
+            if release == HEADER_SIZE + block_content_size {
+                debug!("Released the whole block.");
+                dec(&self.counters.blocks, 1);
+                self.tail_free.set(prev_free);
+            } else {
+                debug!("Released the tail of the block.");
+                let new_content_size = block_content_size - release;
+                self.create_new_block(block_header.cast(), new_content_size, true, prev_free);
+            }
+            release
         }
     }
 }
 
-impl<T: Grower> RawMalloc<T> {
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
     #[instrument(level = "info", ret(level = Level::INFO), err(Debug, level = Level::ERROR))]
-    unsafe fn __alloc(&self, layout: Layout) -> Result<NonNull<u8>, ()> {
+    unsafe fn __alloc(&self, layout: Layout) -> Result<NonNull<u8>, MallocError> {
         let augmented_layout = augment_layout(layout)?;
         debug!(?augmented_layout, "Layout augmented.");
 
         let obj_size = augmented_layout.size();
         let obj_align = augmented_layout.align();
 
-        let obj_start = match unsafe { self.place_in_first_free_block(obj_size, obj_align) } {
-            Ok(p) => {
+        let mut placed = unsafe { P::place(self, obj_size, obj_align) };
+        if placed.is_none() && unsafe { self.merge_all_nodes() } {
+            debug!("Merged free blocks, retrying placement.");
+            placed = unsafe { P::place(self, obj_size, obj_align) };
+        }
+
+        let obj_start = match placed {
+            Some(p) => {
                 debug!(obj_start = ?p.as_ptr(), "Found free block to accomodate object.");
                 p
             }
-            Err(()) => {
+            None => {
                 debug!("Couldn't find free block to accomodate object, requesting heap growth.");
                 unsafe { self.grow_and_place(obj_size, obj_align)? }
             }
         };
 
-        Ok(obj_start)
+        write_layout_record(obj_start.as_ptr(), layout);
+        record_callsite(obj_start.as_ptr());
+        self.record_alloc(obj_start.as_ptr(), layout);
+        Ok(NonNull::new_unchecked(fill_redzones(obj_start.as_ptr())))
     }
 
     #[instrument(level = "info", ret(level = Level::INFO), err(Debug, level = Level::ERROR))]
     unsafe fn __realloc(
         &self,
-        obj_start: *mut u8,
+        ptr: *mut u8,
         layout: Layout,
-        new_obj_size: usize,
-    ) -> Result<NonNull<u8>, ()> {
-        let new_obj_size = augment_size(new_obj_size)?;
+        new_size: usize,
+    ) -> Result<NonNull<u8>, MallocError> {
+        let obj_start = self.enforce_object_contents(ptr, "realloc");
+        self.enforce_valid_object(obj_start, "realloc");
+        Self::enforce_layout(obj_start, layout, "realloc");
+        enforce_redzones(obj_start, "realloc");
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_obj_size = augment_layout(new_layout)?.size();
         debug!(augmented_size = ?new_obj_size, "Augmented new_obj_size.");
 
         let block_start = obj_start.sub(HEADER_SIZE);
-        let block_header: *mut Header = block_start.cast();
-        debug_assert!(
-            !(*block_header).is_tagged(),
-            "Objects should be preceded by untagged headers."
-        );
-        let obj_size = (*block_header).__content_size;
-
-        if self.try_adjust(block_start, new_obj_size).is_ok() {
-            return Ok(NonNull::new_unchecked(obj_start));
+        let obj_size = util::usable_size(obj_start);
+        let callsite = read_callsite_record(obj_start);
+        self.record_dealloc(obj_start, layout);
+
+        if self.try_adjust(block_start, new_obj_size) {
+            write_layout_record(obj_start, new_layout);
+            record_callsite(obj_start);
+            self.record_alloc(obj_start, new_layout);
+            return Ok(NonNull::new_unchecked(fill_redzones(obj_start)));
         }
         debug_assert!(new_obj_size > layout.size());
         debug!("Couldn't adjust current block, attempting reallocation to a new block.");
 
-        let new_obj_start = self
-            .__alloc(Layout::from_size_align_unchecked(
-                new_obj_size,
-                layout.align(),
-            ))?
-            .as_ptr();
-        copy_nonoverlapping(obj_start, new_obj_start, obj_size);
-        self.dealloc(obj_start, layout);
-        Ok(NonNull::new_unchecked(new_obj_start))
+        let new_ptr = match self.__alloc(new_layout) {
+            Ok(p) => p.as_ptr(),
+            Err(e) => {
+                // The object stays where it is, possibly having absorbed successive free blocks.
+                write_layout_record(obj_start, layout);
+                write_callsite_record(obj_start, callsite);
+                self.record_alloc(obj_start, layout);
+                fill_redzones(obj_start);
+                return Err(e);
+            }
+        };
+        copy_nonoverlapping(ptr, new_ptr, obj_size.min(new_size));
+        self.retire_block(block_start);
+        Ok(NonNull::new_unchecked(new_ptr))
     }
 
     /// Tries to adjust (that is shrink or expand) an occupied block for an object with size `new_obj_size`.
@@ -134,16 +367,15 @@ impl<T: Grower> RawMalloc<T> {
     /// # Notes
     /// This adjustment might consume subsequent free blocks and/or create new padding blocks.
     /// Shrinks are guaranteed to be carried out successfully whereas grows might fail if there
-    /// is not enough space in which case `Err(())` is returned.
+    /// is not enough space in which case `false` is returned.
     ///
     /// # Safety
     /// This function is unsafe since it assumes that `block_start` is pointing to a valid occupied block
     /// and `new_obj_size` is properly augmented for an allocation (see the [`module`](self) level
     /// documentation). Additionally callers must ensure that no allocator fields are currently borrowed.
-    #[instrument(level = "debug", ret(level = Level::DEBUG), err(Debug, level = Level::ERROR))]
-    unsafe fn try_adjust(&self, block_start: *mut u8, new_obj_size: usize) -> Result<(), ()> {
+    #[instrument(level = "debug", ret(level = Level::DEBUG))]
+    unsafe fn try_adjust(&self, block_start: *mut u8, new_obj_size: usize) -> bool {
         debug_assert!(self.heap_end().is_some());
-        let heap_end = raw_ptr(self.heap_end());
         let block_header: *mut Header = block_start.cast();
         let obj_start = block_start.add(HEADER_SIZE);
 
@@ -152,19 +384,20 @@ impl<T: Grower> RawMalloc<T> {
             "Objects should be preceded by untagged headers."
         );
 
-        let new_block_end = checked_add(obj_start, new_obj_size).ok_or(())?;
+        let Some(new_block_end) = checked_add(obj_start, new_obj_size) else {
+            return false;
+        };
 
+        // With footers successive free blocks are always absorbed (even when shrinking)
+        // so that any right padding left by the adjustment is coalesced with them.
         loop {
-            let block_end = obj_start.add((*block_header).__content_size);
+            let block_end = obj_start.add((*block_header).content_size());
 
-            if block_end as *const u8 >= new_block_end {
-                // Shrink
-                debug_assert_eq!(obj_start as usize - block_start as usize, HEADER_SIZE);
-                self.place_raw(block_start, block_end, obj_start, new_obj_size);
-                return Ok(());
+            if !FOOTERS && block_end as *const u8 >= new_block_end {
+                break;
             }
 
-            if block_end == heap_end {
+            if self.is_region_end(block_end) {
                 break;
             }
 
@@ -175,9 +408,11 @@ impl<T: Grower> RawMalloc<T> {
                 break;
             }
 
+            let next_block_size = next_block_header.content_size();
             let next_block_node: *mut Node = next_block_start.add(HEADER_SIZE).cast();
-            (*self.freelist.get()).remove(next_block_node);
-            (*block_header).__content_size += HEADER_SIZE + next_block_header.content_size();
+            (*self.freelists.get()).remove(next_block_node, next_block_size);
+            (*block_header).__content_size += HEADER_SIZE + next_block_size;
+            dec(&self.counters.blocks, 1);
             debug!(
                 ?next_block_start,
                 ?next_block_header,
@@ -186,12 +421,23 @@ impl<T: Grower> RawMalloc<T> {
             );
         }
 
-        Err(())
+        let block_end = obj_start.add((*block_header).content_size());
+
+        if block_end as *const u8 >= new_block_end {
+            debug_assert_eq!(obj_start as usize - block_start as usize, HEADER_SIZE);
+            let prev_free = (*block_header).prev_free();
+            self.place_raw(block_start, block_end, obj_start, new_obj_size, prev_free);
+            return true;
+        }
+
+        // Blocks that were merged are no longer free.
+        self.set_prev_free(block_end, false);
+        false
     }
 
     /// Grows the heap for an allocation of size `obj_size` and alignment `obj_align`.
     /// Returns the old heap end, the growth ammount and a pointer to where to put the allocation
-    /// or `Err(())` if the heap can not grow to accomodate the object.
+    /// or the reason the heap can not grow to accomodate the object.
     /// A space for a preceding header is always accounted for and if necessary a space for
     /// a padding free block is also considered.
     ///
@@ -204,7 +450,7 @@ impl<T: Grower> RawMalloc<T> {
         &self,
         obj_size: usize,
         obj_align: usize,
-    ) -> Result<(NonNull<u8>, usize, NonNull<u8>), ()> {
+    ) -> Result<(NonNull<u8>, usize, NonNull<u8>), MallocError> {
         debug_assert_eq!(obj_size % HEADER_SIZE, 0);
 
         let old_heap_end: *mut u8;
@@ -212,11 +458,11 @@ impl<T: Grower> RawMalloc<T> {
         let obj_end: *mut u8;
 
         {
-            match self.heap_end() {
-                Some(end) => old_heap_end = end.as_ptr(),
-                None => {
+            match (*self.grower.get()).grow(0) {
+                Ok((end, _)) => old_heap_end = end.as_ptr(),
+                Err(e) => {
                     error!("Growth failiure, couldn't get heap end.");
-                    return Err(());
+                    return Err(e);
                 }
             };
             debug_assert_eq!(old_heap_end as usize % HEADER_ALIGN, 0);
@@ -225,7 +471,7 @@ impl<T: Grower> RawMalloc<T> {
                 Some(p) => obj_start = p.as_ptr(),
                 None => {
                     error!("Growth failiure, object alignment is too big.");
-                    return Err(());
+                    return Err(MallocError::UnsatisfiableAlignment);
                 }
             }
             debug_assert_eq!(obj_start as usize % HEADER_ALIGN, 0);
@@ -234,7 +480,7 @@ impl<T: Grower> RawMalloc<T> {
                 Some(p) => obj_end = p as *mut u8,
                 None => {
                     error!("Growth failure, object is too big.");
-                    return Err(());
+                    return Err(MallocError::LayoutTooLarge);
                 }
             }
         }
@@ -242,13 +488,18 @@ impl<T: Grower> RawMalloc<T> {
         let growth_amount = obj_end as usize - old_heap_end as usize;
         debug!(growth_amount, "Calculated growth ammount.");
 
+        inc(&self.counters.grow_calls, 1);
         match (*self.grower.get()).grow(growth_amount) {
-            Err(()) => {
+            Err(e) => {
                 error!("Growth failiure, no memory.");
-                Err(())
+                Err(e)
             }
             Ok((__old_heap_end, growth_amount)) => {
                 debug_assert_eq!(old_heap_end, __old_heap_end.as_ptr());
+                if self.heap_start.get().is_none() {
+                    self.heap_start.set(Some(__old_heap_end));
+                }
+                inc(&self.counters.grown, growth_amount);
                 Ok((
                     __old_heap_end,
                     growth_amount,
@@ -260,7 +511,9 @@ impl<T: Grower> RawMalloc<T> {
 
     /// Grows the heap for an allocation of size `obj_size` and alignment `obj_align` and
     /// divides the newly allocated space into blocks one of which delegated to the allocation.
-    /// Returns a pointer to the new allocation or `Err(())` if the growth failed
+    /// If the current region ran out of memory the heap moves to a new region
+    /// if the grower supports it, other growth failures are returned as they are.
+    /// Returns a pointer to the new allocation or the reason the growth failed
     /// (see [`grow`](RawMalloc::grow) for details on when this happens).
     ///
     /// Safety:
@@ -268,25 +521,40 @@ impl<T: Grower> RawMalloc<T> {
     /// conform to the allocator object requirements (See the [`module`](self) level documentation).
     /// Additionally callers must ensure that no allocator field is currently borrowed.
     #[instrument(level = "debug", ret(level = Level::DEBUG), err(Debug, level=Level::ERROR))]
-    unsafe fn grow_and_place(&self, obj_size: usize, obj_align: usize) -> Result<NonNull<u8>, ()> {
+    unsafe fn grow_and_place(
+        &self,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Result<NonNull<u8>, MallocError> {
         let (old_heap_end, growth_amount, obj_start) = self
             .grow(obj_size, obj_align)
+            .or_else(|e| {
+                if !is_region_full(e) {
+                    return Err(e);
+                }
+                match self.grow_region(obj_size, obj_align) {
+                    Err(MallocError::Unsupported) => Err(e),
+                    grown => grown,
+                }
+            })
             .map(|p| (p.0.as_ptr(), p.1, p.2.as_ptr()))
             .inspect_err(|_| error!("Couldn't grow heap"))?;
 
         debug!(?obj_start, "Heap growth successful.");
+        inc(&self.counters.blocks, 1);
         self.place_raw(
             old_heap_end,
             old_heap_end.add(growth_amount),
             obj_start,
             obj_size,
+            self.tail_free.get(),
         );
         Ok(NonNull::new_unchecked(obj_start))
     }
 
     /// Tries to place an object into the block pointed to by `block_start`,
     /// creating additional free blocks if padding is necessary.
-    /// On success a pointer to the newly allocated object is returned.
+    /// On success a pointer to the newly allocated object is returned, otherwise `None`.
     ///
     /// Safety:
     /// This function is unsafe since it assumes
@@ -294,13 +562,49 @@ impl<T: Grower> RawMalloc<T> {
     /// and that `obj_align` and `obj_size` conform to the allocator object requirements
     /// (See the [`module`](self) level documentation). Additionally callers must ensure
     /// that the allocator's freelist is not currently borrowed.
-    #[instrument(level = "debug", ret(level = Level::DEBUG), err(Debug, level=Level::DEBUG))]
+    #[instrument(level = "debug", ret(level = Level::DEBUG))]
     unsafe fn try_place(
         &self,
         block_start: *mut u8,
         obj_size: usize,
         obj_align: usize,
-    ) -> Result<NonNull<u8>, ()> {
+    ) -> Option<NonNull<u8>> {
+        let obj_start = self.fit(block_start, obj_size, obj_align)?.as_ptr();
+        self.place(block_start, obj_start, obj_size);
+        Some(NonNull::new_unchecked(obj_start))
+    }
+
+    /// Places an object at `obj_start` inside the free block pointed to by `block_start`,
+    /// creating additional free blocks if padding is necessary.
+    ///
+    /// Safety:
+    /// This function is unsafe since it assumes that `obj_start` was obtained
+    /// by calling [`fit`](RawMalloc::fit) with the same parameters. Additionally callers must
+    /// ensure that the allocator's freelist is not currently borrowed.
+    unsafe fn place(&self, block_start: *mut u8, obj_start: *mut u8, obj_size: usize) {
+        let block_header: *mut Header = block_start.cast();
+        let block_end = block_start.add(HEADER_SIZE + (*block_header).content_size());
+        let prev_free = (*block_header).prev_free();
+
+        let block_freenode = block_start.add(HEADER_SIZE).cast();
+        (*self.freelists.get()).remove(block_freenode, (*block_header).content_size());
+        self.place_raw(block_start, block_end, obj_start, obj_size, prev_free);
+    }
+
+    /// Checks whether an object fits into the free block pointed to by `block_start`.
+    /// Returns a pointer to where the object should be placed or `None` if it doesn't fit.
+    ///
+    /// Safety:
+    /// This function is unsafe since it assumes
+    /// that `block_start` is pointing to the header of a valid *free* block,
+    /// and that `obj_align` and `obj_size` conform to the allocator object requirements
+    /// (See the [`module`](self) level documentation).
+    unsafe fn fit(
+        &self,
+        block_start: *mut u8,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>> {
         let block_header: *mut Header = block_start.cast();
         debug_assert!((*block_header).is_tagged(), "Block should be free.");
 
@@ -314,7 +618,7 @@ impl<T: Grower> RawMalloc<T> {
                 Some(p) => obj_start = p.as_ptr(),
                 None => {
                     debug!("Couldn't place object, alignment is too big.");
-                    return Err(());
+                    return None;
                 }
             }
             debug!(?obj_start);
@@ -323,15 +627,12 @@ impl<T: Grower> RawMalloc<T> {
                 Some(p) if p <= block_end as *const u8 => {}
                 _ => {
                     debug!("Couldn't place object, size is too large.");
-                    return Err(());
+                    return None;
                 }
             }
         }
 
-        let block_freenode = block_start.add(HEADER_SIZE).cast();
-        (*self.freelist.get()).remove(block_freenode);
-        self.place_raw(block_start, block_end, obj_start, obj_size);
-        Ok(NonNull::new_unchecked(obj_start))
+        Some(NonNull::new_unchecked(obj_start))
     }
 
     /// Places an object with `obj_size` at `obj_start` creting a new block for it.
@@ -349,8 +650,9 @@ impl<T: Grower> RawMalloc<T> {
     /// alignment for populating it with blocks. It's also assumed that the object parameters are valid -
     /// that is the object should not only fit and be properly aligned in the region
     /// but any left padding should also be sufficiently large to hold a
-    /// [`BLOCK_MIN_SIZE`]-sized block. Lastly callers should ensure
-    /// that the allocator's freelist is not currently borrowed.
+    /// [`BLOCK_MIN_SIZE`]-sized block. `prev_free` should tell whether the block preceding
+    /// `block_start` is free. Lastly callers should ensure that no allocator field
+    /// is currently borrowed.
     #[instrument(level = "debug")]
     unsafe fn place_raw(
         &self,
@@ -358,6 +660,7 @@ impl<T: Grower> RawMalloc<T> {
         block_end: *mut u8,
         obj_start: *mut u8,
         mut obj_size: usize,
+        mut prev_free: bool,
     ) {
         debug_assert!(obj_size as isize > 0);
         debug_assert!(block_end as usize >= block_start as usize);
@@ -389,9 +692,11 @@ impl<T: Grower> RawMalloc<T> {
             let padding_content_size = dist - 2 * HEADER_SIZE;
 
             debug!("Placing a free block as left padding.");
-            self.create_new_block(padding_start, padding_content_size, true);
+            self.create_new_block(padding_start, padding_content_size, true, prev_free);
+            inc(&self.counters.blocks, 1);
 
             block_start = block_start.add(HEADER_SIZE + padding_content_size).cast();
+            prev_free = true;
         }
 
         let dist = block_end as usize - obj_end as usize;
@@ -401,7 +706,9 @@ impl<T: Grower> RawMalloc<T> {
             let padding_content_size = dist - HEADER_SIZE;
 
             debug!("Placing a free block as right padding.");
-            self.create_new_block(padding_start, padding_content_size, true);
+            self.create_new_block(padding_start, padding_content_size, true, false);
+            inc(&self.counters.blocks, 1);
+            self.set_prev_free(block_end, true);
         } else {
             obj_end = block_end;
             obj_size = obj_end as usize - obj_start as usize;
@@ -410,34 +717,80 @@ impl<T: Grower> RawMalloc<T> {
                 new_obj_size = obj_size,
                 "No space for right padding, adjusting object size."
             );
+            self.set_prev_free(block_end, false);
         }
 
         debug!("Placing block to accomodate object.");
-        self.create_new_block(block_start, obj_size, false);
+        self.create_new_block(block_start, obj_size, false, prev_free);
     }
 
-    /// Frees the block pointed to by `block_start`, currently this is equivalent to
-    /// tagging the block header and adding the block to the freelist.
+    /// Frees the block pointed to by `block_start`, that is the block is immediately coalesced
+    /// with its neighbours (if they are free) and the resulting block is added to the freelist.
+    /// Without footers this is equivalent to tagging the block header and adding the block
+    /// to the freelist, coalescing is left to [`merge_subsequent_nodes`](Self::merge_subsequent_nodes).
     ///
     /// # Safety
     /// This function is unsafe since it assumes that `block_start` points to a block
     /// that is indeed to be freed, i.e. the block shouldn't be free already and should be treated
-    /// as free after this function returns. Additionally callers must ensure the allocator's freelist isn't
-    /// currently borrowed.
+    /// as free after this function returns. Additionally callers must ensure that no allocator
+    /// field is currently borrowed.
     #[instrument(level = "debug")]
-    unsafe fn free_block(&self, block_start: *mut u8) {
-        let block_header: *mut Header = block_start.cast();
-        let old_header: &Header = &*block_header;
+    unsafe fn free_block(&self, mut block_start: *mut u8) {
+        let block_header: &Header = &*block_start.cast();
+
+        debug_assert!(
+            !block_header.is_tagged(),
+            "Block shouldn't be free already."
+        );
 
-        debug_assert!(!old_header.is_tagged(), "Block shouldn't be free already.");
+        let mut content_size = block_header.content_size();
+        let mut prev_free = block_header.prev_free();
 
-        let new_header = old_header.tagged();
-        *block_header = new_header;
+        if !FOOTERS {
+            self.create_new_block(block_start, content_size, true, false);
+            return;
+        }
+
+        if prev_free {
+            let prev_footer: &Header = &*block_start.cast::<Header>().sub(1);
+            debug_assert!(prev_footer.is_tagged(), "Footers should be tagged.");
+            let prev_content_size = prev_footer.content_size();
+            let prev_block_start = block_start.sub(HEADER_SIZE + prev_content_size);
+            let prev_block_header: &Header = &*prev_block_start.cast();
+            debug_assert_eq!(
+                prev_block_header.with_prev_free(false),
+                *prev_footer,
+                "Footers should match the headers of their blocks."
+            );
+
+            (*self.freelists.get())
+                .remove(prev_block_start.add(HEADER_SIZE).cast(), prev_content_size);
+            prev_free = prev_block_header.prev_free();
+            content_size += HEADER_SIZE + prev_content_size;
+            block_start = prev_block_start;
+            dec(&self.counters.blocks, 1);
+            debug!(?prev_block_start, "Merging with preceding free block.");
+        }
+
+        let next_block_start = block_start.add(HEADER_SIZE + content_size);
+        if !self.is_region_end(next_block_start) {
+            let next_block_header: &Header = &*next_block_start.cast();
+            if next_block_header.is_tagged() {
+                let next_content_size = next_block_header.content_size();
+                (*self.freelists.get())
+                    .remove(next_block_start.add(HEADER_SIZE).cast(), next_content_size);
+                content_size += HEADER_SIZE + next_content_size;
+                dec(&self.counters.blocks, 1);
+                debug!(?next_block_start, "Merging with successive free block.");
+            }
+        }
 
-        (*self.freelist.get()).push_front(block_header.add(1).cast());
+        self.create_new_block(block_start, content_size, true, prev_free);
+        self.set_prev_free(block_start.add(HEADER_SIZE + content_size), true);
     }
 
     /// Creates a new block at the location pointed to by `block_start`.
+    /// Free blocks also get a footer (with footers enabled) and are added to the freelist.
     ///
     /// # Safety
     /// This function is unsafe since it assumes that the block parameters
@@ -445,22 +798,73 @@ impl<T: Grower> RawMalloc<T> {
     /// Additionally if `is_free` is true callers must ensure the allocator's freelist isn't
     /// currently borrowed.
     #[instrument(level = "debug")]
-    unsafe fn create_new_block(&self, block_start: *mut u8, content_size: usize, is_free: bool) {
+    unsafe fn create_new_block(
+        &self,
+        block_start: *mut u8,
+        content_size: usize,
+        is_free: bool,
+        prev_free: bool,
+    ) {
         let block_header: *mut Header = block_start.cast();
-        *block_header = Header::new_unchecked(content_size, is_free);
+        *block_header =
+            Header::new_unchecked(content_size, is_free).with_prev_free(FOOTERS && prev_free);
         if is_free {
-            (*self.freelist.get()).push_front(block_header.add(1).cast());
+            self.write_footer(block_start);
+            (*self.freelists.get()).push_front(block_header.add(1).cast(), content_size);
         }
         debug!(?block_start, block_header = ?*block_header, "Created a new block.");
     }
 
+    /// Copies the header of the free block pointed to by `block_start` into the block's footer.
+    /// Does nothing without footers.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `block_start` points to a valid free block.
+    #[inline(always)]
+    unsafe fn write_footer(&self, block_start: *mut u8) {
+        if !FOOTERS {
+            return;
+        }
+        let block_header: &Header = &*block_start.cast();
+        debug_assert!(block_header.is_tagged(), "Only free blocks have footers.");
+        let content_size = block_header.content_size();
+        let footer: *mut Header = block_start.add(content_size).cast();
+        *footer = block_header.with_prev_free(false);
+    }
+
+    /// Sets the "previous block free" bit of the block pointed to by `block_start`
+    /// or the allocator's `tail_free` flag if `block_start` is the heap end.
+    /// Nothing is set without footers or if `block_start` is the end of a sealed region.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `block_start` either points to a valid block
+    /// or is a region end, and that the allocator's grower isn't currently borrowed.
+    #[inline(always)]
+    unsafe fn set_prev_free(&self, block_start: *mut u8, prev_free: bool) {
+        if !FOOTERS {
+            return;
+        }
+        if Some(block_start) == self.heap_end().map(|p| p.as_ptr()) {
+            self.tail_free.set(prev_free);
+            return;
+        }
+        if self.is_sealed_region_end(block_start) {
+            return;
+        }
+        let block_header: *mut Header = block_start.cast();
+        *block_header = (*block_header).with_prev_free(prev_free);
+    }
+
     /// Merges subsequent (in memory) freelist nodes starting from `node` into a single node.
+    /// If the merged block outgrows its size class it is moved to the appropriate list.
+    /// Returns the node that followed `node` in its original list after the merge,
+    /// so that callers walking that list can continue from it.
     ///
     /// # Safety
     /// This function is unsafe since it assumes that `node` is a part of a valid free block
     /// and that no allocator field is currently borrowed.
     #[instrument(level = "debug")]
-    unsafe fn merge_subsequent_nodes(&self, node: *mut Node) {
+    unsafe fn merge_subsequent_nodes(&self, node: *mut Node) -> *mut Node {
         let block_header = &mut *(node.cast::<Header>().sub(1));
         debug_assert!(
             block_header.is_tagged(),
@@ -469,17 +873,13 @@ impl<T: Grower> RawMalloc<T> {
 
         debug!(?block_header);
 
-        let heap_end = self
-            .heap_end()
-            .expect("Couldn't get heap end.")
-            .as_ptr()
-            .cast();
+        let class = size_class(block_header.content_size());
         loop {
             let block_content_size = block_header.content_size();
             let next_block_start = node.cast::<u8>().add(block_content_size);
 
-            if next_block_start == heap_end {
-                debug!("Reached heap end, no more blocks to merge with, stopping.");
+            if self.is_region_end(next_block_start) {
+                debug!("Reached region end, no more blocks to merge with, stopping.");
                 break;
             }
 
@@ -497,8 +897,9 @@ impl<T: Grower> RawMalloc<T> {
             let next_block_size = next_block_header.content_size();
             let next_block_node: *mut Node = next_block_start.add(HEADER_SIZE).cast();
 
-            (*self.freelist.get()).remove(next_block_node);
+            (*self.freelists.get()).remove(next_block_node, next_block_size);
             block_header.__content_size += HEADER_SIZE + next_block_size;
+            dec(&self.counters.blocks, 1);
 
             debug!(
                 ?next_block_start,
@@ -507,44 +908,225 @@ impl<T: Grower> RawMalloc<T> {
                 "Merging with successive free block."
             );
         }
+
+        self.write_footer(node.cast::<u8>().sub(HEADER_SIZE));
+
+        let next = (*node).next;
+        (*self.freelists.get()).reclassify(node, class, block_header.content_size());
+        next
+    }
+
+    /// Merges all free blocks that are adjacent in memory.
+    /// Returns whether any blocks were merged.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    #[instrument(level = "debug", ret(level = Level::DEBUG))]
+    unsafe fn merge_all_nodes(&self) -> bool {
+        let mut merged = false;
+        for class in 0..SIZE_CLASS_COUNT {
+            let mut p: *mut Node = raw_ptr((*self.freelists.get()).list(class).head());
+            while !p.is_null() {
+                let block_header: *const Header = p.cast::<Header>().sub(1);
+                let block_content_size = (*block_header).content_size();
+                let next = self.merge_subsequent_nodes(p);
+                merged |= (*block_header).content_size() != block_content_size;
+                p = next;
+            }
+        }
+        merged
     }
 
     /// Places the object with the provided parameters into the first free block
-    /// that can accomodate the object. Returns a pointer to that object or `Err(())`
+    /// that can accomodate the object. Lists are searched in increasing size class order
+    /// starting from the class of the object. Returns a pointer to that object or `None`
     /// if there was no suitable block for the object.
     ///
     /// # Safety
     /// This function is unsafe since it assumes that the object layout is augmented
     /// and that no allocator field is currently borrowed.
-    #[instrument(level = "debug", ret(level = Level::DEBUG), err(Debug, level = Level::DEBUG))]
+    #[instrument(level = "debug", ret(level = Level::DEBUG))]
     unsafe fn place_in_first_free_block(
         &self,
         obj_size: usize,
         obj_align: usize,
-    ) -> Result<NonNull<u8>, ()> {
-        let mut p: *mut Node = raw_ptr((*self.freelist.get()).head());
+    ) -> Option<NonNull<u8>> {
+        for class in size_class(obj_size)..SIZE_CLASS_COUNT {
+            let mut p: *mut Node = raw_ptr((*self.freelists.get()).list(class).head());
 
-        while !p.is_null() {
-            self.merge_subsequent_nodes(p);
+            while !p.is_null() {
+                let next = self.merge_subsequent_nodes(p);
 
-            let free_block_start = p.cast::<u8>().sub(HEADER_SIZE);
-            let free_block_header: &Header = &*free_block_start.cast();
-            let free_block_content_size = free_block_header.content_size();
-            debug!(
-                ?free_block_start,
-                ?free_block_content_size,
-                "Found free block."
-            );
+                let free_block_start = p.cast::<u8>().sub(HEADER_SIZE);
+                let free_block_header: &Header = &*free_block_start.cast();
+                let free_block_content_size = free_block_header.content_size();
+                debug!(
+                    ?free_block_start,
+                    ?free_block_content_size,
+                    "Found free block."
+                );
+
+                if let Some(obj_start) = self.try_place(free_block_start, obj_size, obj_align) {
+                    return Some(obj_start);
+                }
+
+                debug!("Couldn't place object in free block. Continuing...");
+                p = next;
+            }
+        }
+
+        None
+    }
+
+    /// Places the object with the provided parameters into the first free block
+    /// that can accomodate the object. Lists are searched in increasing size class order
+    /// starting from the class of the object. Within a list the search starts from the list's
+    /// rover and wraps around to the head, on success the rover is moved past the chosen block.
+    /// Returns a pointer to that object or `None` if there was no suitable block for the object.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the object layout is augmented
+    /// and that no allocator field is currently borrowed.
+    #[instrument(level = "debug", ret(level = Level::DEBUG))]
+    unsafe fn place_in_next_free_block(
+        &self,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>> {
+        for class in size_class(obj_size)..SIZE_CLASS_COUNT {
+            let freelist: *mut Freelist = (*self.freelists.get()).list(class);
+            let start: *mut Node = match (*freelist).rover() {
+                Some(rover) => rover.as_ptr(),
+                None => raw_ptr((*freelist).head()),
+            };
+            let mut p = start;
+            let mut wrapped = false;
+
+            loop {
+                if p.is_null() {
+                    if wrapped {
+                        break;
+                    }
+                    debug!("Reached the end of the freelist, wrapping around.");
+                    wrapped = true;
+                    p = raw_ptr((*freelist).head());
+                    continue;
+                }
+                if wrapped && p == start {
+                    break;
+                }
+
+                let next = self.merge_subsequent_nodes(p);
+                let free_block_start = p.cast::<u8>().sub(HEADER_SIZE);
+
+                if let Some(obj_start) = self.try_place(free_block_start, obj_size, obj_align) {
+                    (*freelist).set_rover(next);
+                    return Some(obj_start);
+                }
+
+                debug!("Couldn't place object in free block. Continuing...");
+                p = next;
+            }
+        }
+
+        None
+    }
+
+    /// Places the object with the provided parameters into the smallest free block
+    /// that can accomodate the object. Returns a pointer to that object or `None`
+    /// if there was no suitable block for the object.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the object layout is augmented
+    /// and that no allocator field is currently borrowed.
+    #[instrument(level = "debug", ret(level = Level::DEBUG))]
+    unsafe fn place_in_best_free_block(
+        &self,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>> {
+        let first_class = size_class(obj_size);
+
+        // Merging has to be done upfront, otherwise the best block
+        // might get absorbed by a block that is visited later.
+        for class in first_class..SIZE_CLASS_COUNT {
+            let mut p: *mut Node = raw_ptr((*self.freelists.get()).list(class).head());
+            while !p.is_null() {
+                p = self.merge_subsequent_nodes(p);
+            }
+        }
+
+        let mut best: Option<(*mut u8, NonNull<u8>, usize)> = None;
+
+        // Blocks of a higher class are always larger, so the first class
+        // with a fitting block contains the best one.
+        for class in first_class..SIZE_CLASS_COUNT {
+            let mut p: *mut Node = raw_ptr((*self.freelists.get()).list(class).head());
+
+            while !p.is_null() {
+                let free_block_start = p.cast::<u8>().sub(HEADER_SIZE);
+                let free_block_header: &Header = &*free_block_start.cast();
+                let free_block_content_size = free_block_header.content_size();
+
+                if let Some(obj_start) = self.fit(free_block_start, obj_size, obj_align) {
+                    if best.is_none_or(|(_, _, size)| free_block_content_size < size) {
+                        debug!(?free_block_start, ?free_block_content_size, "Found better fit.");
+                        best = Some((free_block_start, obj_start, free_block_content_size));
+                    }
+                    if free_block_content_size == obj_size {
+                        debug!("Found exact fit, stopping.");
+                        break;
+                    }
+                }
+
+                p = (*p).next;
+            }
+
+            if best.is_some() {
+                break;
+            }
+        }
+
+        let (block_start, obj_start, _) = best?;
+        self.place(block_start, obj_start.as_ptr(), obj_size);
+        Some(obj_start)
+    }
 
-            if let Ok(obj_start) = self.try_place(free_block_start, obj_size, obj_align) {
-                return Ok(obj_start);
+    /// Returns the node of the free block that ends at the heap end
+    /// or `None` if the block at the heap end is occupied.
+    ///
+    /// With footers free blocks are always merged and the block is read through its footer.
+    /// Otherwise the blocks of the last region are walked and the free blocks at its end
+    /// are merged, so that the returned block is as large as possible.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    unsafe fn find_last_free_node(&self) -> Option<*mut Node> {
+        let heap_end: *mut u8 = self.heap_end()?.as_ptr();
+        if FOOTERS {
+            if !self.tail_free.get() {
+                return None;
             }
+            let footer: &Header = &*heap_end.sub(HEADER_SIZE).cast();
+            return Some(heap_end.sub(footer.content_size()).cast());
+        }
 
-            debug!("Couldn't place object in free block. Continuing...");
-            p = (*p).next;
+        self.heap_start.get()?;
+        let mut block_start = self.last_region_start();
+        // The first of the free blocks up to the current one.
+        let mut free_run = None;
+        while block_start < heap_end {
+            let block_header: &Header = &*block_start.cast();
+            free_run = match block_header.is_tagged() {
+                true => free_run.or(Some(block_start)),
+                false => None,
+            };
+            block_start = block_start.add(HEADER_SIZE + block_header.content_size());
         }
 
-        Err(())
+        let node: *mut Node = free_run?.add(HEADER_SIZE).cast();
+        self.merge_subsequent_nodes(node);
+        Some(node)
     }
 
     /// Returns the current end of the heap.
@@ -554,22 +1136,19 @@ impl<T: Grower> RawMalloc<T> {
     /// allocator's inner grower.
     #[inline(always)]
     unsafe fn heap_end(&self) -> Option<NonNull<u8>> {
-        match (*self.grower.get()).grow(0) {
-            Ok((end, _)) => Some(end),
-            Err(()) => None,
-        }
+        (*self.grower.get()).grow(0).ok().map(|(end, _)| end)
     }
 }
 
 //---------------impl Allocator for RawMalloc---------------//
 
-unsafe impl<T: Grower> Allocator for RawMalloc<T> {
+unsafe impl<T: Grower, P: Placement> Allocator for RawMalloc<T, P> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         unsafe {
-            let Ok(obj_start) = self.__alloc(layout) else {
+            let Ok(ptr) = self.try_alloc_detailed(layout) else {
                 return Err(AllocError);
             };
-            Ok(to_nonnull_slice(obj_start))
+            Ok(to_nonnull_slice(ptr, object_contents(ptr.as_ptr(), "allocate")))
         }
     }
 
@@ -579,79 +1158,60 @@ unsafe impl<T: Grower> Allocator for RawMalloc<T> {
 
     unsafe fn grow(
         &self,
-        obj_start: NonNull<u8>,
+        ptr: NonNull<u8>,
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
         debug_assert!(old_layout.size() <= new_layout.size());
         debug_assert_eq!(old_layout.align(), new_layout.align());
-        let Ok(obj_start) = self.__realloc(obj_start.as_ptr(), old_layout, new_layout.size())
-        else {
+        let Ok(ptr) = self.try_realloc_detailed(ptr.as_ptr(), old_layout, new_layout.size()) else {
             return Err(AllocError);
         };
-        Ok(to_nonnull_slice(obj_start))
+        Ok(to_nonnull_slice(ptr, object_contents(ptr.as_ptr(), "grow")))
     }
 
     unsafe fn shrink(
         &self,
-        obj_start: NonNull<u8>,
+        ptr: NonNull<u8>,
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
         debug_assert!(old_layout.size() >= new_layout.size());
         debug_assert_eq!(old_layout.align(), new_layout.align());
 
-        let Ok(obj_start) = self.__realloc(obj_start.as_ptr(), old_layout, new_layout.size())
-        else {
+        let Ok(ptr) = self.try_realloc_detailed(ptr.as_ptr(), old_layout, new_layout.size()) else {
             return Err(AllocError);
         };
 
-        Ok(to_nonnull_slice(obj_start))
+        Ok(to_nonnull_slice(ptr, object_contents(ptr.as_ptr(), "shrink")))
     }
 }
 
 //---------------impl GlobalAlloc for RawMalloc---------------//
 
-unsafe impl<T: Grower> GlobalAlloc for RawMalloc<T> {
+unsafe impl<T: Grower, P: Placement> GlobalAlloc for RawMalloc<T, P> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        raw_ptr(self.__alloc(layout).ok())
+        raw_ptr(self.try_alloc_detailed(layout).ok())
     }
 
     #[instrument(level = "info")]
-    unsafe fn dealloc(&self, obj_start: *mut u8, layout: Layout) {
-        let block_start = obj_start.sub(HEADER_SIZE);
-        let block_header: &Header = &*block_start.cast();
-
-        debug_assert_eq!(
-            obj_start as usize % HEADER_ALIGN,
-            0,
-            "All allocations should have header alignment."
-        );
-
-        debug_assert!(
-            !block_header.is_tagged(),
-            "Allocations should be preceded by untagged headers."
-        );
-        debug_assert!(
-            block_header.content_size() >= BLOCK_CONTENT_MIN_SIZE,
-            "Allocation size should be at least {BLOCK_MIN_SIZE}."
-        );
-
-        self.free_block(block_start);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dealloc_untraced(ptr, layout);
+        self.trace(TraceOp::Dealloc, layout, ptr, null(), 0);
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        raw_ptr(self.__realloc(ptr, layout, new_size).ok())
+        raw_ptr(self.try_realloc_detailed(ptr, layout, new_size).ok())
     }
 }
 
-impl<T: Grower> PartialEq for RawMalloc<T> {
+impl<T: Grower, P: Placement> PartialEq for RawMalloc<T, P> {
     fn eq(&self, other: &Self) -> bool {
         core::ptr::eq(self, other)
     }
 }
 
-impl<T: Grower> Eq for RawMalloc<T> {}
+impl<T: Grower, P: Placement> Eq for RawMalloc<T, P> {}
 
 #[cfg(test)]
 mod tests;
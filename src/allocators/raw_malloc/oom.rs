@@ -0,0 +1,99 @@
+//! Out-of-memory handlers, which get a chance to free memory before an allocation fails,
+//! see [`RawMalloc::set_oom_handler`].
+//!
+//! Like C++'s `new_handler`, a handler is called every time an allocation fails for lack of
+//! memory and decides whether the allocation is retried or fails. Handlers typically shed load,
+//! flush caches or [`trim`](RawMalloc::trim) other heaps before asking for a retry.
+
+use super::placement::Placement;
+use super::stats::Stats;
+use super::RawMalloc;
+use crate::error::MallocError;
+use crate::growers::Grower;
+
+use core::alloc::Layout;
+
+/// A function called with a failed allocation,
+/// which decides whether the allocation should be retried.
+pub type OomHandler = fn(&AllocFailure) -> OomAction;
+
+/// What an allocator does after its [`OomHandler`] returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OomAction {
+    /// Retry the allocation, which calls the handler again if it fails again.
+    Retry,
+    /// Give up, that is return a null pointer or an error.
+    Fail,
+}
+
+/// A failed allocation, as seen by an [`OomHandler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocFailure {
+    /// The layout of the allocation, the new layout for reallocations.
+    pub layout: Layout,
+    /// The reason the allocation failed.
+    pub error: MallocError,
+    /// The statistics of the allocator at the time of the failure.
+    pub stats: Stats,
+    /// The number of times the allocation has already been retried.
+    pub retries: usize,
+}
+
+/// Returns whether an allocation that failed with `error` might succeed once memory is freed.
+/// Handlers aren't called for other failures since retrying would fail the same way.
+fn is_out_of_memory(error: MallocError) -> bool {
+    match error {
+        MallocError::OutOfMemory | MallocError::LimitExceeded | MallocError::Os(_) => true,
+        MallocError::LayoutTooLarge
+        | MallocError::UnsatisfiableAlignment
+        | MallocError::Unsupported
+        | MallocError::InvalidSize => false,
+    }
+}
+
+/// Calls `attempt` until it succeeds or the handler returned by `handler` gives up on it,
+/// passing the handler the statistics returned by `stats` on each failure.
+/// `handler` is only called on failures, keeping it off the fast path.
+pub(crate) fn retry_on_oom<R>(
+    handler: impl Fn() -> Option<OomHandler>,
+    layout: Layout,
+    mut attempt: impl FnMut() -> Result<R, MallocError>,
+    stats: impl Fn() -> Stats,
+) -> Result<R, MallocError> {
+    let mut retries = 0;
+    loop {
+        let error = match attempt() {
+            Ok(result) => return Ok(result),
+            Err(error) => error,
+        };
+        let Some(handler) = handler().filter(|_| is_out_of_memory(error)) else {
+            return Err(error);
+        };
+        let failure = AllocFailure {
+            layout,
+            error,
+            stats: stats(),
+            retries,
+        };
+        if handler(&failure) == OomAction::Fail {
+            return Err(error);
+        }
+        retries += 1;
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Sets the function called when an allocation or a growing reallocation runs out of memory,
+    /// or removes it if `handler` is `None`. See the [`oom`](self) module for details.
+    ///
+    /// The handler is called outside of any allocator operation, so it may use the allocator,
+    /// although allocating from it is likely to fail again.
+    pub fn set_oom_handler(&self, handler: Option<OomHandler>) {
+        self.oom_handler.set(handler);
+    }
+
+    /// Returns the allocator's out-of-memory handler, if any.
+    pub fn oom_handler(&self) -> Option<OomHandler> {
+        self.oom_handler.get()
+    }
+}
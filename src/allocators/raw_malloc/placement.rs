@@ -0,0 +1,90 @@
+//! Placement policies that decide which free block [`RawMalloc`] uses for an allocation.
+//!
+//! All policies search the allocator's freelists in increasing size class order,
+//! starting from the class of the allocation, and only differ in how they
+//! pick a block from within a freelist:
+//!
+//! - [`FirstFit`] places the object into the first free block (starting from the freelist head)
+//!   that can accomodate it. This is the fastest policy for fresh heaps but tends to
+//!   cluster small fragments at the front of the freelist.
+//! - [`NextFit`] works like [`FirstFit`] but resumes the search from where the previous one
+//!   stopped, spreading allocations more evenly across the freelist.
+//! - [`BestFit`] walks the whole freelist and picks the smallest block that can
+//!   accomodate the object, trading speed for less fragmentation.
+//!
+//! [`RawMalloc`]: super::RawMalloc
+
+use super::RawMalloc;
+use crate::growers::Grower;
+
+use core::fmt::Debug;
+use core::ptr::NonNull;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A strategy for choosing a free block for an allocation.
+///
+/// This trait is sealed, the available policies are [`FirstFit`], [`NextFit`] and [`BestFit`].
+pub trait Placement: sealed::Sealed + Debug + Clone + Sized {
+    /// Places an object with the provided parameters into a free block of `allocator`.
+    /// Returns a pointer to that object or `None` if there was no suitable block for the object.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the object layout is augmented
+    /// and that no allocator field is currently borrowed.
+    #[doc(hidden)]
+    unsafe fn place<T: Grower>(
+        allocator: &RawMalloc<T, Self>,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>>;
+}
+
+/// Place objects into the first free block that fits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FirstFit;
+
+/// Place objects into the first free block that fits,
+/// starting from where the previous search left off.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NextFit;
+
+/// Place objects into the smallest free block that fits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BestFit;
+
+impl sealed::Sealed for FirstFit {}
+impl sealed::Sealed for NextFit {}
+impl sealed::Sealed for BestFit {}
+
+impl Placement for FirstFit {
+    unsafe fn place<T: Grower>(
+        allocator: &RawMalloc<T, Self>,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>> {
+        allocator.place_in_first_free_block(obj_size, obj_align)
+    }
+}
+
+impl Placement for NextFit {
+    unsafe fn place<T: Grower>(
+        allocator: &RawMalloc<T, Self>,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>> {
+        allocator.place_in_next_free_block(obj_size, obj_align)
+    }
+}
+
+impl Placement for BestFit {
+    unsafe fn place<T: Grower>(
+        allocator: &RawMalloc<T, Self>,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Option<NonNull<u8>> {
+        allocator.place_in_best_free_block(obj_size, obj_align)
+    }
+}
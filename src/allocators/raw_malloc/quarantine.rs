@@ -0,0 +1,182 @@
+//! Use-after-free detection by poisoning freed objects and keeping them in a quarantine,
+//! see [`RawMalloc::set_quarantine_budget`].
+//!
+//! Quarantined blocks stay occupied as far as the rest of the allocator is concerned,
+//! so they are neither coalesced nor reused. Their contents are laid out as follows:
+//! ```text
+//! | next | poison ... |
+//! ```
+//! where `next` links to the next newer quarantined block. Every other byte is filled with
+//! [`POISON_BYTE`], which is verified when the block leaves the quarantine and by
+//! [`RawMalloc::validate`]. Quarantined blocks are told apart from live objects by a bit
+//! in their header, so nothing a live object stores can make it look quarantined.
+
+use super::hardened::abort;
+use super::placement::Placement;
+use super::stats::{dec, inc};
+use super::{RawMalloc, BLOCK_CONTENT_MIN_SIZE};
+use crate::growers::Grower;
+use crate::header::{Header, HEADER_ALIGN, HEADER_SIZE};
+
+use core::cell::Cell;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::slice;
+
+/// The byte pattern the contents of quarantined blocks are filled with.
+pub const POISON_BYTE: u8 = 0xdd;
+
+const POISON_WORD: usize = usize::from_ne_bytes([POISON_BYTE; size_of::<usize>()]);
+
+/// The size of the link at the start of quarantined blocks.
+const QUARANTINE_LINK_SIZE: usize = size_of::<*mut u8>();
+
+/// A FIFO of freed blocks, linked through their contents.
+#[derive(Debug)]
+pub(super) struct Quarantine {
+    budget: Cell<usize>,
+    bytes: Cell<usize>,
+    // The oldest and the newest quarantined block, both null if the quarantine is empty.
+    head: Cell<*mut u8>,
+    tail: Cell<*mut u8>,
+}
+
+impl Quarantine {
+    pub const fn new() -> Self {
+        Quarantine {
+            budget: Cell::new(0),
+            bytes: Cell::new(0),
+            head: Cell::new(null_mut()),
+            tail: Cell::new(null_mut()),
+        }
+    }
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Returns the quarantine budget in bytes, 0 if the quarantine is disabled.
+    pub fn quarantine_budget(&self) -> usize {
+        self.quarantine.budget.get()
+    }
+
+    /// Returns the total size of the blocks currently in quarantine, including their headers.
+    pub fn quarantined_bytes(&self) -> usize {
+        self.quarantine.bytes.get()
+    }
+
+    /// Sets the quarantine budget in bytes, which enables the quarantine unless it's 0.
+    ///
+    /// While the quarantine is enabled freed objects are filled with [`POISON_BYTE`]
+    /// and kept in a FIFO queue instead of being freed right away, so a use after free
+    /// doesn't corrupt a newer object sharing the same memory. Once the blocks
+    /// in quarantine take more than `budget` bytes the oldest ones are released,
+    /// after verifying that they are still poisoned. Writes to a quarantined object
+    /// abort the process with a diagnostic at that point or make [`validate`](RawMalloc::validate)
+    /// fail before then.
+    ///
+    /// Lowering the budget releases blocks right away, setting it to 0 empties the quarantine.
+    pub fn set_quarantine_budget(&self, budget: usize) {
+        self.quarantine.budget.set(budget);
+        unsafe { self.release_quarantine(budget) };
+    }
+
+    /// Frees the block pointed to by `block_start` or puts it into quarantine,
+    /// if the quarantine is enabled.
+    ///
+    /// # Safety
+    /// Same as for [`free_block`](RawMalloc::free_block).
+    pub(super) unsafe fn retire_block(&self, block_start: *mut u8) {
+        let budget = self.quarantine.budget.get();
+        if budget == 0 {
+            self.free_block(block_start);
+            return;
+        }
+
+        let block_header = &mut *block_start.cast::<Header>();
+        *block_header = block_header.with_quarantined(true);
+        let content_size = block_header.content_size();
+        let contents = block_start.add(HEADER_SIZE);
+        contents.write_bytes(POISON_BYTE, content_size);
+        *contents.cast::<*mut u8>() = null_mut();
+
+        let quarantine = &self.quarantine;
+        match quarantine.tail.get() {
+            tail if tail.is_null() => quarantine.head.set(block_start),
+            tail => *tail.add(HEADER_SIZE).cast::<*mut u8>() = block_start,
+        }
+        quarantine.tail.set(block_start);
+        inc(&quarantine.bytes, HEADER_SIZE + content_size);
+        // Quarantined blocks count as in use until they are released.
+        inc(&self.counters.in_use, content_size);
+
+        self.release_quarantine(budget);
+    }
+
+    /// Releases the oldest quarantined blocks until they take at most `budget` bytes.
+    /// Aborts the process if any of them was written to since it was quarantined.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    unsafe fn release_quarantine(&self, budget: usize) {
+        let quarantine = &self.quarantine;
+        while quarantine.bytes.get() > budget {
+            let block_start = quarantine.head.get();
+            if !self.is_intact(block_start) {
+                abort(format_args!(
+                    "use after free of block {block_start:?} detected on quarantine release"
+                ));
+            }
+            let next = *block_start.add(HEADER_SIZE).cast::<*mut u8>();
+            quarantine.head.set(next);
+            if next.is_null() {
+                quarantine.tail.set(null_mut());
+            }
+
+            let block_header = &mut *block_start.cast::<Header>();
+            *block_header = block_header.with_quarantined(false);
+            let content_size = block_header.content_size();
+            dec(&quarantine.bytes, HEADER_SIZE + content_size);
+            dec(&self.counters.in_use, content_size);
+            self.free_block(block_start);
+        }
+    }
+
+    /// Returns whether the occupied block pointed to by `block_start` is in quarantine.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `block_start` points to an occupied block.
+    #[inline]
+    pub(super) unsafe fn is_quarantined(&self, block_start: *const u8) -> bool {
+        (*block_start.cast::<Header>()).is_quarantined()
+    }
+
+    /// Returns whether the quarantined block pointed to by `block_start` is unchanged
+    /// since it was quarantined, that is its contents are still poisoned and its link
+    /// points to another quarantined block (or is null).
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `block_start` points to a block
+    /// that was quarantined and that no allocator field is currently borrowed.
+    pub(super) unsafe fn is_intact(&self, block_start: *const u8) -> bool {
+        let next = *block_start.add(HEADER_SIZE).cast::<*const u8>();
+        let next_valid = next.is_null()
+            || (self.region_of(next).is_some_and(|(_, end)| {
+                next as usize + HEADER_SIZE + BLOCK_CONTENT_MIN_SIZE <= end as usize
+            }) && (next as usize).is_multiple_of(HEADER_ALIGN)
+                && self.is_quarantined(next));
+        self.is_quarantined(block_start) && next_valid && self.is_poisoned(block_start)
+    }
+
+    /// Returns whether the contents of the quarantined block pointed to by `block_start`
+    /// are still poisoned.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `block_start` points to a quarantined block.
+    unsafe fn is_poisoned(&self, block_start: *const u8) -> bool {
+        let content_size = (*block_start.cast::<Header>()).content_size();
+        let poison: *const usize = block_start.add(HEADER_SIZE + QUARANTINE_LINK_SIZE).cast();
+        let words = (content_size - QUARANTINE_LINK_SIZE) / size_of::<usize>();
+        slice::from_raw_parts(poison, words)
+            .iter()
+            .all(|&word| word == POISON_WORD)
+    }
+}
@@ -0,0 +1,176 @@
+//! Canary redzones around objects, enabled by the `redzones` feature.
+//!
+//! With redzones the contents of an occupied block are laid out as follows:
+//! ```text
+//! | left redzone | object | right redzone | records |
+//! ```
+//! Both redzones are filled with [`REDZONE_BYTE`] when the object is allocated and are checked
+//! when it's freed or reallocated and by [`RawMalloc::validate`](super::RawMalloc::validate),
+//! which catches writes past either end of the object.
+//!
+//! The left redzone is at least [`REDZONE_SIZE`] bytes and at least as large as
+//! the object's alignment, so that the object stays aligned. Its last word encodes its size,
+//! which is how an object pointer is mapped back to its block. The right redzone spans from
+//! the end of the object to the records at the end of the block (the call site record
+//! and the layout record, see the `callsites` and `hardened` modules), so it's at least
+//! [`REDZONE_SIZE`] bytes and also covers any slack left by layout augmentation.
+
+use super::hardened::{abort, read_layout_record};
+use super::util::{content_size, TRAILER_SIZE};
+use crate::header::{Header, HEADER_SIZE};
+
+use core::fmt::{self, Display};
+use core::mem::size_of;
+
+/// The minimal size of each redzone, 0 if the `redzones` feature is disabled.
+pub const REDZONE_SIZE: usize = match cfg!(feature = "redzones") {
+    true => MIN_REDZONE_SIZE,
+    false => 0,
+};
+
+/// The minimal size of each redzone if the `redzones` feature is enabled.
+const MIN_REDZONE_SIZE: usize = 2 * HEADER_SIZE;
+
+/// The byte pattern redzones are filled with.
+pub const REDZONE_BYTE: u8 = 0xfd;
+
+const REDZONE_WORD: usize = usize::from_ne_bytes([REDZONE_BYTE; size_of::<usize>()]);
+
+/// The side of an object a redzone is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedzoneSide {
+    /// The redzone preceding the object.
+    Left,
+    /// The redzone following the object.
+    Right,
+}
+
+impl Display for RedzoneSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedzoneSide::Left => write!(f, "left"),
+            RedzoneSide::Right => write!(f, "right"),
+        }
+    }
+}
+
+/// Returns the size of the left redzone of objects with alignment `align`.
+#[inline(always)]
+pub(super) fn left_redzone_size(align: usize) -> usize {
+    match cfg!(feature = "redzones") {
+        true => align.max(MIN_REDZONE_SIZE),
+        false => 0,
+    }
+}
+
+/// Returns the start of the block contents of the object pointed to by `ptr`.
+/// Without redzones these are the same.
+///
+/// Aborts if the left redzone size can't be decoded, since then the block can't be found.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `ptr` was returned by a [`RawMalloc`](super::RawMalloc).
+#[inline(always)]
+pub(super) unsafe fn object_contents(ptr: *const u8, op: &str) -> *mut u8 {
+    match try_object_contents(ptr) {
+        Some(obj_start) => obj_start,
+        None => abort(format_args!("left redzone of {ptr:?} overwritten in {op}")),
+    }
+}
+
+/// Like [`object_contents`], but returns `None` instead of aborting.
+/// The word preceding `ptr` is overwritten by a freelist node once the object is freed,
+/// so this also fails for most freed objects.
+///
+/// # Safety
+/// This function is unsafe since it assumes that the word preceding `ptr` is readable.
+#[inline(always)]
+pub(super) unsafe fn try_object_contents(ptr: *const u8) -> Option<*mut u8> {
+    if !cfg!(feature = "redzones") {
+        return Some(ptr.cast_mut());
+    }
+    let left_size = *ptr.sub(HEADER_SIZE).cast::<usize>() ^ REDZONE_WORD;
+    if !left_size.is_power_of_two()
+        || left_size < MIN_REDZONE_SIZE
+        || left_size > ptr as usize
+    {
+        return None;
+    }
+    Some(ptr.sub(left_size).cast_mut())
+}
+
+/// Fills the redzones of the object whose block contents start at `obj_start`
+/// and returns a pointer to the object. The object's layout has to be recorded already.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block which was augmented for the recorded layout.
+#[inline(always)]
+pub(super) unsafe fn fill_redzones(obj_start: *mut u8) -> *mut u8 {
+    if !cfg!(feature = "redzones") {
+        return obj_start;
+    }
+    let layout = read_layout_record(obj_start).unwrap_unchecked();
+    let left_size = left_redzone_size(layout.align());
+    let ptr = obj_start.add(left_size);
+    obj_start.write_bytes(REDZONE_BYTE, left_size - HEADER_SIZE);
+    *ptr.sub(HEADER_SIZE).cast::<usize>() = REDZONE_WORD ^ left_size;
+
+    let obj_end = ptr.add(layout.size());
+    let right_end = obj_start.add(content_size(obj_start) - TRAILER_SIZE);
+    obj_end.write_bytes(REDZONE_BYTE, right_end as usize - obj_end as usize);
+    ptr
+}
+
+/// Checks the redzones of the object whose block contents start at `obj_start`
+/// and returns the side of the first overwritten redzone.
+/// A corrupted layout record is treated as an overwritten right redzone.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+pub(super) unsafe fn check_redzones(obj_start: *const u8) -> Result<(), RedzoneSide> {
+    if !cfg!(feature = "redzones") {
+        return Ok(());
+    }
+    let contents = content_size(obj_start) - TRAILER_SIZE;
+    let layout = read_layout_record(obj_start).unwrap_unchecked();
+    let left_size = left_redzone_size(layout.align());
+    let Some(right_size) = contents
+        .checked_sub(left_size)
+        .and_then(|n| n.checked_sub(layout.size()))
+        .filter(|&n| n >= MIN_REDZONE_SIZE)
+    else {
+        return Err(RedzoneSide::Right);
+    };
+
+    let ptr = obj_start.add(left_size);
+    let left = core::slice::from_raw_parts(obj_start, left_size - HEADER_SIZE);
+    if left.iter().any(|&b| b != REDZONE_BYTE)
+        || *ptr.sub(HEADER_SIZE).cast::<usize>() != REDZONE_WORD ^ left_size
+    {
+        return Err(RedzoneSide::Left);
+    }
+    let right = core::slice::from_raw_parts(ptr.add(layout.size()), right_size);
+    if right.iter().any(|&b| b != REDZONE_BYTE) {
+        return Err(RedzoneSide::Right);
+    }
+    Ok(())
+}
+
+/// Aborts the process if the redzones of the object whose block contents start at `obj_start`
+/// are overwritten. `op` names the operation for the diagnostic.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+#[inline(always)]
+pub(super) unsafe fn enforce_redzones(obj_start: *const u8, op: &str) {
+    if let Err(side) = check_redzones(obj_start) {
+        let block = obj_start.sub(HEADER_SIZE);
+        let size = (*block.cast::<Header>()).content_size();
+        abort(format_args!(
+            "{side} redzone of block {block:?} of size {size} overwritten in {op}"
+        ));
+    }
+}
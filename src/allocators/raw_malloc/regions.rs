@@ -0,0 +1,250 @@
+//! Heaps spanning several discontiguous regions, see [`Grower::grow_region`].
+//!
+//! Once the grower can't grow its current region anymore the heap continues in a new region.
+//! Every region but the first starts with a [`REGION_HEADER_SIZE`]-byte header
+//! followed by the region's blocks:
+//! ```text
+//! | region header | block | block | ... | block |
+//! ```
+//! The headers link the regions in the order they were added and record where
+//! the previous region ends. Region ends are treated like the heap end, that is blocks
+//! are never merged across them. Only the last region grows or shrinks, the others are sealed,
+//! which is why the "previous block free" bit of their ends isn't kept anywhere.
+//!
+//! Sealing a region writes a fencepost, a header with a content size of 0, into the
+//! [`REGION_FOOT_SIZE`] bytes the grower keeps past its end. No block is that small,
+//! so the end of a sealed region is told apart from a block start in constant time:
+//! ```text
+//! | block | ... | block | fencepost |   | region header | block | ... | block |
+//! ```
+
+use super::placement::Placement;
+use super::stats::inc;
+use super::util::find_place;
+use super::{RawMalloc, BLOCK_MIN_SIZE};
+use crate::error::MallocError;
+use crate::growers::{Grower, REGION_FOOT_SIZE};
+use crate::header::{Header, HEADER_ALIGN, HEADER_SIZE};
+use crate::util::raw_ptr;
+
+use core::cell::Cell;
+use core::ptr::{null_mut, NonNull};
+
+use static_assertions::const_assert;
+use tracing::{debug, error};
+
+/// The header at the start of every region but the first.
+#[repr(C)]
+pub(super) struct RegionHeader {
+    /// The end of the previous region.
+    pub prev_end: *mut u8,
+    /// The header of the next region, null for the last region.
+    pub next: *mut RegionHeader,
+}
+
+/// The size of region headers, which keeps the blocks after them aligned.
+pub(super) const REGION_HEADER_SIZE: usize = size_of::<RegionHeader>();
+
+const_assert!(REGION_HEADER_SIZE.is_multiple_of(HEADER_ALIGN));
+
+/// The header written at the end of every sealed region.
+pub(super) const FENCEPOST: Header = Header { __content_size: 0 };
+
+const_assert!(REGION_FOOT_SIZE >= HEADER_SIZE);
+
+/// The regions of an allocator after its first one.
+pub(super) struct RegionList {
+    /// The header of the second region, null while the heap has a single region.
+    pub first: Cell<*mut RegionHeader>,
+    /// The header of the last region, null while the heap has a single region.
+    last: Cell<*mut RegionHeader>,
+    /// The number of region headers.
+    pub count: Cell<usize>,
+}
+
+impl RegionList {
+    pub const fn new() -> Self {
+        RegionList {
+            first: Cell::new(null_mut()),
+            last: Cell::new(null_mut()),
+            count: Cell::new(0),
+        }
+    }
+}
+
+/// An iterator over the `(start, end)` bounds of the regions of a heap in the order
+/// they were added. The end of the last region is the heap end at the time of creation.
+pub(super) struct Regions {
+    /// The start of the next region, null once all regions are visited.
+    start: *mut u8,
+    /// The header of the region after the next one.
+    header: *mut RegionHeader,
+    heap_end: *mut u8,
+}
+
+impl Iterator for Regions {
+    type Item = (*mut u8, *mut u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.start;
+        if start.is_null() {
+            return None;
+        }
+        let end = match self.header.is_null() {
+            true => {
+                self.start = null_mut();
+                self.heap_end
+            }
+            false => unsafe {
+                let header = self.header;
+                self.start = header.cast::<u8>().add(REGION_HEADER_SIZE);
+                self.header = (*header).next;
+                (*header).prev_end
+            },
+        };
+        Some((start, end))
+    }
+}
+
+/// Returns whether a growth that failed with `error` failed since the current region
+/// ran out of memory, which a new region may have. Other failures, like exceeding a limit,
+/// would only seal the current region for good without need.
+pub(super) fn is_region_full(error: MallocError) -> bool {
+    matches!(error, MallocError::OutOfMemory | MallocError::Os(libc::ENOMEM))
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Returns the number of regions the heap spans, 0 if the heap was never grown.
+    pub fn region_count(&self) -> usize {
+        match self.heap_start.get() {
+            Some(_) => self.regions.count.get() + 1,
+            None => 0,
+        }
+    }
+
+    /// Returns an iterator over the bounds of all regions.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the allocator's grower
+    /// isn't currently borrowed.
+    pub(super) unsafe fn regions(&self) -> Regions {
+        Regions {
+            start: raw_ptr(self.heap_start.get()),
+            header: self.regions.first.get(),
+            heap_end: raw_ptr(self.heap_end()),
+        }
+    }
+
+    /// Returns the start of the blocks of the last region, null if the heap was never grown.
+    pub(super) fn last_region_start(&self) -> *mut u8 {
+        let last = self.regions.last.get();
+        match last.is_null() {
+            true => raw_ptr(self.heap_start.get()),
+            false => unsafe { last.cast::<u8>().add(REGION_HEADER_SIZE) },
+        }
+    }
+
+    /// Returns the bounds of the region containing `ptr`, if any.
+    /// The last region, where the heap grows, is checked without walking the others.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the allocator's grower
+    /// isn't currently borrowed.
+    pub(super) unsafe fn region_of(&self, ptr: *const u8) -> Option<(*mut u8, *mut u8)> {
+        let start = self.last_region_start();
+        let end = raw_ptr(self.heap_end());
+        if start.cast_const() <= ptr && ptr < end {
+            return Some((start, end));
+        }
+        if self.regions.last.get().is_null() {
+            return None;
+        }
+        self.regions()
+            .find(|&(start, end)| start.cast_const() <= ptr && ptr < end)
+    }
+
+    /// Returns whether `ptr` is the end of any region, that is whether
+    /// no block starts at `ptr` even though the preceding block ends there.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `ptr` is either the start of a block
+    /// or a region end, and that the allocator's grower isn't currently borrowed.
+    #[inline(always)]
+    pub(super) unsafe fn is_region_end(&self, ptr: *const u8) -> bool {
+        ptr == raw_ptr(self.heap_end()) || self.is_sealed_region_end(ptr)
+    }
+
+    /// Returns whether `ptr` is the end of a region other than the last one,
+    /// that is whether a fencepost is written at `ptr`.
+    ///
+    /// # Safety
+    /// Callers must ensure that `ptr` is either the start of a block or the end
+    /// of a sealed region.
+    #[inline(always)]
+    pub(super) unsafe fn is_sealed_region_end(&self, ptr: *const u8) -> bool {
+        !self.regions.first.get().is_null() && *ptr.cast::<Header>() == FENCEPOST
+    }
+
+    /// Moves the heap to a new region for an allocation of size `obj_size` and alignment
+    /// `obj_align` after the current region couldn't grow. Returns the same as
+    /// [`grow`](RawMalloc::grow), that is the start of the new blocks, the space available to them
+    /// and a pointer to where to put the allocation, or the reason the heap couldn't move.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `obj_align` and `obj_size`
+    /// conform to the allocator object requirements (See the [`module`](super) level
+    /// documentation). Additionally callers must ensure that no allocator field is currently
+    /// borrowed.
+    pub(super) unsafe fn grow_region(
+        &self,
+        obj_size: usize,
+        obj_align: usize,
+    ) -> Result<(NonNull<u8>, usize, NonNull<u8>), MallocError> {
+        let old_heap_end = raw_ptr(self.heap_end());
+        let has_header = self.heap_start.get().is_some();
+        let header_size = if has_header { REGION_HEADER_SIZE } else { 0 };
+
+        // The object's header and left padding can take at most `HEADER_SIZE + BLOCK_MIN_SIZE`
+        // bytes plus the alignment.
+        let size = [HEADER_SIZE + BLOCK_MIN_SIZE, obj_align, obj_size]
+            .into_iter()
+            .try_fold(header_size, usize::checked_add)
+            .ok_or(MallocError::LayoutTooLarge)?;
+
+        let grown = (*self.grower.get()).grow_region(size);
+        if grown == Err(MallocError::Unsupported) {
+            return Err(MallocError::Unsupported);
+        }
+        inc(&self.counters.grow_calls, 1);
+        let (region_start, growth) =
+            grown.inspect_err(|_| error!("Growth failure, couldn't create a new region."))?;
+        debug_assert_eq!(region_start.as_ptr() as usize % HEADER_ALIGN, 0);
+        debug_assert!(growth >= size);
+        inc(&self.counters.grown, growth);
+
+        let blocks_start = region_start.add(header_size);
+        if has_header {
+            *old_heap_end.cast::<Header>() = FENCEPOST;
+            let header: *mut RegionHeader = region_start.as_ptr().cast();
+            *header = RegionHeader {
+                prev_end: old_heap_end,
+                next: null_mut(),
+            };
+            match self.regions.last.get().is_null() {
+                true => self.regions.first.set(header),
+                false => (*self.regions.last.get()).next = header,
+            }
+            self.regions.last.set(header);
+            inc(&self.regions.count, 1);
+            // The last block of the previous region is no longer at the heap end.
+            self.tail_free.set(false);
+            debug!(?region_start, ?old_heap_end, "Sealed the previous region.");
+        } else {
+            self.heap_start.set(Some(region_start));
+        }
+
+        let obj_start = find_place(blocks_start.as_ptr(), obj_align)
+            .expect("The region should fit the object.");
+        Ok((blocks_start, growth - header_size, obj_start))
+    }
+}
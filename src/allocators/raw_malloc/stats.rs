@@ -0,0 +1,169 @@
+//! Allocation statistics, see [`RawMalloc::stats`].
+
+use super::placement::Placement;
+use super::regions::REGION_HEADER_SIZE;
+use super::util::{augment_layout, content_size};
+use super::RawMalloc;
+use crate::growers::Grower;
+use crate::header::HEADER_SIZE;
+
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::ops::{Add, AddAssign};
+
+/// A snapshot of an allocator's statistics.
+///
+/// The heap is fully accounted for, that is
+/// `heap_bytes == in_use_bytes + header_bytes + free_bytes`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes requested by live objects, as given by their layouts.
+    ///
+    /// Objects freed or reallocated with a larger layout than they were allocated with
+    /// (which is allowed up to their usable size) are subtracted with the larger size.
+    pub requested_bytes: usize,
+    /// Bytes occupied by live objects after layout augmentation.
+    /// Objects in quarantine count as in use, but not as requested.
+    pub in_use_bytes: usize,
+    /// Bytes of padding given to live objects along with their augmented layouts,
+    /// since it was too small to form a free block of its own.
+    ///
+    /// Like `requested_bytes` this is subtracted according to the layouts objects are freed with.
+    pub padding_bytes: usize,
+    /// Bytes taken by the headers of all blocks and regions.
+    pub header_bytes: usize,
+    /// Bytes in the contents of free blocks.
+    pub free_bytes: usize,
+    /// The current size of the heap.
+    pub heap_bytes: usize,
+    /// The number of times the heap was grown (or was attempted to be grown).
+    pub grow_calls: usize,
+    /// The total number of bytes the heap was grown by.
+    pub grown_bytes: usize,
+    /// The total number of bytes released back to the grower by trimming.
+    pub released_bytes: usize,
+}
+
+impl Add for Stats {
+    type Output = Stats;
+
+    fn add(self, other: Stats) -> Stats {
+        Stats {
+            requested_bytes: self.requested_bytes + other.requested_bytes,
+            in_use_bytes: self.in_use_bytes + other.in_use_bytes,
+            padding_bytes: self.padding_bytes + other.padding_bytes,
+            header_bytes: self.header_bytes + other.header_bytes,
+            free_bytes: self.free_bytes + other.free_bytes,
+            heap_bytes: self.heap_bytes + other.heap_bytes,
+            grow_calls: self.grow_calls + other.grow_calls,
+            grown_bytes: self.grown_bytes + other.grown_bytes,
+            released_bytes: self.released_bytes + other.released_bytes,
+        }
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        *self = *self + other;
+    }
+}
+
+/// The counters backing [`Stats`], which are updated on every allocator operation.
+#[derive(Debug)]
+pub(super) struct Counters {
+    pub requested: Cell<usize>,
+    pub in_use: Cell<usize>,
+    pub padding: Cell<usize>,
+    pub blocks: Cell<usize>,
+    pub grow_calls: Cell<usize>,
+    pub grown: Cell<usize>,
+    pub released: Cell<usize>,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters {
+            requested: Cell::new(0),
+            in_use: Cell::new(0),
+            padding: Cell::new(0),
+            blocks: Cell::new(0),
+            grow_calls: Cell::new(0),
+            grown: Cell::new(0),
+            released: Cell::new(0),
+        }
+    }
+}
+
+#[inline(always)]
+pub(super) fn inc(counter: &Cell<usize>, n: usize) {
+    counter.set(counter.get() + n);
+}
+
+#[inline(always)]
+pub(super) fn dec(counter: &Cell<usize>, n: usize) {
+    debug_assert!(counter.get() >= n, "Counters should never go below zero.");
+    counter.set(counter.get().wrapping_sub(n));
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Returns a snapshot of the allocator's statistics.
+    ///
+    /// The statistics are kept up to date by every allocator operation,
+    /// so this takes constant time.
+    pub fn stats(&self) -> Stats {
+        let counters = &self.counters;
+        let heap_bytes = counters.grown.get() - counters.released.get();
+        let header_bytes =
+            counters.blocks.get() * HEADER_SIZE + self.regions.count.get() * REGION_HEADER_SIZE;
+        Stats {
+            requested_bytes: counters.requested.get(),
+            in_use_bytes: counters.in_use.get(),
+            padding_bytes: counters.padding.get(),
+            header_bytes,
+            free_bytes: heap_bytes - counters.in_use.get() - header_bytes,
+            heap_bytes,
+            grow_calls: counters.grow_calls.get(),
+            grown_bytes: counters.grown.get(),
+            released_bytes: counters.released.get(),
+        }
+    }
+
+    /// Records that the object whose block contents start at `obj_start`
+    /// was allocated with `layout`.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `obj_start` is the start of the contents
+    /// of an occupied block.
+    #[inline]
+    pub(super) unsafe fn record_alloc(&self, obj_start: *const u8, layout: Layout) {
+        inc(&self.counters.requested, layout.size());
+        inc(&self.counters.in_use, content_size(obj_start));
+        inc(&self.counters.padding, padding_size(obj_start, layout));
+    }
+
+    /// Records that the object whose block contents start at `obj_start`,
+    /// which was allocated with `layout`, is about to be freed.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `obj_start` is the start of the contents
+    /// of an occupied block.
+    #[inline]
+    pub(super) unsafe fn record_dealloc(&self, obj_start: *const u8, layout: Layout) {
+        dec(&self.counters.requested, layout.size());
+        dec(&self.counters.in_use, content_size(obj_start));
+        dec(&self.counters.padding, padding_size(obj_start, layout));
+    }
+}
+
+/// Returns the size of the padding at the end of the occupied block whose contents
+/// start at `obj_start`, that is the block contents past the object allocated with `layout`
+/// after augmentation.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is the start of the contents
+/// of an occupied block.
+#[inline]
+unsafe fn padding_size(obj_start: *const u8, layout: Layout) -> usize {
+    let obj_size = augment_layout(layout).map_or(usize::MAX, |layout| layout.size());
+    content_size(obj_start).saturating_sub(obj_size)
+}
@@ -1,4 +1,5 @@
 //! Ad hoc formatter for readable logs + layer for logging on span entry.
+#![allow(dead_code)]
 
 use std::fmt;
 
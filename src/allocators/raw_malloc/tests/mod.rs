@@ -1,10 +1,16 @@
 #![allow(unused_imports)]
 
-use crate::growers::arena_grower::ArenaGrower;
+use crate::error::MallocError;
+use crate::growers::{ArenaGrower, ChainGrower, LimitGrower};
+
+use core::sync::atomic::AtomicUsize;
 use crate::util::checked_add;
 
 use self::format::{RecordEntryLayer, SimpleFormatter};
 
+use super::redzones::{left_redzone_size, REDZONE_SIZE};
+use super::regions::{FENCEPOST, REGION_HEADER_SIZE};
+use super::util::TRAILER_SIZE;
 use super::*;
 
 use tracing_subscriber::fmt::Layer;
@@ -13,7 +19,20 @@ use tracing_subscriber::{EnvFilter, Registry};
 
 mod format;
 
+/// Returns the size of the redzones and the trailing records of objects with `align`,
+/// that is the block contents they take on top of their size.
+fn overhead(align: usize) -> usize {
+    left_redzone_size(align) + REDZONE_SIZE + TRAILER_SIZE
+}
+
+/// Returns a layout with `align` whose objects take exactly `content_size` bytes
+/// of block contents, so that block layouts don't depend on the enabled features.
+fn block_layout(content_size: usize, align: usize) -> Layout {
+    Layout::from_size_align(content_size - overhead(align), align).unwrap()
+}
+
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn test_1() {
     // let __filter = EnvFilter::from_default_env()
     //     .add_directive("rusty_malloc::allocators=debug".parse().unwrap());
@@ -27,7 +46,7 @@ fn test_1() {
 
     const BUF_SIZE: usize = 64 * 1024;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let mut objects: Vec<(*mut u8, Layout)> = vec![];
@@ -38,6 +57,7 @@ fn test_1() {
         }
     }
 
+    let mut moved: Vec<*mut u8> = objects.iter().map(|o| o.0).collect();
     for i in 0..objects.len() {
         let (ptr, layout) = objects[i];
         assert_eq!(ptr as usize % layout.align(), 0);
@@ -45,12 +65,17 @@ fn test_1() {
             assert!(checked_add(ptr, layout.size()).unwrap() <= objects[i + 1].0);
         }
         unsafe { allocator.dealloc(ptr, layout) };
+        if FOOTERS {
+            // The freed block is coalesced with the free padding around it,
+            // so the object might be placed into a better fitting block.
+            moved[i] = unsafe { allocator.alloc(layout) };
+            continue;
+        }
         unsafe { assert_eq!(allocator.alloc(layout), ptr) };
     }
 
     for i in (0..objects.len()).rev() {
-        let (ptr, layout) = objects[i];
-        unsafe { allocator.dealloc(ptr, layout) };
+        unsafe { allocator.dealloc(moved[i], objects[i].1) };
     }
 
     for i in 0..objects.len() {
@@ -62,7 +87,7 @@ fn test_1() {
 fn test_2() {
     const BUF_SIZE: usize = 32 * BLOCK_MIN_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout_1 = Layout::from_size_align(BLOCK_CONTENT_MIN_SIZE, 1).unwrap();
@@ -87,7 +112,7 @@ fn test_2() {
 fn test_3() {
     const BUF_SIZE: usize = 32 * BLOCK_MIN_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout_1 = Layout::from_size_align(BLOCK_CONTENT_MIN_SIZE, 1).unwrap();
@@ -112,7 +137,7 @@ fn test_3() {
 fn test_4() {
     const BUF_SIZE: usize = 32 * BLOCK_MIN_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout_1 = Layout::from_size_align(BLOCK_CONTENT_MIN_SIZE, 1).unwrap();
@@ -129,6 +154,8 @@ fn test_4() {
         allocator.dealloc(p2, layout_1);
         allocator.dealloc(p4, layout_2);
         allocator.dealloc(p1, layout_1);
+        // p4 is in the size class of the request so it's found before p1 and p2 get merged.
+        assert_eq!(p4, allocator.alloc(layout_2));
         assert_eq!(p1, allocator.alloc(layout_2));
     }
 }
@@ -137,10 +164,10 @@ fn test_4() {
 fn test_5() {
     const BUF_SIZE: usize = 8 * BLOCK_MIN_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
-    let layout = Layout::from_size_align(BUF_SIZE - HEADER_SIZE, 1).unwrap();
+    let layout = block_layout(BUF_SIZE - HEADER_SIZE, 1);
     unsafe {
         let p1 = allocator.alloc(layout);
         assert!(!p1.is_null());
@@ -155,11 +182,11 @@ fn test_5() {
 fn test_6() {
     const BUF_SIZE: usize = 128 * HEADER_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
-    let layout_1 = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
-    let layout_2 = Layout::from_size_align(HEADER_SIZE * 10, HEADER_ALIGN).unwrap();
+    let layout_1 = block_layout(HEADER_SIZE * 8, HEADER_ALIGN);
+    let layout_2 = block_layout(HEADER_SIZE * 20, HEADER_ALIGN);
     unsafe {
         let p1 = allocator.alloc(layout_1);
         let p2 = allocator.alloc(layout_2);
@@ -173,12 +200,35 @@ fn test_6() {
         assert_eq!(p1, allocator.realloc(p1, layout_1, layout_2.size()));
         assert_eq!(p1, allocator.realloc(p1, layout_2, layout_1.size()));
 
+        if !FOOTERS {
+            // Growing p1 leaves a padding block of 8 headers after it,
+            // which is an exact fit for p4.
+            let p4 = allocator.alloc(layout_1);
+            assert_eq!(p1.add(21 * HEADER_SIZE), p4);
+
+            // Shrinking p1 left a free padding block between it and p4 which p1 can grow into.
+            assert_eq!(p1, allocator.realloc(p1, layout_1, layout_2.size()));
+            // p1 can't grow in place anymore and the free block after p4 is too small,
+            // so the object is moved to the end of the heap.
+            assert_eq!(
+                p4.add(30 * HEADER_SIZE),
+                allocator.realloc(p1, layout_2, 2 * layout_2.size())
+            );
+            return;
+        }
+
+        // Shrinking p1 coalesced the released space with the free block after it,
+        // so p4 is placed right after p1.
         let p4 = allocator.alloc(layout_1);
-        assert_eq!(p2, p4);
+        assert_eq!(p1.add(9 * HEADER_SIZE), p4);
 
+        // p1 can't grow in place anymore, so it is moved to the free block after p4.
+        let p5 = allocator.realloc(p1, layout_1, layout_2.size());
+        assert_eq!(p4.add(9 * HEADER_SIZE), p5);
+        // The free block after p5 is too small, so the object is moved to the end of the heap.
         assert_eq!(
-            p4.add(5 * HEADER_SIZE),
-            allocator.realloc(p1, layout_1, layout_2.size())
+            p5.add(33 * HEADER_SIZE),
+            allocator.realloc(p5, layout_2, 2 * layout_2.size())
         );
     }
 }
@@ -187,7 +237,7 @@ fn test_6() {
 fn test_7() {
     const BUF_SIZE: usize = 64 * HEADER_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout = Layout::from_size_align(HEADER_SIZE * 10, HEADER_ALIGN).unwrap();
@@ -206,20 +256,23 @@ fn test_7() {
 fn test_8() {
     const BUF_SIZE: usize = 1024 * HEADER_SIZE;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
-    let layout = Layout::from_size_align(HEADER_SIZE * 32, HEADER_SIZE * 32).unwrap();
+    // Objects take two units of their alignment, which leaves room for a left redzone.
+    const ALIGN: usize = HEADER_SIZE * 32;
+    let layout = block_layout(ALIGN * 2, ALIGN);
     unsafe {
         let p1 = allocator.alloc(layout);
         let p2 = allocator.alloc(layout);
         assert!(!p1.is_null());
         assert!(!p2.is_null());
-        assert_eq!(p1.add(layout.size() * 2), p2);
+        assert_eq!(p1.add(ALIGN * 3), p2);
         let p3 = allocator.realloc(p2, layout, layout.size() * 2);
-        let p4 = allocator.alloc(Layout::from_size_align(layout.size() * 2, HEADER_ALIGN).unwrap());
-        assert_eq!(p1.add(layout.size() + HEADER_SIZE), p4);
-        assert_eq!(p4.add(layout.size() * 3 - HEADER_SIZE), p3);
+        let p4 = allocator.alloc(block_layout(ALIGN * 3, HEADER_ALIGN));
+        let [p1, p3, p4] = [p1, p3, p4].map(|p| object_contents(p, "test_8"));
+        assert_eq!(p1.add(ALIGN * 2 + HEADER_SIZE), p4);
+        assert_eq!(p4.add(ALIGN * 4 - HEADER_SIZE), p3);
     }
 }
 
@@ -227,7 +280,7 @@ fn test_8() {
 fn test_9() {
     const BUF_SIZE: usize = BLOCK_MIN_SIZE * 32;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout = Layout::from_size_align(HEADER_SIZE * 8, HEADER_ALIGN).unwrap();
@@ -236,8 +289,12 @@ fn test_9() {
         assert!(!p1.is_null());
         let p2 = allocator.realloc(p1, layout, 1);
         assert_eq!(p1, p2);
-        let p2_header: *mut Header = p2.sub(HEADER_SIZE).cast();
-        assert_eq!((*p2_header).content_size(), BLOCK_CONTENT_MIN_SIZE);
+        let p2_header: *mut Header = object_contents(p2, "test_9").sub(HEADER_SIZE).cast();
+        let new_layout = Layout::from_size_align(1, HEADER_ALIGN).unwrap();
+        assert_eq!(
+            (*p2_header).content_size(),
+            augment_layout(new_layout).unwrap().size()
+        );
     }
 }
 
@@ -245,7 +302,7 @@ fn test_9() {
 fn test_10() {
     const BUF_SIZE: usize = BLOCK_MIN_SIZE * 8;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let p1 = allocator
@@ -263,7 +320,7 @@ fn test_10() {
 fn test_11() {
     const BUF_SIZE: usize = BLOCK_MIN_SIZE * 8;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, 0);
+    let grower = ArenaGrower::new(&mut buf, 0);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout_1 = Layout::from_size_align(20, 4).unwrap();
@@ -287,7 +344,7 @@ fn test_11() {
 fn test_12() {
     const BUF_SIZE: usize = BLOCK_MIN_SIZE * 32;
     let mut buf = [0_u8; BUF_SIZE];
-    let grower = ArenaGrower::new((&mut buf) as *mut _, BUF_SIZE, BUF_SIZE);
+    let grower = ArenaGrower::new(&mut buf, BUF_SIZE);
     let allocator = unsafe { RawMalloc::with_grower(grower) };
 
     let layout = Layout::from_size_align(BLOCK_CONTENT_MIN_SIZE, 1).unwrap();
@@ -298,3 +355,1096 @@ fn test_12() {
         assert_eq!(p1, p2);
     }
 }
+
+#[test]
+fn test_13() {
+    let grower = crate::growers::MmapGrower::new(1 << 24, 4096);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let mut objects: Vec<(*mut u8, Layout)> = vec![];
+    for i in 0..16 {
+        let l = Layout::from_size_align(1 << i, 1 << (i % 8)).unwrap();
+        let p = unsafe { allocator.alloc(l) };
+        assert!(!p.is_null());
+        assert_eq!(p as usize % l.align(), 0);
+        unsafe { p.write_bytes(i as u8, l.size()) };
+        objects.push((p, l));
+    }
+    for (i, &(p, l)) in objects.iter().enumerate() {
+        unsafe {
+            assert_eq!(*p.add(l.size() - 1), i as u8);
+            allocator.dealloc(p, l);
+        }
+    }
+}
+
+#[test]
+fn test_trim_1() {
+    const BUF_SIZE: usize = BLOCK_MIN_SIZE * 64;
+    let mut buf = [0_u8; BUF_SIZE];
+    let mut grower = ArenaGrower::new(&mut buf, BUF_SIZE / 2);
+    let allocator = unsafe { RawMalloc::with_grower(&mut grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    let content_size = augment_layout(layout).unwrap().size();
+    let block_size = HEADER_SIZE + content_size;
+    let kept_size = augment_size(HEADER_SIZE).unwrap();
+    unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.alloc(layout);
+        assert!(!p1.is_null());
+        assert!(!p2.is_null());
+
+        // Everything after p2 is a single free block.
+        assert_eq!(allocator.trim(0), BUF_SIZE / 2 - 2 * block_size);
+        assert_eq!(allocator.trim(0), 0, "The last block is occupied.");
+
+        allocator.dealloc(p2, layout);
+        assert_eq!(allocator.trim(HEADER_SIZE), content_size - kept_size);
+        assert_eq!(allocator.trim(HEADER_SIZE), 0);
+        assert_eq!(allocator.trim(0), HEADER_SIZE + kept_size);
+
+        // The trimmed memory can be grown into again.
+        assert_eq!(allocator.alloc(layout), p2);
+    }
+    assert_eq!(grower.remaining(), BUF_SIZE / 2 - block_size);
+}
+
+#[test]
+fn test_trim_2() {
+    const BUF_SIZE: usize = BLOCK_MIN_SIZE * 64;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.alloc(layout);
+        let p3 = allocator.alloc(layout);
+        assert!(!p3.is_null());
+        allocator.dealloc(p3, layout);
+        allocator.dealloc(p2, layout);
+        // p2 and p3 should be merged and trimmed as a whole.
+        let content_size = augment_layout(layout).unwrap().size();
+        assert_eq!(allocator.trim(0), 2 * (HEADER_SIZE + content_size));
+        assert_eq!(allocator.alloc(layout), p2);
+        allocator.dealloc(p1, layout);
+        assert_eq!(allocator.trim(0), 0);
+    }
+}
+
+#[test]
+fn test_trim_3() {
+    const BUF_SIZE: usize = BLOCK_MIN_SIZE * 64;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    let content_size = augment_layout(layout).unwrap().size();
+    unsafe {
+        let objects = [0; 5].map(|_| allocator.alloc(layout));
+        allocator.dealloc(objects[0], layout);
+        allocator.dealloc(objects[1], layout);
+        allocator.dealloc(objects[4], layout);
+        allocator.dealloc(objects[3], layout);
+
+        // Only the free blocks at the heap end are merged and released,
+        // without footers the blocks before the occupied one stay apart.
+        assert_eq!(allocator.trim(0), 2 * (HEADER_SIZE + content_size));
+        let free_blocks = allocator.blocks().filter(|block| block.is_free).count();
+        assert_eq!(free_blocks, if FOOTERS { 1 } else { 2 });
+        assert_eq!(allocator.validate(), Ok(()));
+
+        allocator.dealloc(objects[2], layout);
+        check_stats(&allocator, &[]);
+    }
+}
+
+#[test]
+fn test_trim_decommitted() {
+    let grower = crate::growers::MmapGrower::new(1 << 24, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(1 << 16, HEADER_ALIGN).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        assert!(!p.is_null());
+        allocator.dealloc(p, layout);
+        // The whole heap is released, so its pages are decommitted.
+        let heap_bytes = allocator.stats().heap_bytes;
+        assert_eq!(allocator.trim(0), heap_bytes);
+        assert_eq!(allocator.validate(), Ok(()));
+
+        let p = allocator.alloc(layout);
+        assert!(!p.is_null());
+        allocator.dealloc(p, layout);
+    }
+}
+
+/// Allocates blocks of 40, 34, 36 and 34 headers (separated by small occupied blocks), frees the first
+/// three and allocates an object of 33 headers. Then frees the last block and allocates another
+/// object of 33 headers. All of the blocks are in the same size class.
+/// Returns the offsets of the two objects from the first block.
+fn placement_trace<P: Placement>(placement: P) -> (usize, usize) {
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower_and_placement(grower, placement) };
+
+    let layout = |n| block_layout(n * HEADER_SIZE, HEADER_ALIGN);
+    unsafe {
+        let mut objects = vec![];
+        for n in [40, 34, 36, 34] {
+            objects.push((allocator.alloc(layout(n)), layout(n)));
+            assert!(!allocator.alloc(separator_layout()).is_null());
+        }
+        for &(p, l) in objects[..3].iter().rev() {
+            allocator.dealloc(p, l);
+        }
+
+        let base = objects[0].0 as usize;
+        let p1 = allocator.alloc(layout(33));
+        allocator.dealloc(objects[3].0, objects[3].1);
+        let p2 = allocator.alloc(layout(33));
+        (p1 as usize - base, p2 as usize - base)
+    }
+}
+
+/// The layout of the occupied blocks separating the blocks of [`placement_trace`].
+fn separator_layout() -> Layout {
+    Layout::from_size_align(BLOCK_CONTENT_MIN_SIZE, HEADER_ALIGN).unwrap()
+}
+
+/// Returns the offset of the `n`th block of [`placement_trace`] from the first block.
+fn placement_offset(n: usize) -> usize {
+    let separator_size = augment_layout(separator_layout()).unwrap().size();
+    let offsets = [0, 42 * HEADER_SIZE, 78 * HEADER_SIZE, 116 * HEADER_SIZE];
+    offsets[n] + n * separator_size
+}
+
+#[test]
+fn test_first_fit() {
+    assert_eq!(placement_trace(FirstFit), (0, placement_offset(3)));
+}
+
+#[test]
+fn test_next_fit() {
+    assert_eq!(placement_trace(NextFit), (0, placement_offset(1)));
+}
+
+#[test]
+fn test_best_fit() {
+    assert_eq!(
+        placement_trace(BestFit),
+        (placement_offset(1), placement_offset(3))
+    );
+}
+
+#[test]
+fn test_next_fit_wraps_around() {
+    const BUF_SIZE: usize = 64 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower_and_placement(grower, NextFit) };
+
+    let layout = Layout::from_size_align(4 * HEADER_SIZE, HEADER_ALIGN).unwrap();
+    unsafe {
+        // Every object is followed by an occupied block so that freed blocks don't get coalesced.
+        let separator = Layout::from_size_align(BLOCK_CONTENT_MIN_SIZE, 1).unwrap();
+        let p1 = allocator.alloc(layout);
+        assert!(!allocator.alloc(separator).is_null());
+        let p2 = allocator.alloc(layout);
+        assert!(!allocator.alloc(separator).is_null());
+        let p3 = allocator.alloc(layout);
+        assert!(!allocator.alloc(separator).is_null());
+        allocator.dealloc(p3, layout);
+        allocator.dealloc(p1, layout);
+        // The freelist is p1 -> p3, after placing into p1 the rover points to p3.
+        assert_eq!(allocator.alloc(layout), p1);
+        allocator.dealloc(p2, layout);
+        // The rover still points to p3 even though p2 is now at the front.
+        assert_eq!(allocator.alloc(layout), p3);
+        // Wrap around to p2.
+        assert_eq!(allocator.alloc(layout), p2);
+    }
+}
+
+/// Runs [`validated_workload_on`] against an allocator over an arena.
+fn validated_workload<P: Placement>(placement: P, quarantine_budget: usize) {
+    const BUF_SIZE: usize = 256 * 1024;
+    let mut buf = vec![0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower_and_placement(grower, placement) };
+    validated_workload_on(&allocator, quarantine_budget);
+}
+
+/// Runs a random sequence of allocations, reallocations, deallocations and trims,
+/// validating the heap after each of them.
+fn validated_workload_on<T: Grower, P: Placement>(
+    allocator: &RawMalloc<T, P>,
+    quarantine_budget: usize,
+) {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    allocator.set_quarantine_budget(quarantine_budget);
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let mut objects: Vec<(*mut u8, Layout)> = vec![];
+    for _ in 0..2000 {
+        unsafe {
+            match rng.gen_range(0..10) {
+                0..=4 => {
+                    let size = rng.gen_range(1..512);
+                    let align = 1 << rng.gen_range(0..6);
+                    let layout = Layout::from_size_align(size, align).unwrap();
+                    let p = allocator.alloc(layout);
+                    if !p.is_null() {
+                        objects.push((p, layout));
+                    }
+                }
+                5..=7 if !objects.is_empty() => {
+                    let (p, layout) = objects.swap_remove(rng.gen_range(0..objects.len()));
+                    allocator.dealloc(p, layout);
+                }
+                8 if !objects.is_empty() => {
+                    let i = rng.gen_range(0..objects.len());
+                    let (p, layout) = objects[i];
+                    let new_size = rng.gen_range(1..1024);
+                    let new_p = allocator.realloc(p, layout, new_size);
+                    if !new_p.is_null() {
+                        objects[i] =
+                            (new_p, Layout::from_size_align(new_size, layout.align()).unwrap());
+                    }
+                }
+                _ => {
+                    allocator.trim(rng.gen_range(0..256));
+                }
+            }
+        }
+        assert_eq!(allocator.validate(), Ok(()));
+        check_stats(allocator, &objects);
+    }
+
+    while let Some((p, layout)) = objects.pop() {
+        unsafe { allocator.dealloc(p, layout) };
+        assert_eq!(allocator.validate(), Ok(()));
+        check_stats(allocator, &objects);
+    }
+    assert!(allocator.quarantined_bytes() <= quarantine_budget);
+
+    allocator.set_quarantine_budget(0);
+    assert_eq!(allocator.quarantined_bytes(), 0);
+    assert_eq!(allocator.validate(), Ok(()));
+    check_stats(allocator, &objects);
+    assert_eq!(allocator.stats().in_use_bytes, 0);
+}
+
+/// Checks the allocator's statistics against a walk of its heap.
+fn check_stats<T: Grower, P: Placement>(allocator: &RawMalloc<T, P>, objects: &[(*mut u8, Layout)]) {
+    let stats = allocator.stats();
+    let (mut block_count, mut in_use, mut free) = (0, 0, 0);
+    for block in allocator.blocks() {
+        block_count += 1;
+        match block.is_free {
+            true => free += block.content_size,
+            false => in_use += block.content_size,
+        }
+    }
+    let requested: usize = objects.iter().map(|(_, layout)| layout.size()).sum();
+    let padding: usize = objects
+        .iter()
+        .map(|&(p, layout)| unsafe {
+            let obj_size = augment_layout(layout).unwrap().size();
+            util::content_size(object_contents(p, "check_stats")) - obj_size
+        })
+        .sum();
+
+    assert_eq!(stats.requested_bytes, requested);
+    assert_eq!(stats.in_use_bytes, in_use);
+    assert_eq!(stats.padding_bytes, padding);
+    let region_headers = allocator.region_count().saturating_sub(1);
+    assert_eq!(
+        stats.header_bytes,
+        block_count * HEADER_SIZE + region_headers * REGION_HEADER_SIZE
+    );
+    assert_eq!(stats.free_bytes, free);
+    assert_eq!(stats.heap_bytes, stats.grown_bytes - stats.released_bytes);
+    assert_eq!(
+        stats.heap_bytes,
+        stats.in_use_bytes + stats.header_bytes + stats.free_bytes
+    );
+
+    let fragmentation = allocator.fragmentation();
+    assert_eq!(fragmentation.free_bytes, stats.free_bytes);
+    assert!(fragmentation.unmerged_free_blocks < fragmentation.free_blocks.max(1));
+    assert_eq!(fragmentation.histogram.iter().sum::<usize>(), fragmentation.free_blocks);
+    assert_eq!(allocator.largest_free_block(), fragmentation.largest_free_block);
+}
+
+#[test]
+fn test_validate_first_fit() {
+    validated_workload(FirstFit, 0);
+}
+
+#[test]
+fn test_validate_next_fit() {
+    validated_workload(NextFit, 0);
+}
+
+#[test]
+fn test_validate_best_fit() {
+    validated_workload(BestFit, 0);
+}
+
+#[test]
+fn test_validate_quarantine() {
+    validated_workload(FirstFit, 4096);
+}
+
+#[test]
+fn test_validate_regions() {
+    let mut buf = vec![0_u8; 4096];
+    // Small regions so that the heap keeps moving to new ones.
+    let grower = ChainGrower::new(ArenaGrower::new(&mut buf, 0), 4096);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    validated_workload_on(&allocator, 4096);
+    assert!(allocator.region_count() > 2);
+}
+
+#[test]
+fn test_validate_corruption() {
+    const BUF_SIZE: usize = 64 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.alloc(layout);
+        let p3 = allocator.alloc(layout);
+        let p4 = allocator.alloc(layout);
+        let p5 = allocator.alloc(layout);
+        // Freeing an object overwrites its left redzone with a freelist node.
+        let obj_starts = [p1, p2, p3, p4].map(|p| object_contents(p, "test_validate_corruption"));
+        allocator.dealloc(p2, layout);
+        assert_eq!(allocator.validate(), Ok(()));
+
+        let content_size = augment_layout(layout).unwrap().size();
+        let [p1_header, p2_header, p3_header, _] =
+            obj_starts.map(|p| p.sub(HEADER_SIZE).cast::<Header>());
+        let block = p2_header.cast_const().cast();
+
+        if FOOTERS {
+            // Breaking the footer of p2's block.
+            let footer: *mut Header = p3_header.sub(1);
+            let saved = *footer;
+            (*footer).__content_size += HEADER_SIZE;
+            assert_eq!(
+                allocator.validate(),
+                Err(ValidationError::FooterMismatch { block })
+            );
+            *footer = saved;
+        }
+
+        // Marking p2's block as occupied.
+        *p2_header = (*p2_header).untagged();
+        if cfg!(feature = "redzones") {
+            // The freelist node overwrote the redzones of the supposed object.
+            assert!(matches!(
+                allocator.validate(),
+                Err(ValidationError::RedzoneOverwritten { block: b, .. }) if b == block
+            ));
+        } else {
+            if FOOTERS {
+                assert_eq!(
+                    allocator.validate(),
+                    Err(ValidationError::PrevFreeMismatch {
+                        block: p3_header.cast_const().cast()
+                    })
+                );
+                *p3_header = (*p3_header).with_prev_free(false);
+            }
+            assert_eq!(
+                allocator.validate(),
+                Err(ValidationError::NodeNotFree { block })
+            );
+        }
+        *p2_header = (*p2_header).tagged();
+        *p3_header = (*p3_header).with_prev_free(FOOTERS);
+        assert_eq!(allocator.validate(), Ok(()));
+
+        // Replacing the node of p2's block by a node inside of p1's block,
+        // which is made to look like a free block of the same size.
+        allocator.dealloc(p4, layout);
+        assert_eq!(allocator.validate(), Ok(()));
+        let [_, p2_node, _, p4_node] = obj_starts.map(|p| p.cast::<Node>());
+        assert_eq!((*p4_node).next, p2_node);
+        let fake_header: *mut Header = p1.cast();
+        let fake_node: *mut Node = p1.add(HEADER_SIZE).cast();
+        *fake_header = *p2_header;
+        *fake_node = Node {
+            next: (*p2_node).next,
+            prev: p4_node,
+        };
+        (*p4_node).next = fake_node;
+        assert_eq!(
+            allocator.validate(),
+            Err(ValidationError::FreeBlockNotListed { block })
+        );
+        (*p4_node).next = p2_node;
+        allocator.dealloc(p5, layout);
+        assert_eq!(allocator.validate(), Ok(()));
+
+        // Breaking the size of p1's block.
+        (*p1_header).__content_size += HEADER_SIZE / 2;
+        assert_eq!(
+            allocator.validate(),
+            Err(ValidationError::InvalidContentSize {
+                block: p1_header.cast_const().cast(),
+                content_size: content_size + HEADER_SIZE / 2
+            })
+        );
+    }
+}
+
+#[test]
+fn test_blocks() {
+    const BUF_SIZE: usize = 64 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    assert_eq!(allocator.blocks().count(), 0);
+
+    let layout_1 = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    let layout_2 = Layout::from_size_align(HEADER_SIZE * 6, HEADER_ALIGN).unwrap();
+    unsafe {
+        let p1 = allocator.alloc(layout_1);
+        let p2 = allocator.alloc(layout_2);
+        let p3 = allocator.alloc(layout_1);
+        let [o1, o2, o3] = [p1, p2, p3].map(|p| object_contents(p, "test_blocks"));
+        allocator.dealloc(p2, layout_2);
+
+        let blocks: Vec<_> = allocator.blocks().collect();
+        let expected = [(o1, layout_1, false), (o2, layout_2, true), (o3, layout_1, false)];
+        assert_eq!(blocks.len(), expected.len());
+        for (block, (p, layout, is_free)) in blocks.iter().zip(expected) {
+            assert_eq!(block.content_start(), p.cast_const());
+            assert_eq!(block.content_size, augment_layout(layout).unwrap().size());
+            assert_eq!(block.is_free, is_free);
+        }
+        assert_eq!(
+            blocks.iter().map(Block::size).sum::<usize>(),
+            allocator.heap_end().unwrap().as_ptr() as usize - blocks[0].start as usize
+        );
+    }
+}
+
+#[test]
+fn test_stats() {
+    const BUF_SIZE: usize = 64 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    assert_eq!(allocator.stats(), Stats::default());
+
+    let layout_1 = Layout::from_size_align(5, 1).unwrap();
+    let layout_2 = Layout::from_size_align(HEADER_SIZE * 6, HEADER_ALIGN).unwrap();
+    let [size_1, size_2] = [layout_1, layout_2].map(|l| augment_layout(l).unwrap().size());
+    unsafe {
+        let p1 = allocator.alloc(layout_1);
+        let p2 = allocator.alloc(layout_2);
+        let stats = allocator.stats();
+        assert_eq!(stats.requested_bytes, 5 + HEADER_SIZE * 6);
+        assert_eq!(stats.in_use_bytes, size_1 + size_2);
+        // Augmenting the layout isn't padding.
+        assert_eq!(stats.padding_bytes, 0);
+        assert_eq!(stats.header_bytes, 2 * HEADER_SIZE);
+        assert_eq!(stats.free_bytes, 0);
+        assert_eq!(stats.grow_calls, 2);
+        assert_eq!(stats.grown_bytes, size_1 + size_2 + HEADER_SIZE * 2);
+
+        // A failed growth is still counted as a grower call.
+        let too_big = Layout::from_size_align(BUF_SIZE, HEADER_ALIGN).unwrap();
+        assert!(allocator.alloc(too_big).is_null());
+        assert_eq!(allocator.stats().grow_calls, 3);
+
+        allocator.dealloc(p2, layout_2);
+        let stats = allocator.stats();
+        assert_eq!(stats.requested_bytes, 5);
+        assert_eq!(stats.free_bytes, size_2);
+
+        // The rest of the free block is too small for a block of its own.
+        let layout_3 = Layout::from_size_align(HEADER_SIZE * 5, HEADER_ALIGN).unwrap();
+        let p3 = allocator.alloc(layout_3);
+        assert_eq!(p3, p2);
+        let stats = allocator.stats();
+        assert_eq!(stats.padding_bytes, HEADER_SIZE);
+        assert_eq!(stats.in_use_bytes, size_1 + size_2);
+        allocator.dealloc(p3, layout_3);
+        assert_eq!(allocator.stats().padding_bytes, 0);
+
+        allocator.dealloc(p1, layout_1);
+        let stats = allocator.stats();
+        // Without footers the freed blocks aren't coalesced.
+        let blocks = if FOOTERS { 1 } else { 2 };
+        assert_eq!(stats.requested_bytes, 0);
+        assert_eq!(stats.in_use_bytes, 0);
+        assert_eq!(stats.header_bytes, blocks * HEADER_SIZE);
+        assert_eq!(stats.free_bytes, stats.heap_bytes - blocks * HEADER_SIZE);
+
+        allocator.trim(0);
+        let stats = allocator.stats();
+        assert_eq!(stats.heap_bytes, 0);
+        assert_eq!(stats.header_bytes, 0);
+        assert_eq!(stats.released_bytes, stats.grown_bytes);
+    }
+}
+
+#[test]
+fn test_fragmentation() {
+    const BUF_SIZE: usize = 64 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    assert_eq!(allocator.fragmentation(), Fragmentation::default());
+    assert_eq!(allocator.fragmentation().external(), 0.0);
+    assert_eq!(allocator.largest_free_block(), 0);
+
+    let layout_1 = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    let layout_2 = Layout::from_size_align(HEADER_SIZE * 12, HEADER_ALIGN).unwrap();
+    unsafe {
+        let p1 = allocator.alloc(layout_1);
+        let p2 = allocator.alloc(layout_1);
+        let p3 = allocator.alloc(layout_2);
+        let _p4 = allocator.alloc(layout_1);
+        allocator.dealloc(p1, layout_1);
+        allocator.dealloc(p3, layout_2);
+
+        let [size_1, size_2] = [layout_1, layout_2].map(|l| augment_layout(l).unwrap().size());
+        let fragmentation = allocator.fragmentation();
+        assert_eq!(fragmentation.free_blocks, 2);
+        assert_eq!(fragmentation.free_bytes, size_1 + size_2);
+        assert_eq!(fragmentation.largest_free_block, size_2);
+        assert_eq!(fragmentation.unmerged_free_blocks, 0);
+        assert_eq!(
+            fragmentation.external(),
+            1.0 - size_2 as f64 / (size_1 + size_2) as f64
+        );
+        assert_eq!(fragmentation.histogram[size_1.ilog2() as usize], 1);
+        assert_eq!(fragmentation.histogram[size_2.ilog2() as usize], 1);
+        assert_eq!(allocator.largest_free_block(), size_2);
+
+        // Without footers the freed block isn't coalesced with its free neighbours.
+        allocator.dealloc(p2, layout_1);
+        let fragmentation = allocator.fragmentation();
+        let unmerged = if FOOTERS { 0 } else { 2 };
+        assert_eq!(fragmentation.unmerged_free_blocks, unmerged);
+        assert_eq!(fragmentation.free_blocks, 1 + unmerged);
+    }
+
+    // Free blocks on both sides of a region end can never be merged.
+    let mut buf = vec![0_u8; 4096];
+    let grower = ChainGrower::new(ArenaGrower::new(&mut buf, 0), 1 << 16);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    let layout = Layout::from_size_align(1536, HEADER_ALIGN).unwrap();
+    unsafe {
+        let objects = [0; 4].map(|_| allocator.alloc(layout));
+        assert_eq!(allocator.region_count(), 2);
+        allocator.dealloc(objects[1], layout);
+        allocator.dealloc(objects[2], layout);
+        // The region of the third object was grown with some room to spare,
+        // which stays free between it and the fourth object.
+        let fragmentation = allocator.fragmentation();
+        let unmerged = if FOOTERS { 0 } else { 1 };
+        assert_eq!(fragmentation.unmerged_free_blocks, unmerged);
+        assert_eq!(fragmentation.free_blocks, 2 + unmerged);
+    }
+}
+
+#[test]
+fn test_check_object() {
+    use super::hardened::InvalidObject;
+    use super::redzones::object_contents;
+
+    const BUF_SIZE: usize = 64 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 4, HEADER_ALIGN).unwrap();
+    unsafe {
+        let ptrs = [0; 3].map(|_| allocator.alloc(layout));
+        let [p1, p2, p3] = ptrs.map(|p| object_contents(p, "check_object"));
+        for p in [p1, p2, p3] {
+            assert_eq!(allocator.check_object(p), Ok(()));
+        }
+        assert_eq!(allocator.check_object(p1.add(1)), Err(InvalidObject::Misaligned));
+        let heap_end = allocator.heap_end().unwrap().as_ptr();
+        assert_eq!(allocator.check_object(heap_end), Err(InvalidObject::OutsideHeap));
+        assert_eq!(
+            allocator.check_object(core::ptr::null()),
+            Err(InvalidObject::OutsideHeap)
+        );
+
+        allocator.dealloc(ptrs[0], layout);
+        assert_eq!(allocator.check_object(p1), Err(InvalidObject::DoubleFree));
+
+        // With footers the header of p2 is left inside the coalesced block untagged.
+        allocator.dealloc(ptrs[1], layout);
+        assert_eq!((*p2.sub(HEADER_SIZE).cast::<Header>()).is_tagged(), !FOOTERS);
+        assert_eq!(allocator.check_object(p2), Err(InvalidObject::DoubleFree));
+        assert_eq!(allocator.check_object(p3), Ok(()));
+    }
+}
+
+#[test]
+fn test_allocated_layout() {
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(40, 16).unwrap();
+    let recorded = |layout| {
+        cfg!(any(feature = "hardened", feature = "redzones", feature = "cabi")).then_some(layout)
+    };
+    unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.alloc(layout);
+        assert_eq!(RawMalloc::<ArenaGrower>::allocated_layout(p1), recorded(layout));
+        assert!(RawMalloc::<ArenaGrower>::usable_size(p1) >= layout.size());
+
+        // Grown in place and moved.
+        let p2 = allocator.realloc(p2, layout, 100);
+        let new_layout = Layout::from_size_align(100, 16).unwrap();
+        assert_eq!(RawMalloc::<ArenaGrower>::allocated_layout(p2), recorded(new_layout));
+        let p1 = allocator.realloc(p1, layout, 200);
+        let new_layout = Layout::from_size_align(200, 16).unwrap();
+        assert_eq!(RawMalloc::<ArenaGrower>::allocated_layout(p1), recorded(new_layout));
+        assert_eq!(allocator.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn test_redzones() {
+    use super::redzones::{check_redzones, object_contents, REDZONE_BYTE};
+
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 3 + 1, 32).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        assert_eq!(p as usize % layout.align(), 0);
+        let obj_start = object_contents(p, "test_redzones");
+        assert_eq!(check_redzones(obj_start), Ok(()));
+        assert_eq!(allocator.validate(), Ok(()));
+
+        if cfg!(feature = "redzones") {
+            let block = obj_start.sub(HEADER_SIZE).cast_const();
+            let content_size = (*block.cast::<Header>()).content_size();
+            for (byte, side) in [
+                (p.add(layout.size()), RedzoneSide::Right),
+                (p.sub(1), RedzoneSide::Left),
+                (obj_start, RedzoneSide::Left),
+            ] {
+                *byte = 0;
+                assert_eq!(check_redzones(obj_start), Err(side));
+                assert_eq!(
+                    allocator.validate(),
+                    Err(ValidationError::RedzoneOverwritten {
+                        block,
+                        content_size,
+                        side
+                    })
+                );
+                *byte = REDZONE_BYTE;
+            }
+        }
+
+        // The right redzone moves along with the end of the object.
+        let p = allocator.realloc(p, layout, HEADER_SIZE * 8);
+        assert_eq!(allocator.validate(), Ok(()));
+        let layout = Layout::from_size_align(HEADER_SIZE * 8, 32).unwrap();
+        let p = allocator.realloc(p, layout, HEADER_SIZE * 2 + 5);
+        assert_eq!(allocator.validate(), Ok(()));
+        let layout = Layout::from_size_align(HEADER_SIZE * 2 + 5, 32).unwrap();
+        allocator.dealloc(p, layout);
+        assert_eq!(allocator.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn test_quarantine() {
+    use super::quarantine::POISON_BYTE;
+
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(HEADER_SIZE * 8, HEADER_ALIGN).unwrap();
+    let block_size = HEADER_SIZE + augment_layout(layout).unwrap().size();
+    allocator.set_quarantine_budget(2 * block_size);
+    unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.alloc(layout);
+        let contents_1 = object_contents(p1, "test_quarantine");
+        allocator.dealloc(p1, layout);
+        assert_eq!(allocator.quarantined_bytes(), block_size);
+        assert_eq!(*p1.add(layout.size() - 1), POISON_BYTE);
+
+        // Quarantined blocks aren't reused.
+        let p3 = allocator.alloc(layout);
+        assert_ne!(p3, p1);
+        allocator.dealloc(p2, layout);
+        assert_eq!(allocator.quarantined_bytes(), 2 * block_size);
+        assert_eq!(allocator.stats().requested_bytes, layout.size());
+        assert_eq!(allocator.validate(), Ok(()));
+
+        // A use after free is caught before the block leaves the quarantine.
+        *p1.add(layout.size() - 1) = 0;
+        assert!(matches!(
+            allocator.validate(),
+            Err(ValidationError::PoisonOverwritten { .. })
+        ));
+        *p1.add(layout.size() - 1) = POISON_BYTE;
+
+        // Any word past the link is checked, the block can't be made to look like a live object.
+        let word = contents_1.add(HEADER_SIZE).cast::<usize>();
+        *word = 0;
+        assert!(matches!(
+            allocator.validate(),
+            Err(ValidationError::PoisonOverwritten { .. })
+        ));
+        *word = usize::from_ne_bytes([POISON_BYTE; HEADER_SIZE]);
+
+        // Freeing another object releases the oldest one, which is then reused.
+        allocator.dealloc(p3, layout);
+        assert_eq!(allocator.quarantined_bytes(), 2 * block_size);
+        let p4 = allocator.alloc(layout);
+        assert_eq!(p4, p1);
+        assert_eq!(allocator.validate(), Ok(()));
+
+        allocator.set_quarantine_budget(0);
+        assert_eq!(allocator.quarantined_bytes(), 0);
+        allocator.dealloc(p4, layout);
+        assert_eq!(allocator.stats().in_use_bytes, 0);
+        assert_eq!(allocator.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn test_leak_report() {
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    assert!(allocator.leak_report().is_empty());
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let large = Layout::from_size_align(100, 8).unwrap();
+    let size_of = |p| unsafe {
+        RawMalloc::<ArenaGrower>::allocated_layout(p)
+            .map_or_else(|| RawMalloc::<ArenaGrower>::usable_size(p), |layout| layout.size())
+    };
+    unsafe {
+        let small_objects = [0; 3].map(|_| allocator.alloc(small));
+        let p = allocator.alloc(large);
+        allocator.dealloc(small_objects[1], small);
+
+        let report = allocator.leak_report();
+        assert_eq!(report.objects, 3);
+        assert_eq!(report.bytes, 2 * size_of(small_objects[0]) + size_of(p));
+        let groups = report.groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!((groups[0].size, groups[0].objects), (size_of(small_objects[0]), 2));
+        assert_eq!((groups[1].size, groups[1].objects), (size_of(p), 1));
+        assert_eq!(groups[0].callsite.is_some(), cfg!(feature = "callsites"));
+        if let Some(callsite) = groups[0].callsite {
+            assert_ne!(groups[1].callsite, Some(callsite));
+            assert!(callsite.frames().count() > 1);
+        }
+
+        let message = format!("{report}");
+        assert!(message.starts_with("rusty_malloc: 3 live allocations"), "{message}");
+        // Quarantined objects aren't live.
+        allocator.set_quarantine_budget(1024);
+        allocator.dealloc(p, large);
+        assert_eq!(allocator.leak_report().objects, 2);
+    }
+}
+
+#[test]
+fn test_alloc_errors() {
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let too_large = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+    assert_eq!(
+        allocator.try_alloc_detailed(too_large),
+        Err(MallocError::LayoutTooLarge)
+    );
+    let huge = Layout::from_size_align(BUF_SIZE, 8).unwrap();
+    assert_eq!(
+        allocator.try_alloc_detailed(huge),
+        Err(MallocError::OutOfMemory)
+    );
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let p = allocator.try_alloc_detailed(layout).unwrap().as_ptr();
+    unsafe {
+        assert_eq!(
+            allocator.try_realloc_detailed(p, layout, BUF_SIZE),
+            Err(MallocError::OutOfMemory)
+        );
+        // The object stays valid after a failed reallocation.
+        allocator.dealloc(p, layout);
+    }
+    assert_eq!(allocator.validate(), Ok(()));
+}
+
+#[test]
+fn test_oom_handler() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static RETRIES: AtomicUsize = AtomicUsize::new(0);
+    fn retry_twice(failure: &AllocFailure) -> OomAction {
+        assert_eq!(failure.error, MallocError::OutOfMemory);
+        assert_eq!(failure.layout.size(), BUF_SIZE);
+        assert_eq!(failure.retries, RETRIES.fetch_add(1, Ordering::Relaxed));
+        match failure.retries {
+            0 | 1 => OomAction::Retry,
+            _ => OomAction::Fail,
+        }
+    }
+
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    allocator.set_oom_handler(Some(retry_twice));
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let huge = Layout::from_size_align(BUF_SIZE, 8).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        assert!(!p.is_null());
+        assert!(allocator.alloc(huge).is_null());
+        assert_eq!(RETRIES.load(Ordering::Relaxed), 3);
+
+        // Growing reallocations call the handler as well.
+        RETRIES.store(0, Ordering::Relaxed);
+        assert!(allocator.realloc(p, layout, BUF_SIZE).is_null());
+        assert_eq!(RETRIES.load(Ordering::Relaxed), 3);
+        allocator.dealloc(p, layout);
+    }
+    assert_eq!(allocator.validate(), Ok(()));
+}
+
+#[test]
+fn test_trace() {
+    use std::fs::File;
+    use std::io::{Seek, SeekFrom};
+    use std::os::fd::AsRawFd;
+
+    const BUF_SIZE: usize = 256 * HEADER_SIZE;
+    let mut buf = [0_u8; BUF_SIZE];
+    let grower = ArenaGrower::new(&mut buf, 0);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let path = std::env::temp_dir().join(format!("rusty_malloc_trace_{}", std::process::id()));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    allocator.set_trace_fd(Some(file.as_raw_fd()));
+
+    let layout = Layout::from_size_align(24, 16).unwrap();
+    let huge = Layout::from_size_align(BUF_SIZE, 8).unwrap();
+    let (p1, p2) = unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.realloc(p1, layout, 40);
+        assert!(allocator.alloc(huge).is_null());
+        allocator.dealloc(p2, Layout::from_size_align(40, 16).unwrap());
+        (p1 as usize, p2 as usize)
+    };
+    allocator.set_trace_fd(None);
+
+    if !cfg!(feature = "trace") {
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        return;
+    }
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let reader = TraceReader::new(&file).unwrap();
+    let records: Vec<TraceRecord> = reader.map(Result::unwrap).collect();
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| (r.op, r.align, r.ptr, r.size, r.new_ptr, r.new_size))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (TraceOp::Alloc, 16, p1, 24, 0, 0),
+            (TraceOp::Realloc, 16, p1, 24, p2, 40),
+            (TraceOp::Alloc, 8, 0, BUF_SIZE, 0, 0),
+            (TraceOp::Dealloc, 16, p2, 40, 0, 0),
+        ]
+    );
+    assert!(records.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+    assert!(records.iter().all(|r| r.thread == records[0].thread));
+    for record in &records {
+        assert_eq!(TraceRecord::from_bytes(&record.to_bytes()), Some(*record));
+    }
+}
+
+#[test]
+fn test_regions() {
+    let mut buf = vec![0_u8; 4096];
+    let arena = buf.as_ptr_range();
+    let grower = ChainGrower::new(ArenaGrower::new(&mut buf, 0), 1 << 16);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+    assert_eq!(allocator.region_count(), 0);
+
+    let layout = Layout::from_size_align(1536, HEADER_ALIGN).unwrap();
+    unsafe {
+        let p1 = allocator.alloc(layout);
+        let p2 = allocator.alloc(layout);
+        assert_eq!(allocator.region_count(), 1);
+        // The arena is exhausted, so the heap moves on to a mapped region.
+        let p3 = allocator.alloc(layout);
+        let p4 = allocator.alloc(layout);
+        assert_eq!(allocator.region_count(), 2);
+        assert!(arena.contains(&p2.cast_const()));
+        assert!(!arena.contains(&p3.cast_const()));
+        assert_eq!(allocator.validate(), Ok(()));
+        check_stats(
+            &allocator,
+            &[(p1, layout), (p2, layout), (p3, layout), (p4, layout)],
+        );
+
+        // Blocks on both sides of a region end are never merged.
+        allocator.dealloc(p2, layout);
+        allocator.dealloc(p3, layout);
+        let double_layout = Layout::from_size_align(2 * layout.size(), HEADER_ALIGN).unwrap();
+        let p5 = allocator.alloc(double_layout);
+        assert!(p5 != p2 && p5 != p3);
+        assert_eq!(allocator.validate(), Ok(()));
+
+        // Objects at the end of a region can't grow in place past it.
+        let p1 = allocator.realloc(p1, layout, 3 * layout.size());
+        assert!(!arena.contains(&p1.cast_const()));
+        let triple_layout = Layout::from_size_align(3 * layout.size(), HEADER_ALIGN).unwrap();
+        assert_eq!(allocator.validate(), Ok(()));
+
+        // Regions are at least as large as the objects that need them.
+        let huge_layout = Layout::from_size_align(1 << 17, HEADER_ALIGN).unwrap();
+        let p6 = allocator.alloc(huge_layout);
+        assert!(!p6.is_null());
+        assert_eq!(allocator.region_count(), 3);
+        assert_eq!(allocator.validate(), Ok(()));
+        let objects = [
+            (p1, triple_layout),
+            (p4, layout),
+            (p5, double_layout),
+            (p6, huge_layout),
+        ];
+        check_stats(&allocator, &objects);
+
+        for (p, layout) in objects {
+            allocator.dealloc(p, layout);
+        }
+        assert_eq!(allocator.validate(), Ok(()));
+        check_stats(&allocator, &[]);
+        assert!(allocator.blocks().all(|block| block.is_free));
+        // Only the last region can be trimmed.
+        assert!(allocator.trim(0) >= huge_layout.size());
+        assert_eq!(allocator.trim(0), 0);
+        assert_eq!(allocator.validate(), Ok(()));
+        check_stats(&allocator, &[]);
+    }
+}
+
+#[test]
+fn test_region_kept_on_limit() {
+    let mut buf = vec![0_u8; 4096];
+    let arena = buf.as_ptr_range();
+    let limit = AtomicUsize::new(2048);
+    let primary = LimitGrower::new(ArenaGrower::new(&mut buf, 0), &limit);
+    let allocator = unsafe { RawMalloc::with_grower(ChainGrower::new(primary, 1 << 16)) };
+
+    unsafe {
+        // Exceeding the limit of the primary grower doesn't move the heap to a new region.
+        let large = Layout::from_size_align(3072, HEADER_ALIGN).unwrap();
+        assert_eq!(
+            allocator.try_alloc_detailed(large),
+            Err(MallocError::LimitExceeded)
+        );
+        assert_eq!(allocator.region_count(), 0);
+
+        let small = Layout::from_size_align(64, HEADER_ALIGN).unwrap();
+        let p = allocator.alloc(small);
+        assert!(arena.contains(&p.cast_const()));
+        assert_eq!(allocator.region_count(), 1);
+        assert_eq!(allocator.validate(), Ok(()));
+        allocator.dealloc(p, small);
+        check_stats(&allocator, &[]);
+    }
+}
+
+#[test]
+fn test_region_fenceposts() {
+    let mut buf = vec![0_u8; 4096];
+    let grower = ChainGrower::new(ArenaGrower::new(&mut buf, 0), 1 << 16);
+    let allocator = unsafe { RawMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(1536, HEADER_ALIGN).unwrap();
+    unsafe {
+        let objects = [0; 5].map(|_| allocator.alloc(layout));
+        let huge_layout = Layout::from_size_align(1 << 17, HEADER_ALIGN).unwrap();
+        let huge = allocator.alloc(huge_layout);
+        assert_eq!(allocator.region_count(), 3);
+
+        let regions: Vec<_> = allocator.regions().collect();
+        for &(start, end) in &regions[..2] {
+            let fencepost: *mut Header = end.cast();
+            assert_eq!(*fencepost, FENCEPOST);
+            assert!(allocator.is_region_end(end));
+            assert_eq!(allocator.region_of(start), Some((start, end)));
+            assert_eq!(allocator.region_of(end.sub(1)), Some((start, end)));
+        }
+        assert_eq!(allocator.region_of(huge), Some(regions[2]));
+        assert_eq!(allocator.region_of(regions[2].1), None);
+
+        let fencepost: *mut Header = regions[0].1.cast();
+        *fencepost = Header::new_unchecked(HEADER_SIZE, false);
+        assert_eq!(
+            allocator.validate(),
+            Err(ValidationError::FencepostOverwritten {
+                end: regions[0].1.cast_const()
+            })
+        );
+        *fencepost = FENCEPOST;
+
+        // Objects next to a fencepost are freed and merged without crossing it.
+        for p in objects {
+            allocator.dealloc(p, layout);
+        }
+        allocator.dealloc(huge, huge_layout);
+        assert_eq!(allocator.validate(), Ok(()));
+        assert_eq!(*fencepost, FENCEPOST);
+        check_stats(&allocator, &[]);
+    }
+}
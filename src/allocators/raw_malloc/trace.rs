@@ -0,0 +1,316 @@
+//! Binary allocation traces, recorded when the `trace` feature is enabled,
+//! see [`RawMalloc::set_trace_fd`].
+//!
+//! A trace starts with a [`TRACE_HEADER_SIZE`]-byte header:
+//! ```text
+//! | magic: "RMTRACE\0" | version: u32 | record size: u32 |
+//! ```
+//! followed by [`TRACE_RECORD_SIZE`]-byte records:
+//! ```text
+//! | op: u8 | log2(align): u8 | 0: u16 | thread: u32 | timestamp: u64 |
+//! | ptr: u64 | size: u64 | new ptr: u64 | new size: u64 |
+//! ```
+//! All integers are little-endian, see [`TraceRecord`] for the meaning of the fields.
+//! Records are buffered in the allocator and written with a single `write` per buffer,
+//! so recording never allocates.
+
+use super::placement::Placement;
+use super::RawMalloc;
+use crate::growers::Grower;
+
+use core::alloc::Layout;
+use core::cell::{Cell, UnsafeCell};
+use core::mem::MaybeUninit;
+use std::io::{self, Read};
+use std::os::fd::RawFd;
+
+/// The magic bytes a trace starts with.
+pub const TRACE_MAGIC: [u8; 8] = *b"RMTRACE\0";
+
+/// The version of the trace format.
+pub const TRACE_VERSION: u32 = 1;
+
+/// The size of the trace header.
+pub const TRACE_HEADER_SIZE: usize = 16;
+
+/// The size of a single trace record.
+pub const TRACE_RECORD_SIZE: usize = 48;
+
+/// The number of records buffered by each allocator, 0 if the `trace` feature is disabled.
+const TRACE_BUFFER_RECORDS: usize = match cfg!(feature = "trace") {
+    true => 64,
+    false => 0,
+};
+
+thread_local! {
+    // The id of the current thread, 0 until it's first traced.
+    static THREAD_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+/// The operation of a [`TraceRecord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum TraceOp {
+    /// An allocation, including those of [`Allocator::allocate`](core::alloc::Allocator::allocate).
+    Alloc = 1,
+    /// A deallocation, including those of
+    /// [`Allocator::deallocate`](core::alloc::Allocator::deallocate).
+    Dealloc = 2,
+    /// A reallocation, including the grows and shrinks of
+    /// [`Allocator`](core::alloc::Allocator).
+    Realloc = 3,
+}
+
+/// A single allocator operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceRecord {
+    /// The operation.
+    pub op: TraceOp,
+    /// The alignment of the object.
+    pub align: usize,
+    /// The kernel id of the thread that performed the operation.
+    pub thread: u32,
+    /// The time of the operation in nanoseconds, as given by `CLOCK_MONOTONIC`.
+    pub timestamp: u64,
+    /// The returned pointer for allocations and the freed or reallocated pointer otherwise.
+    /// Failed allocations have a null pointer.
+    pub ptr: usize,
+    /// The size of the object, that is the old size for reallocations.
+    pub size: usize,
+    /// The returned pointer for reallocations, null if the reallocation failed.
+    /// Always null for other operations.
+    pub new_ptr: usize,
+    /// The new size for reallocations, 0 for other operations.
+    pub new_size: usize,
+}
+
+impl TraceRecord {
+    /// Encodes the record.
+    pub fn to_bytes(&self) -> [u8; TRACE_RECORD_SIZE] {
+        let mut bytes = [0; TRACE_RECORD_SIZE];
+        bytes[0] = self.op as u8;
+        bytes[1] = self.align.trailing_zeros() as u8;
+        bytes[4..8].copy_from_slice(&self.thread.to_le_bytes());
+        let words = [self.ptr, self.size, self.new_ptr, self.new_size];
+        bytes[8..16].copy_from_slice(&self.timestamp.to_le_bytes());
+        for (chunk, word) in bytes[16..].chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&(word as u64).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes a record or returns `None` if `bytes` isn't a valid record.
+    pub fn from_bytes(bytes: &[u8; TRACE_RECORD_SIZE]) -> Option<Self> {
+        let op = match bytes[0] {
+            1 => TraceOp::Alloc,
+            2 => TraceOp::Dealloc,
+            3 => TraceOp::Realloc,
+            _ => return None,
+        };
+        let align = 1_usize.checked_shl(bytes[1] as u32)?;
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let usize_at = |i: usize| usize::try_from(u64_at(i)).ok();
+        Some(TraceRecord {
+            op,
+            align,
+            thread: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            timestamp: u64_at(8),
+            ptr: usize_at(16)?,
+            size: usize_at(24)?,
+            new_ptr: usize_at(32)?,
+            new_size: usize_at(40)?,
+        })
+    }
+
+    /// Returns the layout of the object, that is its old layout for reallocations
+    /// or `None` if the size and alignment don't form a valid layout.
+    pub fn layout(&self) -> Option<Layout> {
+        Layout::from_size_align(self.size, self.align).ok()
+    }
+}
+
+/// Returns the header of traces.
+pub fn trace_header() -> [u8; TRACE_HEADER_SIZE] {
+    let mut header = [0; TRACE_HEADER_SIZE];
+    header[..8].copy_from_slice(&TRACE_MAGIC);
+    header[8..12].copy_from_slice(&TRACE_VERSION.to_le_bytes());
+    header[12..].copy_from_slice(&(TRACE_RECORD_SIZE as u32).to_le_bytes());
+    header
+}
+
+/// An iterator over the records of a trace.
+#[derive(Debug)]
+pub struct TraceReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Reads the trace header from `reader`,
+    /// failing with [`io::ErrorKind::InvalidData`] if it isn't a supported trace.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; TRACE_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if header != trace_header() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a rusty_malloc trace of a supported version",
+            ));
+        }
+        Ok(TraceReader { reader })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; TRACE_RECORD_SIZE];
+        let mut len = 0;
+        while len < TRACE_RECORD_SIZE {
+            match self.reader.read(&mut bytes[len..]) {
+                Ok(0) if len == 0 => return None,
+                Ok(0) => return Some(Err(io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(
+            TraceRecord::from_bytes(&bytes)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid trace record")),
+        )
+    }
+}
+
+/// The buffered records of an allocator and the file descriptor they are written to.
+pub(super) struct Tracer {
+    // -1 if tracing is disabled.
+    fd: Cell<RawFd>,
+    buf: UnsafeCell<[u8; TRACE_BUFFER_RECORDS * TRACE_RECORD_SIZE]>,
+    len: Cell<usize>,
+}
+
+impl Tracer {
+    pub const fn new() -> Self {
+        Tracer {
+            fd: Cell::new(-1),
+            buf: UnsafeCell::new([0; TRACE_BUFFER_RECORDS * TRACE_RECORD_SIZE]),
+            len: Cell::new(0),
+        }
+    }
+}
+
+/// Writes all of `bytes` to `fd`, giving up on errors other than interruptions.
+fn write_all(fd: RawFd, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        match unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) } {
+            n if n > 0 => bytes = &bytes[n as usize..],
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => return,
+        }
+    }
+}
+
+/// Returns the kernel id of the current thread.
+fn thread_id() -> u32 {
+    let gettid = || unsafe { libc::gettid() } as u32;
+    THREAD_ID
+        .try_with(|id| {
+            if id.get() == 0 {
+                id.set(gettid());
+            }
+            id.get()
+        })
+        .unwrap_or_else(|_| gettid())
+}
+
+/// Returns the current `CLOCK_MONOTONIC` time in nanoseconds.
+fn timestamp() -> u64 {
+    let mut time = MaybeUninit::<libc::timespec>::uninit();
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, time.as_mut_ptr()) };
+    let time = unsafe { time.assume_init() };
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Starts recording every allocation, deallocation and reallocation of the allocator
+    /// to `fd`, which has to stay open until tracing is stopped. The trace header is written
+    /// to `fd` right away. `None` stops tracing, after writing any buffered records.
+    ///
+    /// Records are buffered and only written once the buffer is full,
+    /// see [`flush_trace`](RawMalloc::flush_trace). Write errors are ignored,
+    /// dropping the records that couldn't be written.
+    /// This has no effect unless the `trace` feature is enabled.
+    ///
+    /// See the [`trace`](self) module for the format of the trace.
+    pub fn set_trace_fd(&self, fd: Option<RawFd>) {
+        self.flush_trace();
+        if let (Some(fd), true) = (fd, TRACE_BUFFER_RECORDS != 0) {
+            write_all(fd, &trace_header());
+        }
+        self.trace_to(fd);
+    }
+
+    /// Like [`set_trace_fd`](RawMalloc::set_trace_fd) but doesn't write the trace header,
+    /// for heaps sharing a trace.
+    pub(crate) fn trace_to(&self, fd: Option<RawFd>) {
+        if TRACE_BUFFER_RECORDS == 0 {
+            return;
+        }
+        self.flush_trace();
+        self.tracer.fd.set(fd.unwrap_or(-1));
+    }
+
+    /// Returns the file descriptor the allocator is traced to, if any.
+    pub fn trace_fd(&self) -> Option<RawFd> {
+        Some(self.tracer.fd.get()).filter(|&fd| fd >= 0)
+    }
+
+    /// Writes the buffered trace records.
+    pub fn flush_trace(&self) {
+        let tracer = &self.tracer;
+        let len = tracer.len.get();
+        if len != 0 {
+            let buf = unsafe { &*tracer.buf.get() };
+            write_all(tracer.fd.get(), &buf[..len * TRACE_RECORD_SIZE]);
+            tracer.len.set(0);
+        }
+    }
+
+    /// Records an operation if tracing is enabled.
+    #[inline(always)]
+    pub(crate) fn trace(
+        &self,
+        op: TraceOp,
+        layout: Layout,
+        ptr: *const u8,
+        new_ptr: *const u8,
+        new_size: usize,
+    ) {
+        if TRACE_BUFFER_RECORDS != 0 && self.tracer.fd.get() >= 0 {
+            self.push_trace_record(TraceRecord {
+                op,
+                align: layout.align(),
+                thread: thread_id(),
+                timestamp: timestamp(),
+                ptr: ptr as usize,
+                size: layout.size(),
+                new_ptr: new_ptr as usize,
+                new_size,
+            });
+        }
+    }
+
+    #[inline(never)]
+    fn push_trace_record(&self, record: TraceRecord) {
+        let tracer = &self.tracer;
+        let len = tracer.len.get();
+        let buf = unsafe { &mut *tracer.buf.get() };
+        buf[len * TRACE_RECORD_SIZE..(len + 1) * TRACE_RECORD_SIZE]
+            .copy_from_slice(&record.to_bytes());
+        tracer.len.set(len + 1);
+        if len + 1 == TRACE_BUFFER_RECORDS {
+            self.flush_trace();
+        }
+    }
+}
@@ -3,7 +3,11 @@
 use core::ptr::NonNull;
 use std::alloc::Layout;
 
+use super::callsites::CALLSITE_RECORD_SIZE;
+use super::hardened::{read_layout_record, LAYOUT_RECORD_SIZE};
+use super::redzones::{left_redzone_size, REDZONE_SIZE};
 use super::{BLOCK_CONTENT_MIN_ALIGN, BLOCK_CONTENT_MIN_SIZE, BLOCK_MIN_SIZE};
+use crate::error::MallocError;
 use crate::header::{Header, HEADER_SIZE};
 use crate::util::find_aligned;
 
@@ -13,6 +17,7 @@ use crate::util::find_aligned;
 /// # Panics
 /// Panics if `y` is 0.
 #[inline]
+#[allow(clippy::manual_is_multiple_of)]
 pub fn find_divisible(x: usize, y: usize) -> Option<usize> {
     if x % y == 0 {
         Some(x)
@@ -48,44 +53,76 @@ pub fn find_place(block_start: *const u8, obj_align: usize) -> Option<NonNull<u8
 }
 
 /// Augments `size` to a size that can be used for an allocation
-/// or returns `Err(())` if the size can not be augmented.
+/// or returns [`MallocError::LayoutTooLarge`] if the size can not be augmented.
 #[inline]
-pub fn augment_size(size: usize) -> Result<usize, ()> {
+pub fn augment_size(size: usize) -> Result<usize, MallocError> {
     // Size of objects should not exceed isize::MAX.
     // https://doc.rust-lang.org/std/ptr/index.html#allocated-object
     match find_divisible(size.max(BLOCK_CONTENT_MIN_SIZE), HEADER_SIZE) {
         Some(new_size) if new_size as isize > 0 => Ok(new_size),
-        _ => Err(()),
+        _ => Err(MallocError::LayoutTooLarge),
     }
 }
 
+/// The size of the records at the end of occupied blocks,
+/// that is the call site record followed by the layout record.
+pub const TRAILER_SIZE: usize = CALLSITE_RECORD_SIZE + LAYOUT_RECORD_SIZE;
+
 /// Augments `layout` to a layout that can be used by the allocator
-/// or returns `Err(())` if the layout can not be augmented.
-pub fn augment_layout(layout: Layout) -> Result<Layout, ()> {
+/// or returns [`MallocError::LayoutTooLarge`] if the layout can not be augmented.
+/// This includes space for the redzones and the trailing records, if there are any.
+pub fn augment_layout(layout: Layout) -> Result<Layout, MallocError> {
     let obj_align = layout.align().max(BLOCK_CONTENT_MIN_ALIGN);
-    let obj_size = augment_size(layout.size())?;
+    let overhead = left_redzone_size(layout.align()) + REDZONE_SIZE + TRAILER_SIZE;
+    let size = layout.size().checked_add(overhead);
+    let obj_size = augment_size(size.ok_or(MallocError::LayoutTooLarge)?)?;
 
     debug_assert!(Layout::from_size_align(obj_size, obj_align).is_ok());
     unsafe { Ok(Layout::from_size_align_unchecked(obj_size, obj_align)) }
 }
 
+/// Returns the content size of the occupied block whose contents start at `obj_start`.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is preceded by an untagged header.
+#[inline(always)]
+pub unsafe fn content_size(obj_start: *const u8) -> usize {
+    let block_header: &Header = &*obj_start.sub(HEADER_SIZE).cast();
+    debug_assert!(
+        !block_header.is_tagged(),
+        "Objects should be preceded by untagged headers."
+    );
+    block_header.content_size()
+}
+
+/// Returns the number of usable bytes of the object whose block contents start at `obj_start`.
+/// With redzones this is the size the object was allocated with, since anything
+/// past that belongs to the right redzone.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `obj_start` is preceded by an untagged header.
+#[inline(always)]
+pub unsafe fn usable_size(obj_start: *const u8) -> usize {
+    match cfg!(feature = "redzones") {
+        true => read_layout_record(obj_start).unwrap_unchecked().size(),
+        false => content_size(obj_start) - TRAILER_SIZE,
+    }
+}
+
 /// Converts an object pointer to a fat pointer to the full object content.
 ///
 /// # Safety
-/// This function is unsafe since it assumes that `obj_start` points to a valid object.
-/// In particular, is expected that as part of an occupied block the object is preceded
-/// by an untagged header.
+/// This function is unsafe since it assumes that `ptr` points to an object
+/// whose block contents start at `obj_start`.
 #[inline]
-pub unsafe fn to_nonnull_slice(obj_start: NonNull<u8>) -> NonNull<[u8]> {
-    let block_header: *const Header = unsafe { obj_start.as_ptr().sub(HEADER_SIZE).cast() };
-    debug_assert!(!(*block_header).is_tagged());
-    let obj_size = (*block_header).__content_size;
-    NonNull::slice_from_raw_parts(obj_start, obj_size)
+pub unsafe fn to_nonnull_slice(ptr: NonNull<u8>, obj_start: *const u8) -> NonNull<[u8]> {
+    NonNull::slice_from_raw_parts(ptr, usable_size(obj_start))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::allocators::raw_malloc::hardened::write_layout_record;
     use crate::header::HEADER_ALIGN;
     use core::ptr::null;
 
@@ -109,6 +146,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::manual_dangling_ptr)]
     fn test_find_place_1() {
         assert_eq!(
             find_place(null(), HEADER_ALIGN).unwrap().as_ptr() as usize,
@@ -148,9 +186,11 @@ mod tests {
             for align in (0..=usize::ilog2(HEADER_ALIGN)).map(|i| 1 << i) {
                 let layout = Layout::from_size_align(size, align).unwrap();
                 let augmented = augment_layout(layout).unwrap();
+                let overhead = left_redzone_size(align) + REDZONE_SIZE + TRAILER_SIZE;
+                let size = (HEADER_SIZE * 2 + overhead).max(BLOCK_CONTENT_MIN_SIZE);
                 assert_eq!(
                     augmented,
-                    Layout::from_size_align(HEADER_SIZE * 2, BLOCK_CONTENT_MIN_ALIGN).unwrap()
+                    Layout::from_size_align(size, BLOCK_CONTENT_MIN_ALIGN).unwrap()
                 );
             }
         }
@@ -158,12 +198,24 @@ mod tests {
 
     #[test]
     fn test_to_nonnull_slice() {
-        let obj_size = 20;
-        let mut header = unsafe { Header::new_unchecked(obj_size, false) };
-        let block_start: *mut u8 = (&mut header as *mut Header).cast();
+        let obj_size = 4 * HEADER_SIZE;
+        let layout = Layout::from_size_align(20, 1).unwrap();
+        // The block header followed by the block contents.
+        let mut block = [0_u128; 5];
+        let block_start: *mut u8 = block.as_mut_ptr().cast();
         let obj_start = unsafe { block_start.add(HEADER_SIZE) };
-        let obj_as_slice = unsafe { to_nonnull_slice(NonNull::new(obj_start).unwrap()) };
+        unsafe {
+            *block_start.cast::<Header>() = Header::new_unchecked(obj_size, false);
+            // With redzones the usable size is the recorded one.
+            write_layout_record(obj_start, layout);
+        }
+        let obj_as_slice =
+            unsafe { to_nonnull_slice(NonNull::new(obj_start).unwrap(), obj_start) };
         assert_eq!(obj_as_slice.as_ptr() as *mut u8, obj_start);
-        assert_eq!(obj_as_slice.len(), obj_size);
+        let usable_size = match cfg!(feature = "redzones") {
+            true => layout.size(),
+            false => obj_size - TRAILER_SIZE,
+        };
+        assert_eq!(obj_as_slice.len(), usable_size);
     }
 }
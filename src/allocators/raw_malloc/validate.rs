@@ -0,0 +1,337 @@
+//! Heap consistency checks, see [`RawMalloc::validate`].
+
+use super::placement::Placement;
+use super::redzones::{check_redzones, RedzoneSide};
+use super::regions::FENCEPOST;
+use super::{RawMalloc, BLOCK_CONTENT_MIN_SIZE, FOOTERS};
+use crate::freelist::{size_class, Node, SIZE_CLASS_COUNT};
+use crate::growers::Grower;
+use crate::header::{Header, HEADER_ALIGN, HEADER_SIZE};
+use crate::util::raw_ptr;
+
+use core::fmt::{self, Display};
+use core::ptr::null_mut;
+
+/// The first inconsistency found by [`RawMalloc::validate`].
+///
+/// Blocks are identified by the address of their header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The block doesn't have header alignment.
+    MisalignedBlock { block: *const u8 },
+    /// The block's content size is too small, isn't a multiple of the header size
+    /// or the block extends past the end of its region of the heap.
+    InvalidContentSize {
+        block: *const u8,
+        content_size: usize,
+    },
+    /// The footer of the free block doesn't match its header.
+    FooterMismatch { block: *const u8 },
+    /// The "previous block free" bit of the block doesn't match the preceding block.
+    /// For the end of the heap this refers to the allocator's record of the last block.
+    PrevFreeMismatch { block: *const u8 },
+    /// A redzone of the object in the occupied block was overwritten.
+    /// Only checked when the `redzones` feature is enabled.
+    RedzoneOverwritten {
+        block: *const u8,
+        content_size: usize,
+        side: RedzoneSide,
+    },
+    /// The quarantined block was written to since it was quarantined.
+    PoisonOverwritten { block: *const u8 },
+    /// The fencepost marking the end of a sealed region of the heap was overwritten.
+    FencepostOverwritten { end: *const u8 },
+    /// A freelist node isn't inside the heap or isn't properly aligned.
+    NodeOutsideHeap { node: *const u8 },
+    /// A freelist node doesn't belong to a free block.
+    NodeNotFree { block: *const u8 },
+    /// A freelist node is in the list of a different size class than its block.
+    WrongSizeClass { block: *const u8, class: usize },
+    /// The `prev` link of a freelist node doesn't point to the node preceding it.
+    BrokenLink { block: *const u8 },
+    /// The rover of a freelist doesn't point to a node of that list.
+    InvalidRover { class: usize },
+    /// The number of free blocks doesn't match the number of freelist nodes,
+    /// that is some free blocks are missing from the freelists or are listed more than once.
+    FreeBlockCountMismatch { free_blocks: usize, nodes: usize },
+    /// The free block isn't on the freelist of its size class, while the freelists
+    /// have as many nodes as there are free blocks, so some other node isn't a block.
+    FreeBlockNotListed { block: *const u8 },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ValidationError::MisalignedBlock { block } => {
+                write!(f, "block at {block:?} is misaligned")
+            }
+            ValidationError::InvalidContentSize {
+                block,
+                content_size,
+            } => write!(
+                f,
+                "block at {block:?} has an invalid content size of {content_size}"
+            ),
+            ValidationError::FooterMismatch { block } => {
+                write!(
+                    f,
+                    "footer of free block at {block:?} doesn't match its header"
+                )
+            }
+            ValidationError::PrevFreeMismatch { block } => write!(
+                f,
+                "previous block free bit at {block:?} doesn't match the preceding block"
+            ),
+            ValidationError::RedzoneOverwritten {
+                block,
+                content_size,
+                side,
+            } => write!(
+                f,
+                "{side} redzone of block at {block:?} of size {content_size} was overwritten"
+            ),
+            ValidationError::PoisonOverwritten { block } => {
+                write!(f, "quarantined block at {block:?} was written to")
+            }
+            ValidationError::FencepostOverwritten { end } => {
+                write!(f, "fencepost of region ending at {end:?} was overwritten")
+            }
+            ValidationError::NodeOutsideHeap { node } => {
+                write!(f, "freelist node at {node:?} is outside of the heap")
+            }
+            ValidationError::NodeNotFree { block } => {
+                write!(
+                    f,
+                    "freelist node of block at {block:?} doesn't belong to a free block"
+                )
+            }
+            ValidationError::WrongSizeClass { block, class } => write!(
+                f,
+                "free block at {block:?} is in the freelist of size class {class}"
+            ),
+            ValidationError::BrokenLink { block } => {
+                write!(
+                    f,
+                    "freelist node of block at {block:?} has a broken prev link"
+                )
+            }
+            ValidationError::InvalidRover { class } => {
+                write!(f, "rover of size class {class} is not part of its freelist")
+            }
+            ValidationError::FreeBlockCountMismatch { free_blocks, nodes } => write!(
+                f,
+                "heap has {free_blocks} free blocks but the freelists have {nodes} nodes"
+            ),
+            ValidationError::FreeBlockNotListed { block } => {
+                write!(f, "free block at {block:?} is not on its freelist")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl<T: Grower, P: Placement> RawMalloc<T, P> {
+    /// Checks the consistency of the heap and returns the first inconsistency found.
+    ///
+    /// Every block of every region of the heap is checked for proper alignment,
+    /// content size, footer and "previous block free" bit (both of which are only kept
+    /// with the `footers` feature), as well as its redzones
+    /// if the `redzones` feature is enabled and its poison if it's quarantined.
+    /// The ends of all regions but the last are checked to hold their fenceposts.
+    /// Then every freelist is walked to verify that its links are consistent
+    /// and that each free block is on the freelist of its size class exactly once.
+    ///
+    /// Besides the walk over the heap, which takes linear time, every free block
+    /// is looked up in its freelist, so this takes time quadratic in the length
+    /// of the longest freelist. It never allocates, so it's safe to call
+    /// from within a global allocator.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        unsafe {
+            let mut free_blocks = 0;
+            for (region_start, region_end) in self.regions() {
+                free_blocks += self.validate_blocks(region_start, region_end)?;
+            }
+            let nodes = self.validate_freelists(free_blocks)?;
+            if nodes != free_blocks {
+                return Err(ValidationError::FreeBlockCountMismatch { free_blocks, nodes });
+            }
+            // Equal counts don't rule out a node listed twice or pointing inside
+            // of a block instead of some free block, which then isn't listed.
+            for (region_start, region_end) in self.regions() {
+                self.validate_listed(region_start, region_end)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every free block in `[region_start, region_end)` is on the freelist
+    /// of its size class.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the blocks of the region
+    /// and the freelists have already been validated
+    /// and that no allocator field is currently borrowed.
+    unsafe fn validate_listed(
+        &self,
+        region_start: *mut u8,
+        region_end: *mut u8,
+    ) -> Result<(), ValidationError> {
+        let mut block_start = region_start;
+        while block_start < region_end {
+            let block_header: &Header = &*block_start.cast();
+            let content_size = block_header.content_size();
+            if block_header.is_tagged() {
+                let node: *mut Node = block_start.add(HEADER_SIZE).cast();
+                let freelist = (*self.freelists.get()).list(size_class(content_size));
+                let mut p: *mut Node = raw_ptr(freelist.head());
+                while !p.is_null() && p != node {
+                    p = (*p).next;
+                }
+                if p.is_null() {
+                    return Err(ValidationError::FreeBlockNotListed {
+                        block: block_start.cast_const(),
+                    });
+                }
+            }
+            block_start = block_start.add(HEADER_SIZE + content_size);
+        }
+        Ok(())
+    }
+
+    /// Walks all blocks in `[region_start, region_end)` and returns the number of free blocks.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the region bounds are valid
+    /// and that no allocator field is currently borrowed.
+    unsafe fn validate_blocks(
+        &self,
+        region_start: *mut u8,
+        region_end: *mut u8,
+    ) -> Result<usize, ValidationError> {
+        let mut free_blocks = 0;
+        let mut prev_free = false;
+        let mut block_start = region_start;
+
+        while block_start < region_end {
+            let block = block_start.cast_const();
+            if !(block_start as usize).is_multiple_of(HEADER_ALIGN) {
+                return Err(ValidationError::MisalignedBlock { block });
+            }
+
+            let block_header: &Header = &*block_start.cast();
+            let content_size = block_header.content_size();
+            if content_size < BLOCK_CONTENT_MIN_SIZE
+                || !content_size.is_multiple_of(HEADER_SIZE)
+                || content_size
+                    > (region_end as usize - block_start as usize).saturating_sub(HEADER_SIZE)
+            {
+                return Err(ValidationError::InvalidContentSize {
+                    block,
+                    content_size,
+                });
+            }
+
+            if block_header.prev_free() != (FOOTERS && prev_free) {
+                return Err(ValidationError::PrevFreeMismatch { block });
+            }
+
+            if block_header.is_tagged() {
+                if FOOTERS {
+                    let footer: &Header = &*block_start.add(content_size).cast();
+                    if *footer != block_header.with_prev_free(false) {
+                        return Err(ValidationError::FooterMismatch { block });
+                    }
+                }
+                free_blocks += 1;
+            } else if self.is_quarantined(block) {
+                if !self.is_intact(block) {
+                    return Err(ValidationError::PoisonOverwritten { block });
+                }
+            } else if let Err(side) = check_redzones(block_start.add(HEADER_SIZE)) {
+                return Err(ValidationError::RedzoneOverwritten {
+                    block,
+                    content_size,
+                    side,
+                });
+            }
+
+            prev_free = block_header.is_tagged();
+            block_start = block_start.add(HEADER_SIZE + content_size);
+        }
+
+        // Only the end of the last region records whether its preceding block is free,
+        // the others are marked by fenceposts.
+        if region_end != raw_ptr(self.heap_end()) {
+            if *region_end.cast::<Header>() != FENCEPOST {
+                return Err(ValidationError::FencepostOverwritten {
+                    end: region_end.cast_const(),
+                });
+            }
+        } else if (FOOTERS && prev_free) != self.tail_free.get() {
+            return Err(ValidationError::PrevFreeMismatch {
+                block: region_end.cast_const(),
+            });
+        }
+
+        Ok(free_blocks)
+    }
+
+    /// Walks all freelists and returns the total number of nodes.
+    /// The walk stops once more than `free_blocks` nodes are visited, which guards against cycles.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that no allocator field is currently borrowed.
+    unsafe fn validate_freelists(&self, free_blocks: usize) -> Result<usize, ValidationError> {
+        let mut nodes = 0;
+
+        for class in 0..SIZE_CLASS_COUNT {
+            let freelist = (*self.freelists.get()).list(class);
+            let rover = raw_ptr(freelist.rover());
+            let mut rover_found = rover.is_null();
+            let mut prev: *mut Node = null_mut();
+            let mut p: *mut Node = raw_ptr(freelist.head());
+
+            while !p.is_null() {
+                let node = p.cast::<u8>();
+                if self
+                    .region_of(node)
+                    .is_none_or(|(start, _)| node < start.wrapping_add(HEADER_SIZE))
+                    || !(node as usize).is_multiple_of(HEADER_ALIGN)
+                {
+                    return Err(ValidationError::NodeOutsideHeap {
+                        node: node.cast_const(),
+                    });
+                }
+
+                let block = node.sub(HEADER_SIZE).cast_const();
+                let block_header: &Header = &*block.cast();
+                if !block_header.is_tagged() || block_header.content_size() < BLOCK_CONTENT_MIN_SIZE
+                {
+                    return Err(ValidationError::NodeNotFree { block });
+                }
+                if size_class(block_header.content_size()) != class {
+                    return Err(ValidationError::WrongSizeClass { block, class });
+                }
+                if (*p).prev != prev {
+                    return Err(ValidationError::BrokenLink { block });
+                }
+
+                rover_found |= p == rover;
+                nodes += 1;
+                if nodes > free_blocks {
+                    // There are more nodes than free blocks, no need to keep walking.
+                    return Ok(nodes);
+                }
+                prev = p;
+                p = (*p).next;
+            }
+
+            if !rover_found {
+                return Err(ValidationError::InvalidRover { class });
+            }
+        }
+
+        Ok(nodes)
+    }
+}
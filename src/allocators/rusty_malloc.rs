@@ -1,19 +1,80 @@
 //! A multithreaded memory allocator.
 
+use crate::allocators::arenas::{release_chunks, Arena, ArenaHeap, Arenas, CHUNK_HEADER_SIZE};
+use crate::allocators::profile::{ProfileSample, Profiler};
+use crate::allocators::raw_malloc::oom::{retry_on_oom, OomHandler};
+use crate::allocators::raw_malloc::{
+    Block, FirstFit, LeakReport, Placement, Stats, TraceOp, ValidationError,
+};
+use crate::allocators::tcache;
 use crate::allocators::RawMalloc;
+use crate::error::MallocError;
 use crate::growers::Grower;
+use crate::util::raw_ptr;
 
-use core::ptr::NonNull;
+use core::ptr::{copy_nonoverlapping, null, NonNull};
 use core::alloc::{Allocator, GlobalAlloc, AllocError, Layout};
-use std::sync::Mutex;
+use core::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::io::{self, Write};
+use std::os::fd::RawFd;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+pub use crate::allocators::arenas::{
+    ARENA_CHUNK_MAX_SIZE, ARENA_CHUNK_MIN_SIZE, THREAD_ARENA_SLOTS,
+};
+
+/// The allocator whose leaks are reported when the process exits,
+/// see [`RustyMalloc::report_leaks_at_exit`].
+static EXIT_LEAK_REPORTER: OnceLock<LeakReporter> = OnceLock::new();
+
+/// A type-erased allocator and the function that prints its leak report.
+struct LeakReporter {
+    owner: *const (),
+    report: unsafe fn(*const ()),
+}
+
+// The owner is a `RustyMalloc` with a static lifetime, which is `Sync`.
+unsafe impl Send for LeakReporter {}
+unsafe impl Sync for LeakReporter {}
+
+extern "C" fn print_exit_leak_report() {
+    if let Some(reporter) = EXIT_LEAK_REPORTER.get() {
+        unsafe { (reporter.report)(reporter.owner) };
+    }
+}
 
 /// A multithreaded memory allocator.
 ///
-/// This allocator is just a `Mutex` wrapper over [`RawMalloc`] to allow for multithreading.
+/// To reduce lock contention each thread allocates from its own thread arena, which is created
+/// on the thread's first allocation. Each arena is a [`RawMalloc`] over chunks allocated
+/// from the main heap, starting at [`ARENA_CHUNK_MIN_SIZE`] bytes and doubling up to
+/// [`ARENA_CHUNK_MAX_SIZE`] bytes. The main heap is a [`RawMalloc`] over the allocator's grower,
+/// which threads only allocate from directly when they already have arenas of
+/// [`THREAD_ARENA_SLOTS`] other allocators.
+///
+/// Objects are always returned to the arena (or the main heap) that owns them,
+/// no matter which thread frees them. The arenas of exited threads are handed over to new threads
+/// and give their chunks back to the main heap once all their objects are freed.
+///
+/// Optionally small objects can be kept in a per-thread cache when they are freed,
+/// see [`with_thread_cache`](RustyMalloc::with_thread_cache),
+/// allocations can be sampled for heap profiling,
+/// see [`with_profiling`](RustyMalloc::with_profiling), and allocations that run out of memory
+/// can be retried, see [`with_oom_handler`](RustyMalloc::with_oom_handler).
 #[derive(Debug)]
 #[repr(C)]
-pub struct RustyMalloc<T: Grower> {
-    inner: Mutex<RawMalloc<T>>,
+pub struct RustyMalloc<T: Grower, P: Placement = FirstFit> {
+    inner: Mutex<RawMalloc<T, P>>,
+    arenas: Arenas<T, P>,
+    thread_cache: bool,
+    quarantine_enabled: AtomicBool,
+    tracing: AtomicBool,
+    profiler: Profiler,
+    last_failure: Mutex<Option<MallocError>>,
+    oom_handler: Mutex<Option<OomHandler>>,
+    // The difference between the bytes requested by objects as seen by the heaps
+    // and by the users, caused by objects passing through thread caches.
+    cached_requested: AtomicIsize,
 }
 
 impl<T: Grower> RustyMalloc<T> {
@@ -21,33 +82,515 @@ impl<T: Grower> RustyMalloc<T> {
     /// Callers must make sure that the provided grower will be the only object
     /// managing it's underlying buffer for the lifetime of the returned allocator.
     pub const unsafe fn with_grower(grower: T) -> Self {
+        RustyMalloc::with_grower_and_placement(grower, FirstFit)
+    }
+}
+
+impl<T: Grower, P: Placement> RustyMalloc<T, P> {
+    /// # Safety
+    /// Callers must make sure that the provided grower will be the only object
+    /// managing it's underlying buffer for the lifetime of the returned allocator.
+    pub const unsafe fn with_grower_and_placement(grower: T, placement: P) -> Self {
         RustyMalloc {
-            inner: Mutex::new(RawMalloc::with_grower(grower)),
+            inner: Mutex::new(RawMalloc::with_grower_and_placement(grower, placement)),
+            arenas: Arenas::new(),
+            thread_cache: false,
+            quarantine_enabled: AtomicBool::new(false),
+            tracing: AtomicBool::new(false),
+            profiler: Profiler::new(0),
+            last_failure: Mutex::new(None),
+            oom_handler: Mutex::new(None),
+            cached_requested: AtomicIsize::new(0),
         }
     }
+
+    /// Enables the thread cache, that is small freed objects are kept in a bounded
+    /// cache of the freeing thread, which later allocations of that thread
+    /// are served from without taking any locks.
+    /// The cache is flushed back to the allocator once it's full and when the thread exits.
+    /// The cache is never used when the `hardened` or `redzones` feature is enabled.
+    ///
+    /// Each thread caches objects of a single allocator,
+    /// the first one that frees an object on that thread.
+    ///
+    /// # Safety
+    /// Callers must make sure that the allocator outlives all threads that use it
+    /// (which is always the case for statics), since cached objects are only
+    /// returned to it when the threads exit.
+    pub const unsafe fn with_thread_cache(mut self) -> Self {
+        self.thread_cache = true;
+        self
+    }
+
+    /// Enables heap profiling, that is allocations are sampled once every `sample_interval`
+    /// bytes on average (see [`DEFAULT_SAMPLE_INTERVAL`]) and the call stacks of sampled
+    /// objects are recorded until they are freed. A `sample_interval` of 1 samples every
+    /// allocation, 0 disables profiling.
+    ///
+    /// Samples are kept in a table allocated from the internal heap shared by all allocators.
+    /// Freeing an object only takes that heap's lock if an object with a similar address
+    /// is sampled.
+    /// See [`dump_profile`](RustyMalloc::dump_profile) for how to read the samples.
+    ///
+    /// [`DEFAULT_SAMPLE_INTERVAL`]: crate::allocators::profile::DEFAULT_SAMPLE_INTERVAL
+    pub const fn with_profiling(mut self, sample_interval: usize) -> Self {
+        self.profiler.set_sample_interval(sample_interval);
+        self
+    }
+
+    /// Sets the function called when an allocation runs out of memory,
+    /// which may free memory and have the allocation retried.
+    /// See [`set_oom_handler`](RustyMalloc::set_oom_handler) for details.
+    pub const fn with_oom_handler(mut self, handler: OomHandler) -> Self {
+        self.oom_handler = Mutex::new(Some(handler));
+        self
+    }
+
+    /// Sets the function called when an allocation runs out of memory, or removes it
+    /// if `handler` is `None`. See [`RawMalloc::set_oom_handler`] for details.
+    ///
+    /// The handler is called with the combined statistics of the allocator
+    /// (see [`stats`](RustyMalloc::stats)) and without holding any of its locks, so it may
+    /// [`trim`](RustyMalloc::trim) the allocator or flush the thread cache. Reallocations
+    /// that can't grow in place fall back to allocating, which calls the handler as well.
+    pub fn set_oom_handler(&self, handler: Option<OomHandler>) {
+        *self.oom_handler.lock().unwrap() = handler;
+    }
+
+    /// Returns the mean number of bytes between samples, 0 if profiling is disabled.
+    pub fn profile_sample_interval(&self) -> usize {
+        self.profiler.sample_interval()
+    }
+
+    /// Calls `f` with the samples of the live objects, in no particular order,
+    /// or with none if profiling is disabled. `f` is free to allocate from this allocator,
+    /// since it gets a copy of the samples.
+    ///
+    /// Each sample stands for `size / (1 - exp(-size / sample_interval))` bytes
+    /// of allocations from the same call stack.
+    pub fn with_profile_samples<R>(&self, f: impl FnOnce(&[ProfileSample]) -> R) -> R {
+        self.profiler.with_samples(f)
+    }
+
+    /// Writes the samples of the live objects to `out` in the legacy heap profile format
+    /// of gperftools, which `pprof` can aggregate and symbolize:
+    /// ```text
+    /// heap profile: <samples>: <bytes> [<samples>: <bytes>] @ heap_v2/<sample interval>
+    /// 1: <size> [1: <size>] @ <frame> <frame> ...
+    /// ...
+    ///
+    /// MAPPED_LIBRARIES:
+    /// <the contents of /proc/self/maps>
+    /// ```
+    /// Frames are return addresses, innermost first. Sizes are those of the sampled objects,
+    /// the estimate of the bytes they stand for is left to the tools reading the profile.
+    pub fn dump_profile(&self, out: &mut impl Write) -> io::Result<()> {
+        self.profiler.dump(out)
+    }
+
+    /// Allocates an object with `layout` like [`alloc`](GlobalAlloc::alloc)
+    /// but returns the reason of a failure instead of a null pointer.
+    /// The reason is also kept as the allocator's [`last_failure`](RustyMalloc::last_failure).
+    pub fn try_alloc_detailed(&self, layout: Layout) -> Result<NonNull<u8>, MallocError> {
+        let ptr = self.alloc_object(layout)?;
+        self.profiler.record_alloc(ptr.as_ptr(), layout.size());
+        Ok(ptr)
+    }
+
+    /// Returns the reason the most recent failed allocation of any thread failed
+    /// or `None` if no allocation has failed yet, no matter whether the allocation
+    /// was made from a thread arena or from the main heap.
+    pub fn last_failure(&self) -> Option<MallocError> {
+        *self.last_failure.lock().unwrap()
+    }
+
+    /// Returns the objects the current thread cached for this allocator to it.
+    /// Does nothing if the thread caches objects of another allocator.
+    pub fn flush_thread_cache(&self) {
+        tcache::flush((self as *const Self).cast());
+    }
+
+    /// Returns the number of usable bytes of the object pointed to by `ptr`.
+    /// See [`RawMalloc::usable_size`] for details.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `ptr` points to an object
+    /// that was allocated by a [`RustyMalloc`] and hasn't been freed yet.
+    pub unsafe fn usable_size(ptr: *const u8) -> usize {
+        RawMalloc::<T, P>::usable_size(ptr)
+    }
+
+    /// Returns the layout the object pointed to by `ptr` was allocated with
+    /// or `None` if none of the `hardened`, `redzones` and `cabi` features is enabled.
+    /// See [`RawMalloc::allocated_layout`] for details.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `ptr` points to an object
+    /// that was allocated by a [`RustyMalloc`] and hasn't been freed yet.
+    pub unsafe fn allocated_layout(ptr: *const u8) -> Option<Layout> {
+        RawMalloc::<T, P>::allocated_layout(ptr)
+    }
+
+    /// Sets the quarantine budget of the main heap and of each thread arena,
+    /// so up to `budget` bytes of freed objects are kept in quarantine per heap.
+    /// See [`RawMalloc::set_quarantine_budget`] for details.
+    ///
+    /// The thread cache is bypassed while the quarantine is enabled.
+    pub fn set_quarantine_budget(&self, budget: usize) {
+        self.quarantine_enabled.store(budget != 0, Ordering::Relaxed);
+        // Arenas created from now on take the budget of the main heap.
+        let _creating = self.arenas.lock_creation();
+        self.inner.lock().unwrap().set_quarantine_budget(budget);
+        for arena in self.arenas.iter() {
+            self.lock_arena(arena).set_quarantine_budget(budget);
+        }
+    }
+
+    /// Starts recording the operations of the main heap and of each thread arena to `fd`,
+    /// or stops recording them if `fd` is `None`.
+    /// See [`RawMalloc::set_trace_fd`] for details.
+    ///
+    /// The heaps share a single trace, but each of them buffers its records separately,
+    /// so records have to be sorted by their timestamps to restore the order of operations.
+    /// Objects served from and freed to the thread cache are recorded by the heaps owning them.
+    pub fn set_trace_fd(&self, fd: Option<RawFd>) {
+        // Arenas created from now on take the file descriptor of the main heap.
+        let _creating = self.arenas.lock_creation();
+        self.tracing
+            .store(fd.is_some() && cfg!(feature = "trace"), Ordering::Relaxed);
+        self.set_trace_fd_from(self.arenas.head(), fd);
+    }
+
+    /// Sets the trace file descriptor of `arena`, the arenas created before it and the main heap,
+    /// holding the locks of all of them at the same time.
+    fn set_trace_fd_from(&self, arena: Option<&Arena<T, P>>, fd: Option<RawFd>) {
+        let Some(arena) = arena else {
+            self.inner.lock().unwrap().set_trace_fd(fd);
+            return;
+        };
+        let heap = self.lock_arena(arena);
+        // Records to the previous file descriptor are written before the new trace header.
+        heap.flush_trace();
+        self.set_trace_fd_from(arena.next(), fd);
+        heap.trace_to(fd);
+    }
+
+    /// Writes the buffered trace records of the main heap and all thread arenas.
+    pub fn flush_trace(&self) {
+        self.inner.lock().unwrap().flush_trace();
+        for arena in self.arenas.iter() {
+            self.lock_arena(arena).flush_trace();
+        }
+    }
+
+    /// Checks the consistency of the main heap and all thread arenas.
+    /// See [`RawMalloc::validate`] for details.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.inner.lock().unwrap().validate()?;
+        for arena in self.arenas.iter() {
+            self.lock_arena(arena).validate()?;
+        }
+        Ok(())
+    }
+
+    /// Returns a report of the live objects of the main heap and all thread arenas.
+    /// See [`RawMalloc::leak_report`] for details.
+    ///
+    /// The current thread's cache is flushed first, while objects in the caches
+    /// of other threads are reported as live.
+    pub fn leak_report(&self) -> LeakReport {
+        self.flush_thread_cache();
+        let mut report = LeakReport::new();
+        // Arena chunks are objects of the main heap, but not live ones.
+        self.inner
+            .lock()
+            .unwrap()
+            .collect_leaks(&mut report, |ptr| !self.arenas.is_chunk(ptr));
+        for arena in self.arenas.iter() {
+            self.lock_arena(arena).collect_leaks(&mut report, |_| true);
+        }
+        report
+    }
+
+    /// Prints the leak report of the allocator to stderr when the process exits,
+    /// which is meant for allocators used as the global allocator.
+    /// Only the first call has an effect, for any allocator.
+    ///
+    /// The report includes objects that are still live because `std` never frees them,
+    /// such as the buffers of the standard streams.
+    pub fn report_leaks_at_exit(&'static self) {
+        let reporter = LeakReporter {
+            owner: (self as *const Self).cast(),
+            report: Self::print_leak_report,
+        };
+        if EXIT_LEAK_REPORTER.set(reporter).is_ok() {
+            unsafe { libc::atexit(print_exit_leak_report) };
+        }
+    }
+
+    /// Prints the leak report of `owner`, which has to be a `RustyMalloc<T, P>`.
+    unsafe fn print_leak_report(owner: *const ()) {
+        (*owner.cast::<Self>()).leak_report().print();
+    }
+
+    /// Calls `f` with every block of the main heap and then with every block of
+    /// each thread arena, while holding the lock of the heap being walked.
+    /// See [`RawMalloc::blocks`] for details.
+    ///
+    /// `f` must not allocate from this allocator, since that would deadlock.
+    pub fn for_each_block(&self, mut f: impl FnMut(Block)) {
+        self.inner.lock().unwrap().blocks().for_each(&mut f);
+        for arena in self.arenas.iter() {
+            self.lock_arena(arena).blocks().for_each(&mut f);
+        }
+    }
+
+    /// Returns a snapshot of the statistics of the main heap and all thread arenas combined.
+    /// See [`RawMalloc::stats`] for details.
+    ///
+    /// Arena chunks are accounted for as the memory of their arenas
+    /// rather than as objects of the main heap, so `heap_bytes` and the grower
+    /// statistics are those of the main heap. The redzones and trailing records
+    /// of the chunk objects count as headers. Objects in thread caches count as in use,
+    /// but not as requested.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats_from(self.arenas.head());
+        let cached_requested = self.cached_requested.load(Ordering::Relaxed);
+        stats.requested_bytes = stats.requested_bytes.saturating_add_signed(cached_requested);
+        stats
+    }
+
+    /// Returns the combined statistics of `arena`, the arenas created before it
+    /// and the main heap, holding the locks of all of them at the same time.
+    fn stats_from(&self, arena: Option<&Arena<T, P>>) -> Stats {
+        let Some(arena) = arena else {
+            return self.inner.lock().unwrap().stats();
+        };
+        // Arenas are locked before the main heap, like when they grow.
+        let mut heap = self.lock_arena(arena);
+        let mut stats = self.stats_from(arena.next());
+        let arena_stats = heap.stats();
+        let grower = heap.grower_mut();
+        let chunk_bytes = grower.chunk_bytes();
+        let chunk_object_bytes = grower.chunk_object_bytes();
+        let chunk_header_bytes = grower.chunk_count() * CHUNK_HEADER_SIZE;
+
+        // The chunk objects of the main heap are replaced by the arena's contents and
+        // the chunk headers, with the parts of the chunks the arena hasn't grown into being free.
+        // Their redzones and trailing records count as headers, while any padding
+        // of the chunk objects stays in use.
+        stats.requested_bytes = stats.requested_bytes.saturating_sub(chunk_bytes);
+        stats.requested_bytes += arena_stats.requested_bytes;
+        stats.in_use_bytes = stats.in_use_bytes - chunk_object_bytes + arena_stats.in_use_bytes;
+        stats.padding_bytes += arena_stats.padding_bytes;
+        stats.header_bytes += arena_stats.header_bytes
+            + chunk_header_bytes
+            + (chunk_object_bytes - chunk_bytes);
+        stats.free_bytes +=
+            arena_stats.free_bytes + (chunk_bytes - chunk_header_bytes - arena_stats.heap_bytes);
+        stats
+    }
+
+    /// Releases free memory at the end of the main heap back to the grower.
+    /// See [`RawMalloc::trim`] for details.
+    ///
+    /// Thread arenas without any objects give their chunks back to the main heap first,
+    /// the other arenas keep theirs.
+    pub fn trim(&self, keep: usize) -> usize {
+        for arena in self.arenas.iter() {
+            release_chunks(&mut self.lock_arena(arena));
+        }
+        self.inner.lock().unwrap().trim(keep)
+    }
+
+    /// Frees an object without going through the thread cache.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `allocator` points to a
+    /// [`RustyMalloc`] which has allocated `ptr`.
+    unsafe fn flush_object(allocator: *const (), ptr: *mut u8, layout: Layout) {
+        let allocator = &*allocator.cast::<Self>();
+        // The heap stops counting the object as requested,
+        // which undoes the adjustment made when it was cached.
+        allocator
+            .cached_requested
+            .fetch_add(layout.size() as isize, Ordering::Relaxed);
+        // The deallocation was traced when the object was cached.
+        allocator.with_owner(ptr, |owner| owner.dealloc_untraced(ptr, layout))
+    }
+
+    /// Tries to put an object into the current thread's cache.
+    /// Returns `Err(())` if the object has to be freed instead.
+    unsafe fn cache(&self, ptr: *mut u8, layout: Layout) -> Result<(), ()> {
+        // Hardened and redzone builds check every freed object and the quarantine
+        // has to see every freed object, which cached objects would bypass.
+        if !self.thread_cache
+            || ptr.is_null()
+            || cfg!(any(feature = "hardened", feature = "redzones"))
+            || self.quarantine_enabled.load(Ordering::Relaxed)
+        {
+            return Err(());
+        }
+        let owner = (self as *const Self).cast();
+        tcache::push(owner, Self::flush_object, NonNull::new_unchecked(ptr), layout)?;
+        self.cached_requested
+            .fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        self.trace_cached(TraceOp::Dealloc, ptr, layout);
+        Ok(())
+    }
+
+    /// Takes an object for `layout` out of the current thread's cache if there is one.
+    fn cached(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if !self.thread_cache {
+            return None;
+        }
+        let ptr = tcache::pop((self as *const Self).cast(), layout)?;
+        self.cached_requested
+            .fetch_add(layout.size() as isize, Ordering::Relaxed);
+        self.trace_cached(TraceOp::Alloc, ptr.as_ptr(), layout);
+        Some(ptr)
+    }
+
+    /// Records an allocation from or a deallocation to the thread cache
+    /// in the trace of the heap owning the object, if tracing is enabled.
+    fn trace_cached(&self, op: TraceOp, ptr: *mut u8, layout: Layout) {
+        if self.tracing.load(Ordering::Relaxed) {
+            self.with_owner(ptr, |owner| owner.trace(op, layout, ptr));
+        }
+    }
+
+    /// Allocates an object for `layout` from the thread cache, the thread arena
+    /// or the main heap, in that order, calling the out-of-memory handler on failure.
+    /// Failures are recorded as the allocator's last failure, whichever heap they came from.
+    fn alloc_object(&self, layout: Layout) -> Result<NonNull<u8>, MallocError> {
+        if let Some(ptr) = self.cached(layout) {
+            return Ok(ptr);
+        }
+        let ptr = retry_on_oom(
+            || *self.oom_handler.lock().unwrap(),
+            layout,
+            || self.alloc_from_heaps(layout),
+            || self.stats(),
+        );
+        if let Err(e) = ptr {
+            *self.last_failure.lock().unwrap() = Some(e);
+        }
+        ptr
+    }
+
+    /// Allocates an object for `layout` from the thread arena or, if the thread has none,
+    /// from the main heap.
+    fn alloc_from_heaps(&self, layout: Layout) -> Result<NonNull<u8>, MallocError> {
+        match self.arenas.thread_arena(&self.inner) {
+            Some(arena) => self.lock_arena(arena).try_alloc_detailed(layout),
+            None => self.inner.lock().unwrap().try_alloc_detailed(layout),
+        }
+    }
+
+    /// Locks the heap of `arena`.
+    fn lock_arena<'a>(&'a self, arena: &'a Arena<T, P>) -> MutexGuard<'a, ArenaHeap<T, P>> {
+        arena.lock(&self.arenas, &self.inner)
+    }
+
+    /// Calls `f` with the allocator owning `ptr`.
+    fn with_owner<R>(&self, ptr: *const u8, f: impl FnOnce(&dyn OwnerOps) -> R) -> R {
+        let Some(arena) = self.arenas.owner(ptr) else {
+            return f(&*self.inner.lock().unwrap());
+        };
+        let mut heap = self.lock_arena(arena);
+        let result = f(&*heap);
+        // The arenas of exited threads give their chunks back once they are empty.
+        if !arena.is_owned() {
+            release_chunks(&mut heap);
+        }
+        result
+    }
+}
+
+/// The operations [`RustyMalloc`] forwards to the allocator owning an object.
+trait OwnerOps {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout);
+    unsafe fn dealloc_untraced(&self, ptr: *mut u8, layout: Layout);
+    fn trace(&self, op: TraceOp, layout: Layout, ptr: *const u8);
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8;
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError>;
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError>;
 }
 
-impl<T: Grower> PartialEq for RustyMalloc<T> {
+impl<T: Grower, P: Placement> OwnerOps for RawMalloc<T, P> {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        GlobalAlloc::dealloc(self, ptr, layout)
+    }
+
+    unsafe fn dealloc_untraced(&self, ptr: *mut u8, layout: Layout) {
+        RawMalloc::dealloc_untraced(self, ptr, layout)
+    }
+
+    fn trace(&self, op: TraceOp, layout: Layout, ptr: *const u8) {
+        RawMalloc::trace(self, op, layout, ptr, null(), 0)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        GlobalAlloc::realloc(self, ptr, layout, new_size)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Allocator::grow(self, ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Allocator::shrink(self, ptr, old_layout, new_layout)
+    }
+}
+
+impl<T: Grower, P: Placement> PartialEq for RustyMalloc<T, P> {
     fn eq(&self, other: &Self) -> bool {
         // Different instances shouldn't be equal since they operate on different growers.
         core::ptr::eq(self, other)
     }
 }
 
-impl<T: Grower> Eq for RustyMalloc<T> {}
+impl<T: Grower, P: Placement> Eq for RustyMalloc<T, P> {}
 
 
-unsafe impl<T: Grower> Sync for RustyMalloc<T> {}
+unsafe impl<T: Grower, P: Placement> Sync for RustyMalloc<T, P> {}
 
 //---------------impl Allocator for RustyMalloc---------------//
 
-unsafe impl<T: Grower> Allocator for RustyMalloc<T> {
+unsafe impl<T: Grower, P: Placement> Allocator for RustyMalloc<T, P> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        (*self.inner.lock().unwrap()).allocate(layout)
+        let ptr = self.try_alloc_detailed(layout).map_err(|_| AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, unsafe {
+            Self::usable_size(ptr.as_ptr())
+        }))
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        (*self.inner.lock().unwrap()).deallocate(ptr, layout)
+        self.profiler.record_dealloc(ptr.as_ptr());
+        if self.cache(ptr.as_ptr(), layout).is_ok() {
+            return;
+        }
+        self.with_owner(ptr.as_ptr(), |owner| owner.dealloc(ptr.as_ptr(), layout))
     }
 
     unsafe fn grow(
@@ -56,7 +599,18 @@ unsafe impl<T: Grower> Allocator for RustyMalloc<T> {
             old_layout: Layout,
             new_layout: Layout,
         ) -> Result<NonNull<[u8]>, AllocError> {
-        Allocator::grow(&*self.inner.lock().unwrap(), ptr, old_layout, new_layout)
+        self.profiler.record_dealloc(ptr.as_ptr());
+        if let Ok(new_ptr) =
+            self.with_owner(ptr.as_ptr(), |owner| owner.grow(ptr, old_layout, new_layout))
+        {
+            self.profiler.record_alloc(new_ptr.as_ptr().cast(), new_layout.size());
+            return Ok(new_ptr);
+        }
+        // The owner of the object is out of memory, so the object is moved elsewhere.
+        let new_ptr = self.allocate(new_layout)?;
+        copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr().cast(), old_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
     }
 
     unsafe fn shrink(
@@ -65,22 +619,42 @@ unsafe impl<T: Grower> Allocator for RustyMalloc<T> {
             old_layout: Layout,
             new_layout: Layout,
         ) -> Result<NonNull<[u8]>, AllocError> {
-        (*self.inner.lock().unwrap()).shrink(ptr, old_layout, new_layout)
+        self.profiler.record_dealloc(ptr.as_ptr());
+        let new_ptr =
+            self.with_owner(ptr.as_ptr(), |owner| owner.shrink(ptr, old_layout, new_layout))?;
+        self.profiler.record_alloc(new_ptr.as_ptr().cast(), new_layout.size());
+        Ok(new_ptr)
     }
 }
 
 //---------------impl GlobalAlloc for RustyMalloc---------------//
 
-unsafe impl<T: Grower> GlobalAlloc for RustyMalloc<T> {
+unsafe impl<T: Grower, P: Placement> GlobalAlloc for RustyMalloc<T, P> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        (*self.inner.lock().unwrap()).alloc(layout)
+        raw_ptr(self.try_alloc_detailed(layout).ok())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        (*self.inner.lock().unwrap()).dealloc(ptr, layout)
+        self.profiler.record_dealloc(ptr);
+        if self.cache(ptr, layout).is_ok() {
+            return;
+        }
+        self.with_owner(ptr, |owner| owner.dealloc(ptr, layout))
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        (*self.inner.lock().unwrap()).realloc(ptr, layout, new_size)
+        self.profiler.record_dealloc(ptr);
+        let new_ptr = self.with_owner(ptr, |owner| owner.realloc(ptr, layout, new_size));
+        if !new_ptr.is_null() {
+            self.profiler.record_alloc(new_ptr, new_size);
+            return new_ptr;
+        }
+        // The owner of the object is out of memory, so the object is moved elsewhere.
+        let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+        if !new_ptr.is_null() {
+            copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
     }
 }
@@ -0,0 +1,303 @@
+//! Thread-local caches of recently freed objects.
+//!
+//! Each thread owns a single cache which is bound to the first allocator that
+//! frees an object into it, objects freed to other allocators simply bypass the cache.
+//! Cached objects are kept in bins by size, where each bin is an intrusive
+//! singly-linked list threaded through the first word of its objects.
+//! The second word holds the layout the object was freed with, which it's flushed with.
+//! Objects are only handed out again for that same layout, since the statistics of the heap
+//! owning an object were recorded for the layout it was first allocated with.
+//! Bins are flushed back to the allocator once they are full and when the thread exits.
+//!
+//! The caches are const-initialized thread locals without destructors, since registering
+//! a destructor may allocate and thread locals with destructors can't be used while
+//! the thread is being torn down. Caches are flushed by the thread exit hook of the
+//! [`arenas`](super::arenas) module instead.
+
+use crate::allocators::arenas::arm_exit_hook;
+use crate::allocators::raw_malloc::BLOCK_CONTENT_MIN_SIZE;
+use crate::header::{HEADER_ALIGN, HEADER_SIZE};
+
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::mem::size_of;
+use core::ptr::{null, null_mut, NonNull};
+
+use static_assertions::const_assert;
+
+/// The number of bins in a thread cache.
+pub(crate) const BIN_COUNT: usize = 32;
+
+/// The maximum number of objects a single bin can hold.
+pub(crate) const BIN_CAPACITY: usize = 16;
+
+/// Objects bigger than this are never cached.
+pub(crate) const TCACHE_MAX_SIZE: usize = BIN_COUNT * HEADER_SIZE;
+
+// Every object has room for the link and the layout.
+const_assert!(BLOCK_CONTENT_MIN_SIZE >= 2 * size_of::<usize>());
+
+/// Returns a cached object to its allocator, bypassing the cache.
+/// The first argument is the allocator the cache is bound to.
+pub(crate) type FlushFn = unsafe fn(*const (), *mut u8, Layout);
+
+struct ThreadCache {
+    owner: Cell<*const ()>,
+    flush: Cell<Option<FlushFn>>,
+    heads: [Cell<*mut u8>; BIN_COUNT],
+    counts: [Cell<usize>; BIN_COUNT],
+}
+
+thread_local! {
+    // Const-initialized thread locals without destructors never allocate,
+    // which makes them safe to use from within a global allocator.
+    static TCACHE: ThreadCache = const { ThreadCache::new() };
+}
+
+/// Returns the bin for objects with `layout` or `None` if such objects can't be cached.
+#[inline(always)]
+fn bin(layout: Layout) -> Option<usize> {
+    if layout.align() > HEADER_ALIGN || layout.size() > TCACHE_MAX_SIZE {
+        return None;
+    }
+    Some(layout.size().div_ceil(HEADER_SIZE).max(1) - 1)
+}
+
+/// Packs the layout of a cacheable object into a word, with the alignment's exponent
+/// in the top byte.
+#[inline(always)]
+fn encode_layout(layout: Layout) -> usize {
+    layout.size() | (layout.align().trailing_zeros() as usize) << (usize::BITS - 8)
+}
+
+/// The inverse of [`encode_layout`].
+#[inline(always)]
+fn decode_layout(word: usize) -> Layout {
+    let size = word & (usize::MAX >> 8);
+    let align = 1 << (word >> (usize::BITS - 8));
+    unsafe { Layout::from_size_align_unchecked(size, align) }
+}
+
+impl ThreadCache {
+    const fn new() -> Self {
+        ThreadCache {
+            owner: Cell::new(null()),
+            flush: Cell::new(None),
+            heads: [const { Cell::new(null_mut()) }; BIN_COUNT],
+            counts: [const { Cell::new(0) }; BIN_COUNT],
+        }
+    }
+
+    fn pop(&self, owner: *const (), layout: Layout) -> Option<NonNull<u8>> {
+        if self.owner.get() != owner {
+            return None;
+        }
+        let bin = bin(layout)?;
+        let mut link = &self.heads[bin];
+        loop {
+            let p = NonNull::new(link.get())?;
+            unsafe {
+                let next = p.as_ptr().cast::<Cell<*mut u8>>();
+                if *p.as_ptr().cast::<usize>().add(1) == encode_layout(layout) {
+                    link.set((*next).get());
+                    self.counts[bin].set(self.counts[bin].get() - 1);
+                    return Some(p);
+                }
+                link = &*next;
+            }
+        }
+    }
+
+    /// # Safety
+    /// This function is unsafe since it assumes that `ptr` is an object with `layout`
+    /// allocated by `owner` and that `flush` frees objects of `owner`.
+    unsafe fn push(
+        &self,
+        owner: *const (),
+        flush: FlushFn,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Result<(), ()> {
+        if self.owner.get().is_null() {
+            self.owner.set(owner);
+            self.flush.set(Some(flush));
+            arm_exit_hook();
+        } else if self.owner.get() != owner {
+            return Err(());
+        }
+        let bin = bin(layout).ok_or(())?;
+        if self.counts[bin].get() == BIN_CAPACITY {
+            self.flush_bin(bin);
+        }
+        *ptr.as_ptr().cast::<*mut u8>() = self.heads[bin].get();
+        *ptr.as_ptr().cast::<usize>().add(1) = encode_layout(layout);
+        self.heads[bin].set(ptr.as_ptr());
+        self.counts[bin].set(self.counts[bin].get() + 1);
+        Ok(())
+    }
+
+    /// Returns all objects in `bin` to the owning allocator.
+    fn flush_bin(&self, bin: usize) {
+        let Some(flush) = self.flush.get() else {
+            return;
+        };
+        // The bin is emptied upfront since flushing might reenter the cache.
+        let mut p = self.heads[bin].replace(null_mut());
+        self.counts[bin].set(0);
+        while !p.is_null() {
+            unsafe {
+                let next = *p.cast::<*mut u8>();
+                let layout = decode_layout(*p.cast::<usize>().add(1));
+                flush(self.owner.get(), p, layout);
+                p = next;
+            }
+        }
+    }
+
+    /// Returns all cached objects to the owning allocator.
+    fn flush_all(&self) {
+        for bin in 0..BIN_COUNT {
+            self.flush_bin(bin);
+        }
+    }
+}
+
+/// Takes an object for `layout` out of the current thread's cache
+/// or returns `None` if there is no such object cached for `owner`.
+#[inline]
+pub(crate) fn pop(owner: *const (), layout: Layout) -> Option<NonNull<u8>> {
+    TCACHE
+        .try_with(|tcache| tcache.pop(owner, layout))
+        .ok()
+        .flatten()
+}
+
+/// Puts an object into the current thread's cache.
+/// Returns `Err(())` if the object couldn't be cached and has to be freed by the caller.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `ptr` is an object with `layout`
+/// allocated by `owner`, that `flush` frees objects of `owner` and that `owner`
+/// outlives the current thread.
+#[inline]
+pub(crate) unsafe fn push(
+    owner: *const (),
+    flush: FlushFn,
+    ptr: NonNull<u8>,
+    layout: Layout,
+) -> Result<(), ()> {
+    TCACHE
+        .try_with(|tcache| tcache.push(owner, flush, ptr, layout))
+        .unwrap_or(Err(()))
+}
+
+/// Returns all objects in the current thread's cache to `owner`,
+/// unless the cache is bound to another allocator.
+pub(crate) fn flush(owner: *const ()) {
+    let _ = TCACHE.try_with(|tcache| {
+        if tcache.owner.get() == owner {
+            tcache.flush_all();
+        }
+    });
+}
+
+/// Returns all objects in the current thread's cache to the owning allocator,
+/// which is done when the thread exits.
+pub(crate) fn flush_at_exit() {
+    let _ = TCACHE.try_with(ThreadCache::flush_all);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_bin() {
+        for size in 0..=TCACHE_MAX_SIZE {
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            let bin = bin(layout).unwrap();
+            assert!(bin < BIN_COUNT);
+            assert!((bin + 1) * HEADER_SIZE >= size);
+        }
+        assert!(bin(Layout::from_size_align(TCACHE_MAX_SIZE + 1, 1).unwrap()).is_none());
+        assert!(bin(Layout::from_size_align(8, 2 * HEADER_ALIGN).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        unsafe fn never(_: *const (), _: *mut u8, _: Layout) {
+            panic!("Nothing should be flushed.");
+        }
+
+        let tcache = ThreadCache::new();
+        let owner = 42 as *const ();
+        let layout = Layout::from_size_align(HEADER_SIZE, HEADER_ALIGN).unwrap();
+        let mut objects = [[0_usize; 2]; 2];
+        let p1 = NonNull::from(&mut objects[0]).cast();
+        let p2 = NonNull::from(&mut objects[1]).cast();
+        unsafe {
+            assert!(tcache.push(owner, never, p1, layout).is_ok());
+            assert!(tcache.push(owner, never, p2, layout).is_ok());
+            assert!(tcache.push(null(), never, p2, layout).is_err());
+        }
+        assert_eq!(tcache.pop(null(), layout), None);
+        assert_eq!(tcache.pop(owner, layout), Some(p2));
+        assert_eq!(tcache.pop(owner, layout), Some(p1));
+        assert_eq!(tcache.pop(owner, layout), None);
+    }
+
+    #[test]
+    fn test_pop_same_layout() {
+        unsafe fn never(_: *const (), _: *mut u8, _: Layout) {
+            panic!("Nothing should be flushed.");
+        }
+
+        let tcache = ThreadCache::new();
+        let owner = 42 as *const ();
+        let small = Layout::from_size_align(3, 1).unwrap();
+        let middle = Layout::from_size_align(4, 1).unwrap();
+        let large = Layout::from_size_align(5, 1).unwrap();
+        assert_eq!(bin(small), bin(large));
+        let mut objects = [[0_usize; 2]; 2];
+        let p1 = NonNull::from(&mut objects[0]).cast();
+        let p2 = NonNull::from(&mut objects[1]).cast();
+        unsafe {
+            assert!(tcache.push(owner, never, p1, small).is_ok());
+            assert!(tcache.push(owner, never, p2, large).is_ok());
+        }
+        // Objects aren't reused for other layouts of the same bin.
+        assert_eq!(tcache.pop(owner, middle), None);
+        assert_eq!(tcache.pop(owner, small), Some(p1));
+        assert_eq!(tcache.pop(owner, small), None);
+        assert_eq!(tcache.pop(owner, large), Some(p2));
+        assert_eq!(tcache.counts[bin(small).unwrap()].get(), 0);
+    }
+
+    #[test]
+    fn test_flush() {
+        static FLUSHED: AtomicUsize = AtomicUsize::new(0);
+        unsafe fn count(_: *const (), _: *mut u8, layout: Layout) {
+            // Objects are flushed with the layout they were cached with.
+            assert_eq!(layout, Layout::from_size_align(3, 1).unwrap());
+            FLUSHED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let tcache = ThreadCache::new();
+        let owner = 42 as *const ();
+        let layout = Layout::from_size_align(3, 1).unwrap();
+        let mut objects = [[0_usize; 2]; BIN_CAPACITY + 1];
+        for object in objects.iter_mut() {
+            unsafe {
+                assert!(tcache
+                    .push(owner, count, NonNull::from(object).cast(), layout)
+                    .is_ok())
+            };
+        }
+        // The bin was flushed once it got full.
+        assert_eq!(FLUSHED.load(Ordering::Relaxed), BIN_CAPACITY);
+
+        tcache.flush_all();
+        assert_eq!(FLUSHED.load(Ordering::Relaxed), BIN_CAPACITY + 1);
+    }
+}
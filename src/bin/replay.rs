@@ -0,0 +1,289 @@
+//! Replays an allocation trace (see [`rusty_malloc::allocators::raw_malloc::trace`])
+//! against a [`RawMalloc`] over an in-memory arena and reports the peak heap size,
+//! the fragmentation of the heap over time and the latency of each kind of operation.
+//!
+//! ```text
+//! replay <trace> [--placement first|next|best] [--arena-size <bytes>] [--samples <count>]
+//! ```
+//! Records are replayed in timestamp order. Recorded pointers only identify objects,
+//! so objects freed or reallocated without being allocated in the trace are skipped,
+//! as are operations that failed when they were recorded.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process::ExitCode;
+use std::time::Instant;
+use std::{env, fmt};
+
+use rusty_malloc::allocators::raw_malloc::{
+    BestFit, FirstFit, NextFit, Placement, TraceOp, TraceReader, TraceRecord,
+};
+use rusty_malloc::growers::ArenaGrower;
+use rusty_malloc::RawMalloc;
+
+const USAGE: &str =
+    "Usage: replay <trace> [--placement first|next|best] [--arena-size <bytes>] [--samples <count>]";
+
+/// The command line options.
+struct Options {
+    trace: String,
+    placement: String,
+    arena_size: usize,
+    samples: usize,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            trace: String::new(),
+            placement: "first".to_string(),
+            arena_size: 1 << 30,
+            samples: 20,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} requires a value"));
+            match arg.as_str() {
+                "--placement" => options.placement = value()?,
+                "--arena-size" => options.arena_size = parse_number(&value()?)?,
+                "--samples" => options.samples = parse_number(&value()?)?.max(1),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+                _ if options.trace.is_empty() => options.trace = arg,
+                _ => return Err(format!("unexpected argument {arg}")),
+            }
+        }
+        if options.trace.is_empty() {
+            return Err("missing trace".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("invalid number {value}"))
+}
+
+/// The heap at some point of the replay.
+struct Sample {
+    ops: usize,
+    heap_bytes: usize,
+    in_use_bytes: usize,
+    free_bytes: usize,
+    free_blocks: usize,
+    largest_free_block: usize,
+    external: f64,
+}
+
+/// The latencies of one kind of operation, in nanoseconds.
+#[derive(Default)]
+struct Latencies(Vec<u64>);
+
+impl Latencies {
+    fn percentile(&self, p: usize) -> u64 {
+        self.0[(self.0.len() - 1) * p / 100]
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    ops: usize,
+    failed: usize,
+    skipped: usize,
+    peak_heap_bytes: usize,
+    samples: Vec<Sample>,
+    latencies: [Latencies; 3],
+}
+
+fn op_index(op: TraceOp) -> usize {
+    match op {
+        TraceOp::Alloc => 0,
+        TraceOp::Dealloc => 1,
+        TraceOp::Realloc => 2,
+    }
+}
+
+fn sample<P: Placement>(allocator: &RawMalloc<ArenaGrower, P>, ops: usize) -> Sample {
+    let stats = allocator.stats();
+    let fragmentation = allocator.fragmentation();
+    Sample {
+        ops,
+        heap_bytes: stats.heap_bytes,
+        in_use_bytes: stats.in_use_bytes,
+        free_bytes: stats.free_bytes,
+        free_blocks: fragmentation.free_blocks,
+        largest_free_block: fragmentation.largest_free_block,
+        external: fragmentation.external(),
+    }
+}
+
+/// Calls `f` and returns its result along with the time it took in nanoseconds.
+fn timed<R>(f: impl FnOnce() -> R) -> (R, u64) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed().as_nanos() as u64)
+}
+
+/// Replays `records` against `allocator`, sampling the heap `samples` times along the way.
+fn replay<P: Placement>(
+    records: &[TraceRecord],
+    allocator: &RawMalloc<ArenaGrower, P>,
+    samples: usize,
+) -> Report {
+    let mut report = Report::default();
+    // Maps recorded pointers to the replayed objects and their layouts.
+    let mut objects: HashMap<usize, (*mut u8, Layout)> = HashMap::new();
+    let sample_every = records.len().div_ceil(samples).max(1);
+
+    for (i, record) in records.iter().enumerate() {
+        // Only the allocator calls are timed, not the bookkeeping of the replayed objects.
+        let replayed = match record.op {
+            TraceOp::Alloc => match record.layout() {
+                Some(layout) if record.ptr != 0 && !objects.contains_key(&record.ptr) => {
+                    let (ptr, elapsed) = timed(|| unsafe { allocator.alloc(layout) });
+                    if !ptr.is_null() {
+                        objects.insert(record.ptr, (ptr, layout));
+                    }
+                    Some((!ptr.is_null(), elapsed))
+                }
+                _ => None,
+            },
+            TraceOp::Dealloc => objects.remove(&record.ptr).map(|(ptr, layout)| {
+                let ((), elapsed) = timed(|| unsafe { allocator.dealloc(ptr, layout) });
+                (true, elapsed)
+            }),
+            TraceOp::Realloc if record.new_ptr != 0 => match objects.remove(&record.ptr) {
+                Some((ptr, layout)) => {
+                    let (new_ptr, elapsed) =
+                        timed(|| unsafe { allocator.realloc(ptr, layout, record.new_size) });
+                    match new_ptr.is_null() {
+                        true => objects.insert(record.ptr, (ptr, layout)),
+                        false => objects.insert(
+                            record.new_ptr,
+                            (new_ptr, unsafe {
+                                Layout::from_size_align_unchecked(record.new_size, layout.align())
+                            }),
+                        ),
+                    };
+                    Some((!new_ptr.is_null(), elapsed))
+                }
+                None => None,
+            },
+            TraceOp::Realloc => None,
+        };
+
+        match replayed {
+            Some((succeeded, elapsed)) => {
+                report.ops += 1;
+                report.failed += !succeeded as usize;
+                report.latencies[op_index(record.op)].0.push(elapsed);
+            }
+            None => report.skipped += 1,
+        }
+        report.peak_heap_bytes = report.peak_heap_bytes.max(allocator.stats().heap_bytes);
+        if (i + 1) % sample_every == 0 || i + 1 == records.len() {
+            report.samples.push(sample(allocator, i + 1));
+        }
+    }
+    for latencies in &mut report.latencies {
+        latencies.0.sort_unstable();
+    }
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "replayed {} operations ({} failed, {} skipped)",
+            self.ops, self.failed, self.skipped
+        )?;
+        writeln!(f, "peak heap size: {} bytes", self.peak_heap_bytes)?;
+
+        writeln!(f, "\nlatency (ns):")?;
+        writeln!(
+            f,
+            "  {:<10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "operation", "count", "mean", "p50", "p99", "max"
+        )?;
+        for (name, latencies) in ["alloc", "dealloc", "realloc"].iter().zip(&self.latencies) {
+            if latencies.0.is_empty() {
+                continue;
+            }
+            let count = latencies.0.len();
+            writeln!(
+                f,
+                "  {name:<10} {count:>10} {:>10} {:>10} {:>10} {:>10}",
+                latencies.0.iter().sum::<u64>() / count as u64,
+                latencies.percentile(50),
+                latencies.percentile(99),
+                latencies.percentile(100),
+            )?;
+        }
+
+        writeln!(f, "\nheap over time:")?;
+        writeln!(
+            f,
+            "  {:>10} {:>12} {:>12} {:>12} {:>11} {:>12} {:>8}",
+            "ops", "heap", "in use", "free", "free blocks", "largest free", "external"
+        )?;
+        for sample in &self.samples {
+            writeln!(
+                f,
+                "  {:>10} {:>12} {:>12} {:>12} {:>11} {:>12} {:>8.3}",
+                sample.ops,
+                sample.heap_bytes,
+                sample.in_use_bytes,
+                sample.free_bytes,
+                sample.free_blocks,
+                sample.largest_free_block,
+                sample.external
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn read_trace(path: &str) -> io::Result<Vec<TraceRecord>> {
+    let mut records =
+        TraceReader::new(BufReader::new(File::open(path)?))?.collect::<io::Result<Vec<_>>>()?;
+    // Heaps buffer their records separately, so records of different heaps might be out of order.
+    records.sort_by_key(|record| record.timestamp);
+    Ok(records)
+}
+
+fn run_with<P: Placement>(records: &[TraceRecord], placement: P, options: &Options) {
+    let mut arena = vec![0_u8; options.arena_size];
+    let grower = ArenaGrower::new(&mut arena, 0);
+    let allocator = unsafe { RawMalloc::with_grower_and_placement(grower, placement) };
+    let report = replay(records, &allocator, options.samples);
+    println!("placement: {:?}", allocator.placement());
+    print!("{report}");
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let records = match read_trace(&options.trace) {
+        Ok(records) => records,
+        Err(error) => {
+            eprintln!("couldn't read {}: {error}", options.trace);
+            return ExitCode::FAILURE;
+        }
+    };
+    match options.placement.as_str() {
+        "first" => run_with(&records, FirstFit, &options),
+        "next" => run_with(&records, NextFit, &options),
+        "best" => run_with(&records, BestFit, &options),
+        placement => {
+            eprintln!("unknown placement {placement}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    }
+    ExitCode::SUCCESS
+}
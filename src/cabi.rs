@@ -0,0 +1,183 @@
+//! C ABI exports of the `malloc` family of functions, enabled by the `cabi` feature.
+//!
+//! With this feature the crate can be built as a shared library, which replaces
+//! the C allocator of any dynamically linked program it is preloaded into, for example:
+//! ```sh
+//! cargo rustc --lib --release --features cabi --crate-type cdylib
+//! LD_PRELOAD=target/release/librusty_malloc.so ls
+//! ```
+//!
+//! All functions are backed by a single [`RustyMalloc`] over an [`MmapGrower`],
+//! which continues in further mapped regions once its reservation is used up
+//! (see [`ChainGrower`]). The program break is left alone, since other code
+//! in the process might manage it, e.g. a Rust global allocator over a
+//! [`BrkGrower`](crate::growers::BrkGrower).
+//! Since C callers don't pass layouts when freeing, objects record the layout
+//! they were allocated with, like in hardened builds.
+//!
+//! The thread cache is not enabled, so objects freed by C code go straight back
+//! to the heap owning them.
+
+use crate::growers::{ChainGrower, MmapGrower};
+use crate::RustyMalloc;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ffi::{c_int, c_void};
+use core::mem::size_of;
+use core::ptr::{null_mut, write_bytes};
+
+/// The address space reserved for the heap, which only takes memory once it's used.
+const RESERVE_SIZE: usize = 1 << 36;
+
+/// The minimum size of the regions the heap continues in once the reservation is used up.
+const REGION_SIZE: usize = 64 << 20;
+
+/// The grower of the heap.
+type CabiGrower = ChainGrower<MmapGrower>;
+
+static ALLOCATOR: RustyMalloc<CabiGrower> = unsafe {
+    RustyMalloc::with_grower(ChainGrower::new(
+        MmapGrower::new(RESERVE_SIZE, 4096 * 64),
+        REGION_SIZE,
+    ))
+};
+
+/// The alignment of objects returned by [`malloc`], that is the alignment of `max_align_t`.
+pub const MALLOC_ALIGN: usize = 2 * size_of::<usize>();
+
+#[inline(always)]
+fn set_errno(errno: c_int) {
+    unsafe { *libc::__errno_location() = errno };
+}
+
+/// Allocates an object of `size` bytes (at least 1) with alignment `align` (at least [`MALLOC_ALIGN`]).
+/// Returns a null pointer and sets `errno` if the allocation failed.
+#[inline]
+unsafe fn alloc(size: usize, align: usize) -> *mut c_void {
+    let Ok(layout) = Layout::from_size_align(size.max(1), align.max(MALLOC_ALIGN)) else {
+        set_errno(libc::ENOMEM);
+        return null_mut();
+    };
+    let ptr = ALLOCATOR.alloc(layout);
+    if ptr.is_null() {
+        set_errno(libc::ENOMEM);
+    }
+    ptr.cast()
+}
+
+/// Returns the layout of the object pointed to by `ptr`.
+///
+/// # Safety
+/// This function is unsafe since it assumes that `ptr` is a live object of [`ALLOCATOR`].
+#[inline]
+unsafe fn object_layout(ptr: *const u8) -> Layout {
+    RustyMalloc::<CabiGrower>::allocated_layout(ptr)
+        .expect("Layouts should be recorded in cabi builds.")
+}
+
+/// Allocates `size` bytes aligned to [`MALLOC_ALIGN`].
+#[no_mangle]
+pub extern "C" fn malloc(size: usize) -> *mut c_void {
+    unsafe { alloc(size, MALLOC_ALIGN) }
+}
+
+/// Frees the object pointed to by `ptr`, null pointers are ignored.
+///
+/// # Safety
+/// `ptr` has to be either null or a live object allocated by one of this module's functions.
+#[no_mangle]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let ptr = ptr.cast();
+    ALLOCATOR.dealloc(ptr, object_layout(ptr));
+}
+
+/// Allocates a zeroed array of `count` elements of `size` bytes each.
+#[no_mangle]
+pub extern "C" fn calloc(count: usize, size: usize) -> *mut c_void {
+    let Some(size) = count.checked_mul(size) else {
+        set_errno(libc::ENOMEM);
+        return null_mut();
+    };
+    let ptr = unsafe { alloc(size, MALLOC_ALIGN) };
+    if !ptr.is_null() {
+        // Reused blocks might contain old data.
+        unsafe { write_bytes(ptr.cast::<u8>(), 0, size) };
+    }
+    ptr
+}
+
+/// Resizes the object pointed to by `ptr` to `size` bytes, moving it if necessary.
+/// A null `ptr` is equivalent to [`malloc`] and a zero `size` to [`free`].
+///
+/// # Safety
+/// `ptr` has to be either null or a live object allocated by one of this module's functions.
+#[no_mangle]
+pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+    if size == 0 {
+        free(ptr);
+        return null_mut();
+    }
+    let ptr = ptr.cast();
+    let new_ptr = ALLOCATOR.realloc(ptr, object_layout(ptr), size);
+    if new_ptr.is_null() {
+        set_errno(libc::ENOMEM);
+    }
+    new_ptr.cast()
+}
+
+/// Allocates `size` bytes aligned to `align` and stores the object in `memptr`.
+/// Returns `EINVAL` if `align` isn't a power-of-two multiple of the pointer size
+/// and `ENOMEM` if the allocation failed.
+///
+/// # Safety
+/// `memptr` has to be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn posix_memalign(
+    memptr: *mut *mut c_void,
+    align: usize,
+    size: usize,
+) -> c_int {
+    if !align.is_power_of_two() || !align.is_multiple_of(size_of::<*mut c_void>()) {
+        return libc::EINVAL;
+    }
+    let ptr = alloc(size, align);
+    if ptr.is_null() {
+        return libc::ENOMEM;
+    }
+    *memptr = ptr;
+    0
+}
+
+/// Allocates `size` bytes aligned to `align`, which has to be a power of two.
+#[no_mangle]
+pub extern "C" fn aligned_alloc(align: usize, size: usize) -> *mut c_void {
+    if !align.is_power_of_two() {
+        set_errno(libc::EINVAL);
+        return null_mut();
+    }
+    unsafe { alloc(size, align) }
+}
+
+/// Same as [`aligned_alloc`].
+#[no_mangle]
+pub extern "C" fn memalign(align: usize, size: usize) -> *mut c_void {
+    aligned_alloc(align, size)
+}
+
+/// Returns the number of usable bytes of the object pointed to by `ptr` or 0 if `ptr` is null.
+///
+/// # Safety
+/// `ptr` has to be either null or a live object allocated by one of this module's functions.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+    RustyMalloc::<CabiGrower>::usable_size(ptr.cast())
+}
@@ -0,0 +1,58 @@
+//! The [`MallocError`] type.
+
+use core::fmt::{self, Display};
+
+/// The reason an allocation (or a growth of the heap) failed.
+///
+/// Growers report why they couldn't grow, and the allocators pass that on along with
+/// failures of their own, see [`RawMalloc::try_alloc_detailed`]
+/// and [`RustyMalloc::last_failure`].
+///
+/// [`RawMalloc::try_alloc_detailed`]: crate::allocators::RawMalloc::try_alloc_detailed
+/// [`RustyMalloc::last_failure`]: crate::allocators::RustyMalloc::last_failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MallocError {
+    /// The object, together with the allocator's metadata, would take more than
+    /// `isize::MAX` bytes or wouldn't fit in the address space.
+    LayoutTooLarge,
+    /// The object's alignment can't be satisfied within the address space.
+    UnsatisfiableAlignment,
+    /// The grower has no memory left, e.g. its arena or its reserved address range is exhausted.
+    OutOfMemory,
+    /// Growing the heap would exceed the limit of a
+    /// [`LimitGrower`](crate::growers::LimitGrower).
+    LimitExceeded,
+    /// A system call of the grower failed with the contained `errno`.
+    Os(i32),
+    /// The grower doesn't support the operation, e.g. shrinking.
+    Unsupported,
+    /// The grower was asked to shrink by more than the size of its buffer.
+    InvalidSize,
+}
+
+impl MallocError {
+    /// Returns an [`Os`](MallocError::Os) error with the current `errno`.
+    pub(crate) fn last_os_error() -> Self {
+        MallocError::Os(std::io::Error::last_os_error().raw_os_error().unwrap_or(0))
+    }
+}
+
+impl Display for MallocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MallocError::LayoutTooLarge => write!(f, "the layout is too large"),
+            MallocError::UnsatisfiableAlignment => write!(f, "the alignment can't be satisfied"),
+            MallocError::OutOfMemory => write!(f, "the grower is out of memory"),
+            MallocError::LimitExceeded => write!(f, "the memory limit is exceeded"),
+            MallocError::Os(errno) => write!(
+                f,
+                "the grower failed: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+            MallocError::Unsupported => write!(f, "the grower doesn't support the operation"),
+            MallocError::InvalidSize => write!(f, "the size exceeds the grower's buffer"),
+        }
+    }
+}
+
+impl std::error::Error for MallocError {}
@@ -1,13 +1,20 @@
-//! Defines the [`Freelist`] struct and associated constants and functions.
+//! Defines the [`Freelist`] and [`SegregatedFreelist`] structs and associated constants and functions.
 
 use core::mem::{align_of, size_of};
 use core::ptr::{addr_of_mut, null_mut, NonNull};
 
-use super::header::HEADER_ALIGN;
+use super::header::{HEADER_ALIGN, HEADER_SIZE};
 
 pub const NODE_SIZE: usize = size_of::<Node>();
 pub const NODE_ALIGN: usize = align_of::<Node>();
 
+/// Content sizes below this limit get a size class of their own,
+/// larger sizes are grouped into power-of-two classes.
+const SMALL_SIZE_LIMIT: usize = 32 * HEADER_SIZE;
+const SMALL_CLASS_COUNT: usize = (SMALL_SIZE_LIMIT - NODE_SIZE) / HEADER_SIZE;
+pub const SIZE_CLASS_COUNT: usize =
+    SMALL_CLASS_COUNT + (usize::BITS - SMALL_SIZE_LIMIT.ilog2()) as usize;
+
 #[repr(C)]
 pub struct Node {
     pub next: *mut Node,
@@ -18,13 +25,19 @@ pub struct Node {
 #[repr(C)]
 pub struct Freelist {
     head: *mut Node,
+    /// A roving pointer into the list that is kept valid across removals.
+    /// It is used by next-fit placement to remember where the last search stopped.
+    rover: *mut Node,
 }
 
 impl Freelist {
     /// Creates an empty Freelist.
     #[inline]
     pub const fn new() -> Self {
-        Freelist { head: null_mut() }
+        Freelist {
+            head: null_mut(),
+            rover: null_mut(),
+        }
     }
 
     /// Creates a node at the location pointed by `p` and adds it to the front of the Freelist.
@@ -51,6 +64,7 @@ impl Freelist {
     }
 
     /// Removes `node` from the list.
+    /// If the rover points to `node` it is moved to the next node in the list.
     /// This operation has a time complexity of *O*(1).
     ///
     /// Safety:
@@ -58,6 +72,9 @@ impl Freelist {
     pub unsafe fn remove(&mut self, node: *const Node) {
         let prev = (*node).prev;
         let next = (*node).next;
+        if core::ptr::eq(self.rover, node) {
+            self.rover = next;
+        }
         match prev.is_null() {
             true => self.head = next,
             false => (*prev).next = next,
@@ -73,6 +90,96 @@ impl Freelist {
     pub fn head(&self) -> Option<NonNull<Node>> {
         NonNull::new(self.head)
     }
+
+    /// Returns the rover or `None` if it isn't set.
+    /// This operation has a time complexity of *O*(1).
+    #[inline]
+    pub fn rover(&self) -> Option<NonNull<Node>> {
+        NonNull::new(self.rover)
+    }
+
+    /// Sets the rover to `node`.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `node` is either null or part of the list.
+    #[inline]
+    pub unsafe fn set_rover(&mut self, node: *mut Node) {
+        self.rover = node;
+    }
+}
+
+/// Returns the size class of a free block with the given content size.
+///
+/// Small blocks are classified exactly (one class per [`HEADER_SIZE`] step),
+/// the rest fall into the class of the largest power of two that does not exceed their size.
+/// A block of any class is guaranteed to be larger than all blocks of lower classes.
+#[inline]
+pub fn size_class(content_size: usize) -> usize {
+    debug_assert!(content_size >= NODE_SIZE);
+    match content_size < SMALL_SIZE_LIMIT {
+        true => (content_size - NODE_SIZE) / HEADER_SIZE,
+        false => SMALL_CLASS_COUNT + (content_size.ilog2() - SMALL_SIZE_LIMIT.ilog2()) as usize,
+    }
+}
+
+/// An array of [`Freelist`]s each of which only holds nodes of blocks
+/// with the same [size class](size_class).
+#[derive(Debug)]
+#[repr(C)]
+pub struct SegregatedFreelist {
+    lists: [Freelist; SIZE_CLASS_COUNT],
+}
+
+impl SegregatedFreelist {
+    /// Creates a SegregatedFreelist with all lists empty.
+    #[inline]
+    pub const fn new() -> Self {
+        SegregatedFreelist {
+            lists: [const { Freelist::new() }; SIZE_CLASS_COUNT],
+        }
+    }
+
+    /// Creates a node at the location pointed by `p` and adds it to the front of the list
+    /// for blocks with `content_size`.
+    /// This operation has a time complexity of *O*(1).
+    ///
+    /// # Safety
+    /// See [`Freelist::push_front`].
+    #[inline]
+    pub unsafe fn push_front(&mut self, p: *mut Node, content_size: usize) {
+        self.lists[size_class(content_size)].push_front(p)
+    }
+
+    /// Removes `node`, which belongs to a block with `content_size`, from its list.
+    /// This operation has a time complexity of *O*(1).
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `node` is part of the list
+    /// for blocks with `content_size`.
+    #[inline]
+    pub unsafe fn remove(&mut self, node: *const Node, content_size: usize) {
+        self.lists[size_class(content_size)].remove(node)
+    }
+
+    /// Moves `node` from the list of `old_class` to the list for blocks with `content_size`.
+    /// Nothing happens if the two lists are the same.
+    /// This operation has a time complexity of *O*(1).
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that `node` is part of the list of `old_class`.
+    pub unsafe fn reclassify(&mut self, node: *mut Node, old_class: usize, content_size: usize) {
+        let new_class = size_class(content_size);
+        if new_class != old_class {
+            self.lists[old_class].remove(node);
+            self.lists[new_class].push_front(node);
+        }
+    }
+
+    /// Returns the list of size class `class`.
+    #[inline]
+    pub fn list(&mut self, class: usize) -> &mut Freelist {
+        &mut self.lists[class]
+    }
 }
 
 #[cfg(test)]
@@ -86,6 +193,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_2() {
         let mut list = Freelist::new();
         let count = 1000;
@@ -116,6 +224,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_3() {
         let mut list = Freelist::new();
 
@@ -161,6 +270,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_4() {
         let mut list = Freelist::new();
 
@@ -191,4 +301,74 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_rover() {
+        let mut list = Freelist::new();
+        let mut nodes: Vec<MaybeUninit<Node>> = (0..3).map(|_| MaybeUninit::uninit()).collect();
+
+        for node in nodes.iter_mut() {
+            unsafe {
+                list.push_front(node.as_mut_ptr());
+            }
+        }
+        assert!(list.rover().is_none());
+
+        let p: *mut Node = nodes[1].as_mut_ptr();
+        unsafe {
+            list.set_rover(p);
+            list.remove(nodes[2].as_ptr());
+            assert_eq!(list.rover().unwrap().as_ptr(), p);
+            list.remove(p);
+            assert_eq!(list.rover().unwrap().as_ptr(), nodes[0].as_mut_ptr());
+            list.remove(nodes[0].as_ptr());
+            assert!(list.rover().is_none());
+        }
+    }
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(NODE_SIZE), 0);
+        assert_eq!(size_class(NODE_SIZE + HEADER_SIZE), 1);
+        assert_eq!(size_class(SMALL_SIZE_LIMIT - HEADER_SIZE), SMALL_CLASS_COUNT - 1);
+        assert_eq!(size_class(SMALL_SIZE_LIMIT), SMALL_CLASS_COUNT);
+        assert_eq!(size_class(SMALL_SIZE_LIMIT * 2 - 1), SMALL_CLASS_COUNT);
+        assert_eq!(size_class(SMALL_SIZE_LIMIT * 2), SMALL_CLASS_COUNT + 1);
+        assert_eq!(size_class(usize::MAX), SIZE_CLASS_COUNT - 1);
+
+        let mut sizes = (NODE_SIZE..1 << 16).step_by(HEADER_SIZE);
+        let mut prev = sizes.next().map(size_class).unwrap();
+        for size in sizes {
+            let class = size_class(size);
+            assert!(class == prev || class == prev + 1);
+            prev = class;
+        }
+    }
+
+    #[test]
+    fn test_segregated_freelist() {
+        let mut lists = SegregatedFreelist::new();
+        let mut nodes: Vec<MaybeUninit<Node>> = (0..3).map(|_| MaybeUninit::uninit()).collect();
+        let small = NODE_SIZE;
+        let large = SMALL_SIZE_LIMIT * 4;
+
+        unsafe {
+            lists.push_front(nodes[0].as_mut_ptr(), small);
+            lists.push_front(nodes[1].as_mut_ptr(), large);
+            lists.push_front(nodes[2].as_mut_ptr(), large + HEADER_SIZE);
+
+            let large_class = size_class(large);
+            assert_eq!(lists.list(0).head().unwrap().as_ptr(), nodes[0].as_mut_ptr());
+            assert_eq!(lists.list(large_class).head().unwrap().as_ptr(), nodes[2].as_mut_ptr());
+
+            lists.reclassify(nodes[0].as_mut_ptr(), 0, large);
+            assert!(lists.list(0).head().is_none());
+            assert_eq!(lists.list(large_class).head().unwrap().as_ptr(), nodes[0].as_mut_ptr());
+
+            lists.remove(nodes[0].as_ptr(), large);
+            lists.remove(nodes[2].as_ptr(), large);
+            lists.remove(nodes[1].as_ptr(), large);
+            assert!(lists.list(large_class).head().is_none());
+        }
+    }
 }
@@ -3,13 +3,18 @@
 //! The [`Grower`] trait allows users to easily change the underlying
 //! buffer on which allocators in [`rusty_malloc::allocators`](crate::allocators) operate.
 
-use super::header::HEADER_ALIGN;
+use super::error::MallocError;
+use super::header::{HEADER_ALIGN, HEADER_SIZE};
 use super::util::{checked_add, find_aligned};
 
+use core::marker::PhantomData;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use libc::{brk, sbrk};
-
+use libc::{madvise, mmap, mprotect, sysconf, MADV_DONTNEED};
+use libc::{MAP_ANONYMOUS, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE};
+use libc::{PROT_NONE, PROT_READ, PROT_WRITE, _SC_PAGESIZE};
 
 /// A trait for types that act as if they were a contiguous growable buffer.
 ///
@@ -20,17 +25,57 @@ use libc::{brk, sbrk};
 pub unsafe trait Grower {
     /// Grows the underlying buffer with at least `size` bytes.
     /// Returns the old end of the buffer and the size of the growth
-    /// or `Err(())` if the growth failed.
+    /// or the reason the growth failed.
     ///
     /// # Safety
     /// Implementors should ensure that `grow(0)` does not grow the buffer.
-    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), ()>;
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError>;
+
+    /// Shrinks the underlying buffer by exactly `size` bytes, giving the memory back
+    /// to wherever it came from. Returns the new end of the buffer
+    /// or the reason the buffer could not be shrunk.
+    ///
+    /// Shrinking is optional, the default implementation always fails
+    /// with [`MallocError::Unsupported`]. Shrinking by more than the size of the buffer
+    /// fails with [`MallocError::InvalidSize`].
+    ///
+    /// # Safety
+    /// Callers must ensure that the last `size` bytes of the buffer are no longer in use.
+    /// Implementors should ensure that `shrink(0)` does not shrink the buffer
+    /// and that the buffer never shrinks below its initial end.
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        let _ = size;
+        Err(MallocError::Unsupported)
+    }
+
+    /// Moves the buffer to a new region of at least `size` bytes, which doesn't have to be
+    /// contiguous with the current one. Meant for when the current region can't grow anymore.
+    /// Returns the start of the new region and the size of the growth
+    /// or the reason the new region could not be created.
+    ///
+    /// The memory of the previous regions stays valid and afterwards the buffer ends
+    /// at the end of the new region, that is [`grow`](Grower::grow) and
+    /// [`shrink`](Grower::shrink) operate on the new region.
+    ///
+    /// Moving to a new region is optional, the default implementation always fails
+    /// with [`MallocError::Unsupported`].
+    ///
+    /// # Safety
+    /// Implementors should ensure that new regions start at an address aligned
+    /// to at least `align_of::<usize>()` and that the buffer never shrinks below
+    /// the start of its current region. Once grown, the buffer must be followed by
+    /// [`REGION_FOOT_SIZE`] writable bytes that stay valid after moving to a new region.
+    unsafe fn grow_region(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        let _ = size;
+        Err(MallocError::Unsupported)
+    }
 }
 
 #[derive(Debug)]
 /// A grower that internally uses [`libc::brk`] to operate
 /// on the end of the process's data segment.
 pub struct BrkGrower {
+    heap_start: *mut u8,
     heap_end: Option<NonNull<u8>>,
     min_increment: usize,
 }
@@ -38,32 +83,37 @@ pub struct BrkGrower {
 impl BrkGrower {
     #[inline(always)]
     pub const fn new(min_increment: usize) -> Self {
-        BrkGrower { heap_end: None, min_increment }
+        BrkGrower {
+            heap_start: core::ptr::null_mut(),
+            heap_end: None,
+            min_increment,
+        }
     }
 
     /// Tries to initialize the grower by calling `sbrk(0)` to get the initial heap end.
-    /// Returns `Err(())` if the grower could not be initialized.
+    /// Returns an error if the grower could not be initialized.
     ///
     /// # Safety
     /// This function is unsafe since it assumes that the grower
     /// wasn't previously initialized and that there aren't any other
     /// objects (growers or not) managing the program brake.
-    unsafe fn try_init(&mut self) -> Result<(), ()> {
+    unsafe fn try_init(&mut self) -> Result<(), MallocError> {
         debug_assert!(self.heap_end.is_none());
         let heap_end = unsafe { sbrk(0) };
-        debug_assert_ne!(heap_end as isize, -1, "Calling sbrk(0) should never fail.");
+        if heap_end as isize == -1 {
+            return Err(MallocError::last_os_error());
+        }
         debug_assert_ne!(heap_end as usize, 0);
-        unsafe {
-            self.heap_end = Some(NonNull::new_unchecked(
-                find_aligned(heap_end.cast(), HEADER_ALIGN).ok_or(())? as *mut u8,
-            ))
-        };
+        // The program break can't be moved past the end of the address space.
+        self.heap_start = find_aligned(heap_end.cast(), HEADER_ALIGN)
+            .ok_or(MallocError::Os(libc::ENOMEM))? as *mut u8;
+        self.heap_end = unsafe { Some(NonNull::new_unchecked(self.heap_start)) };
         Ok(())
     }
 }
 
 unsafe impl Grower for BrkGrower {
-    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), ()> {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
         if self.heap_end.is_none() {
             unsafe { self.try_init()? };
         }
@@ -72,75 +122,566 @@ unsafe impl Grower for BrkGrower {
             return Ok((heap_end, 0));
         }
         let size = size.max(self.min_increment);
-        let new_heap_end: *mut u8 = checked_add(heap_end.as_ptr(), size).ok_or(())? as _;
+        let new_heap_end: *mut u8 =
+            checked_add(heap_end.as_ptr(), size).ok_or(MallocError::LayoutTooLarge)? as _;
         if unsafe { brk(new_heap_end.cast()) == -1 } {
-            return Err(());
+            return Err(MallocError::last_os_error());
         }
         self.heap_end = unsafe { Some(NonNull::new_unchecked(new_heap_end)) };
         Ok((heap_end, size))
     }
+
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        let heap_end = self.heap_end.ok_or(MallocError::Unsupported)?;
+        if size > heap_end.as_ptr() as usize - self.heap_start as usize {
+            return Err(MallocError::InvalidSize);
+        }
+        if size == 0 {
+            return Ok(heap_end);
+        }
+        let new_heap_end = unsafe { heap_end.as_ptr().sub(size) };
+        if unsafe { brk(new_heap_end.cast()) == -1 } {
+            return Err(MallocError::last_os_error());
+        }
+        self.heap_end = unsafe { Some(NonNull::new_unchecked(new_heap_end)) };
+        Ok(self.heap_end.unwrap())
+    }
 }
 
-#[cfg(test)]
-pub mod arena_grower {
-    use super::Grower;
-    use crate::util::checked_add;
-    use core::ptr::NonNull;
-
-    /// An inherently unsafe grower that operates on an arena.
-    /// This structure is intended solely for debugging purposes.
-    pub struct ArenaGrower {
-        heap_end: *mut u8,
-        arena_end: *mut u8,
-        min_increment: usize,
-    }
-
-    impl ArenaGrower {
-        /// Creates a new arena that operates on the provided buffer.
-        pub const fn new(buf: *mut u8, size: usize, min_increment: usize) -> Self {
-            let heap_end = buf;
-            let arena_end = unsafe { buf.add(size) };
-            ArenaGrower {
-                heap_end,
-                arena_end,
-                min_increment,
-            }
+#[derive(Debug)]
+/// A grower that reserves a contiguous range of virtual memory with [`libc::mmap`]
+/// and commits pages from it with [`libc::mprotect`] as the buffer grows.
+///
+/// Unlike [`BrkGrower`] this grower does not touch the program break,
+/// so it can coexist with other allocators (e.g. the one of libc) in the same process.
+/// The reserved range is mapped with `PROT_NONE` and `MAP_NORESERVE`, which means that
+/// it only consumes address space until pages are committed.
+/// Shrinking the buffer decommits any pages that are left unused.
+pub struct MmapGrower {
+    heap_end: Option<NonNull<u8>>,
+    committed_end: *mut u8,
+    reserved_end: *mut u8,
+    reserve_size: usize,
+    min_increment: usize,
+}
+
+impl MmapGrower {
+    /// Creates a grower that will reserve `reserve_size` bytes of address space on first use.
+    /// The buffer can never grow beyond `reserve_size`.
+    #[inline(always)]
+    pub const fn new(reserve_size: usize, min_increment: usize) -> Self {
+        MmapGrower {
+            heap_end: None,
+            committed_end: core::ptr::null_mut(),
+            reserved_end: core::ptr::null_mut(),
+            reserve_size,
+            min_increment,
         }
     }
 
-    unsafe impl Grower for ArenaGrower {
-        unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), ()> {
-            let heap_end = self.heap_end;
-            if size == 0 {
-                return Ok((NonNull::new(heap_end).unwrap(), 0));
-            }
-            let size = size.max(self.min_increment);
-            let new_heap_end = checked_add(heap_end, size).ok_or(())? as *mut u8;
-            if new_heap_end > self.arena_end {
-                return Err(());
+    /// Tries to initialize the grower by reserving its address range.
+    /// Returns an error if the grower could not be initialized.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the grower
+    /// wasn't previously initialized.
+    unsafe fn try_init(&mut self) -> Result<(), MallocError> {
+        debug_assert!(self.heap_end.is_none());
+        if self.reserve_size == 0 {
+            return Err(MallocError::OutOfMemory);
+        }
+        let region = unsafe {
+            mmap(
+                core::ptr::null_mut(),
+                self.reserve_size,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if region == MAP_FAILED {
+            return Err(MallocError::last_os_error());
+        }
+        let region: *mut u8 = region.cast();
+        // Mappings are page-aligned which is more than enough for headers.
+        debug_assert_eq!(region as usize % HEADER_ALIGN, 0);
+        self.committed_end = region;
+        self.reserved_end = unsafe { region.add(self.reserve_size) };
+        self.heap_end = Some(unsafe { NonNull::new_unchecked(region) });
+        Ok(())
+    }
+
+    /// Returns the start of the reserved range.
+    #[inline(always)]
+    fn reserved_start(&self) -> *mut u8 {
+        self.reserved_end.wrapping_sub(self.reserve_size)
+    }
+
+    /// Returns the system page size.
+    #[inline(always)]
+    fn page_size() -> usize {
+        unsafe { sysconf(_SC_PAGESIZE) as usize }
+    }
+
+    /// Makes sure that all pages up to `new_heap_end` are readable and writable.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the grower is initialized
+    /// and that `new_heap_end` lies within the reserved range.
+    unsafe fn commit(&mut self, new_heap_end: *mut u8) -> Result<(), MallocError> {
+        if new_heap_end <= self.committed_end {
+            return Ok(());
+        }
+        let new_committed_end = find_aligned(new_heap_end, Self::page_size())
+            .ok_or(MallocError::LayoutTooLarge)?
+            .min(self.reserved_end as *const u8) as *mut u8;
+        let len = new_committed_end as usize - self.committed_end as usize;
+        if unsafe { mprotect(self.committed_end.cast(), len, PROT_READ | PROT_WRITE) } == -1 {
+            return Err(MallocError::last_os_error());
+        }
+        self.committed_end = new_committed_end;
+        Ok(())
+    }
+
+    /// Releases all committed pages that lie entirely after `new_heap_end`.
+    ///
+    /// # Safety
+    /// This function is unsafe since it assumes that the grower is initialized,
+    /// that `new_heap_end` lies within the reserved range and that the memory after it
+    /// is no longer in use.
+    unsafe fn decommit(&mut self, new_heap_end: *mut u8) -> Result<(), MallocError> {
+        let new_committed_end = find_aligned(new_heap_end, Self::page_size())
+            .ok_or(MallocError::Os(libc::ENOMEM))? as *mut u8;
+        if new_committed_end >= self.committed_end {
+            return Ok(());
+        }
+        let len = self.committed_end as usize - new_committed_end as usize;
+        unsafe {
+            if madvise(new_committed_end.cast(), len, MADV_DONTNEED) == -1
+                || mprotect(new_committed_end.cast(), len, PROT_NONE) == -1
+            {
+                return Err(MallocError::last_os_error());
             }
-            self.heap_end = new_heap_end;
-            Ok((NonNull::new(heap_end).unwrap(), size))
         }
+        self.committed_end = new_committed_end;
+        Ok(())
+    }
+}
+
+unsafe impl Grower for MmapGrower {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        if self.heap_end.is_none() {
+            unsafe { self.try_init()? };
+        }
+        let heap_end = self.heap_end.unwrap();
+        if size == 0 {
+            return Ok((heap_end, 0));
+        }
+        let size = size.max(self.min_increment);
+        let new_heap_end: *mut u8 =
+            checked_add(heap_end.as_ptr(), size).ok_or(MallocError::LayoutTooLarge)? as _;
+        if new_heap_end > self.reserved_end {
+            return Err(MallocError::OutOfMemory);
+        }
+        unsafe { self.commit(new_heap_end)? };
+        self.heap_end = unsafe { Some(NonNull::new_unchecked(new_heap_end)) };
+        Ok((heap_end, size))
+    }
+
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        let heap_end = self.heap_end.ok_or(MallocError::Unsupported)?;
+        if size > heap_end.as_ptr() as usize - self.reserved_start() as usize {
+            return Err(MallocError::InvalidSize);
+        }
+        let new_heap_end = unsafe { heap_end.as_ptr().sub(size) };
+        unsafe { self.decommit(new_heap_end)? };
+        self.heap_end = unsafe { Some(NonNull::new_unchecked(new_heap_end)) };
+        Ok(self.heap_end.unwrap())
+    }
+}
+
+/// A grower that operates on a fixed, caller-provided buffer (an arena).
+///
+/// The arena borrows its buffer for `'a` so the memory can't be touched through
+/// other means while an allocator manages it. Since the buffer can't be resized,
+/// growth fails once the arena is exhausted, which makes this grower a good fit for
+/// bounded heaps. Use [`remaining`](ArenaGrower::remaining) to query how much of the arena
+/// is still available.
+///
+/// If the buffer doesn't start at an address suitably aligned for block headers
+/// the first few bytes are skipped so that the heap start is properly aligned.
+///
+/// # Example
+/// ```
+/// #![feature(allocator_api)]
+/// use rusty_malloc::RawMalloc;
+/// use rusty_malloc::growers::{ArenaGrower, StaticArenaGrower};
+/// use core::ptr::addr_of_mut;
+///
+/// static mut HEAP: [u8; 4096] = [0; 4096];
+///
+/// let grower: StaticArenaGrower = ArenaGrower::new(unsafe { &mut *addr_of_mut!(HEAP) }, 0);
+/// let allocator = unsafe { RawMalloc::with_grower(grower) };
+/// let v: Vec<u32, _> = Vec::with_capacity_in(16, &allocator);
+/// assert_eq!(v.capacity(), 16);
+/// ```
+#[derive(Debug)]
+pub struct ArenaGrower<'a> {
+    heap_end: Option<NonNull<u8>>,
+    arena_start: *mut u8,
+    arena_end: *mut u8,
+    min_increment: usize,
+    _buf: PhantomData<&'a mut [u8]>,
+}
+
+/// An [`ArenaGrower`] over a buffer that lives for the rest of the program,
+/// e.g. a `static` array. Suitable as the grower of a global allocator.
+pub type StaticArenaGrower = ArenaGrower<'static>;
+
+impl<'a> ArenaGrower<'a> {
+    /// Creates a new arena that operates on the provided buffer.
+    #[inline(always)]
+    pub const fn new(buf: &'a mut [u8], min_increment: usize) -> Self {
+        let arena_start = buf.as_mut_ptr();
+        let arena_end = unsafe { arena_start.add(buf.len()) };
+        ArenaGrower {
+            heap_end: None,
+            arena_start,
+            arena_end,
+            min_increment,
+            _buf: PhantomData,
+        }
+    }
+
+    /// Returns the number of bytes that the buffer can still grow with.
+    pub fn remaining(&self) -> usize {
+        let heap_end = match self.heap_end {
+            Some(end) => end.as_ptr() as *const u8,
+            None => match find_aligned(self.arena_start, HEADER_ALIGN) {
+                Some(p) => p,
+                None => return 0,
+            },
+        };
+        (self.arena_end as usize).saturating_sub(heap_end as usize)
+    }
+
+    /// Initializes the grower by aligning the start of the arena.
+    /// Returns [`MallocError::OutOfMemory`] if the arena is too small to be aligned.
+    fn try_init(&mut self) -> Result<(), MallocError> {
+        debug_assert!(self.heap_end.is_none());
+        let heap_start =
+            find_aligned(self.arena_start, HEADER_ALIGN).ok_or(MallocError::OutOfMemory)?;
+        if heap_start > self.arena_end {
+            return Err(MallocError::OutOfMemory);
+        }
+        self.heap_end = NonNull::new(heap_start as *mut u8);
+        self.heap_end.ok_or(MallocError::OutOfMemory).map(|_| ())
+    }
+}
+
+unsafe impl Send for ArenaGrower<'_> {}
+
+unsafe impl Grower for ArenaGrower<'_> {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        if self.heap_end.is_none() {
+            self.try_init()?;
+        }
+        let heap_end = self.heap_end.unwrap();
+        if size == 0 {
+            return Ok((heap_end, 0));
+        }
+        let size = size.max(self.min_increment);
+        let new_heap_end =
+            checked_add(heap_end.as_ptr(), size).ok_or(MallocError::LayoutTooLarge)? as *mut u8;
+        if new_heap_end > self.arena_end {
+            return Err(MallocError::OutOfMemory);
+        }
+        self.heap_end = unsafe { Some(NonNull::new_unchecked(new_heap_end)) };
+        Ok((heap_end, size))
+    }
+
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        let heap_end = self.heap_end.ok_or(MallocError::Unsupported)?;
+        let heap_start =
+            find_aligned(self.arena_start, HEADER_ALIGN).ok_or(MallocError::Unsupported)?;
+        if size > heap_end.as_ptr() as usize - heap_start as usize {
+            return Err(MallocError::InvalidSize);
+        }
+        self.heap_end = unsafe { Some(NonNull::new_unchecked(heap_end.as_ptr().sub(size))) };
+        Ok(self.heap_end.unwrap())
+    }
+}
+
+/// A grower that wraps another grower and refuses to grow past a byte limit,
+/// failing with [`MallocError::LimitExceeded`] instead.
+///
+/// The limit is read from an atomic on every growth, so it can be raised or lowered
+/// at runtime, e.g. to cap the memory of a tenant. Lowering it below the bytes already
+/// grown doesn't release any memory, it only refuses further growth. Memory given back
+/// by [`shrink`](Grower::shrink) counts against the limit no more, shrinking by more
+/// than was grown through the limit fails with [`MallocError::InvalidSize`].
+///
+/// A growth the inner grower makes past the limit, e.g. because of its minimum increment,
+/// is cut back to the requested size or refused. If the inner grower can't give the growth
+/// back, it's kept as slack past the reported end of the buffer, which counts against
+/// the limit and is handed out by later growths before the inner grower is grown again.
+///
+/// # Example
+/// ```
+/// use rusty_malloc::RustyMalloc;
+/// use rusty_malloc::growers::{BrkGrower, LimitGrower};
+/// use core::sync::atomic::{AtomicUsize, Ordering};
+///
+/// static LIMIT: AtomicUsize = AtomicUsize::new(64 << 20);
+///
+/// static ALLOCATOR: RustyMalloc<LimitGrower<BrkGrower>> =
+///     unsafe { RustyMalloc::with_grower(LimitGrower::new(BrkGrower::new(4096), &LIMIT)) };
+///
+/// LIMIT.store(128 << 20, Ordering::Relaxed);
+/// ```
+#[derive(Debug)]
+pub struct LimitGrower<'a, G: Grower> {
+    inner: G,
+    limit: &'a AtomicUsize,
+    grown: usize,
+    /// The bytes the inner grower is grown by past the end of the buffer.
+    slack: usize,
+}
+
+impl<'a, G: Grower> LimitGrower<'a, G> {
+    /// Creates a grower that grows `inner` by at most `limit` bytes in total.
+    pub const fn new(inner: G, limit: &'a AtomicUsize) -> Self {
+        LimitGrower {
+            inner,
+            limit,
+            grown: 0,
+            slack: 0,
+        }
+    }
+
+    /// Returns the current limit in bytes.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes the inner grower is currently grown by,
+    /// including any slack it couldn't give back.
+    pub fn grown(&self) -> usize {
+        self.grown
+    }
+}
+
+impl<G: Grower> LimitGrower<'_, G> {
+    /// Grows the inner grower by `size` bytes with `grow` unless that would exceed the limit.
+    /// `new_region` tells whether `grow` moves the inner grower to a new region,
+    /// which can't be undone.
+    ///
+    /// # Safety
+    /// This function is unsafe since `grow` is assumed to grow the inner grower by at least
+    /// `size` bytes at the end of its buffer, like [`Grower::grow`] does.
+    unsafe fn grow_within_limit(
+        &mut self,
+        size: usize,
+        new_region: bool,
+        grow: impl FnOnce(&mut G, usize) -> Result<(NonNull<u8>, usize), MallocError>,
+    ) -> Result<(NonNull<u8>, usize), MallocError> {
+        let available = self.limit().saturating_sub(self.grown);
+        if size > available {
+            return Err(MallocError::LimitExceeded);
+        }
+        let (start, growth) = grow(&mut self.inner, size)?;
+        self.grown += growth;
+        if growth <= available {
+            return Ok((start, growth));
+        }
+
+        // The inner grower's minimum increment took it past the limit, so the surplus
+        // is given back or, if it can't be, the whole growth is undone.
+        if unsafe { self.inner.shrink(growth - size) }.is_ok() {
+            self.grown -= growth - size;
+            return Ok((start, size));
+        }
+        if !new_region && unsafe { self.inner.shrink(growth) }.is_ok() {
+            self.grown -= growth;
+            return Err(MallocError::LimitExceeded);
+        }
+        // Growth which can't be given back is kept as slack. The inner grower
+        // already moved to a new region, so the region is used up to the requested size.
+        if new_region {
+            self.slack = growth - size;
+            return Ok((start, size));
+        }
+        self.slack += growth;
+        Err(MallocError::LimitExceeded)
+    }
+}
+
+unsafe impl<G: Grower> Grower for LimitGrower<'_, G> {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        let grow = |inner: &mut G, size| unsafe { inner.grow(size) };
+        if self.slack == 0 {
+            return self.grow_within_limit(size, false, grow);
+        }
+        // The slack is used first, it already counts against the limit.
+        let slack = self.slack;
+        let start = self.inner.grow(0)?.0.sub(slack);
+        if size <= slack {
+            self.slack -= size;
+            return Ok((start, size));
+        }
+        let (_, growth) = self.grow_within_limit(size - slack, false, grow)?;
+        self.slack = 0;
+        Ok((start, slack + growth))
+    }
+
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        // Only what was grown through the limit can be given back,
+        // the slack along with the end of the buffer.
+        if size > self.grown - self.slack {
+            return Err(MallocError::InvalidSize);
+        }
+        let heap_end = self.inner.shrink(size + self.slack)?;
+        self.grown -= size + self.slack;
+        self.slack = 0;
+        Ok(heap_end)
+    }
+
+    unsafe fn grow_region(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        // The slack of the current region is left behind.
+        self.grow_within_limit(size, true, |inner, size| unsafe { inner.grow_region(size) })
+    }
+}
+
+/// The number of bytes growers keep past the end of each region (see [`Grower::grow_region`]),
+/// where [`RawMalloc`](crate::RawMalloc) marks the end of a region once it moved on to the next.
+pub const REGION_FOOT_SIZE: usize = HEADER_SIZE;
+
+fn with_foot(size: usize) -> Result<usize, MallocError> {
+    size.checked_add(REGION_FOOT_SIZE).ok_or(MallocError::LayoutTooLarge)
+}
+
+/// A grower that grows a primary grower for as long as it can and then moves on
+/// to regions mapped with [`MmapGrower`]s, each reserving at least `region_size` bytes.
+///
+/// The primary grower alone fails for good once its buffer can't be extended, e.g. when
+/// the program break of a [`BrkGrower`] runs into a mapping. A `ChainGrower` keeps going
+/// in a new, discontiguous region instead (see [`Grower::grow_region`]), which
+/// [`RawMalloc`](crate::RawMalloc) treats as a separate part of its heap.
+/// Regions are never unmapped, although shrinking decommits their unused pages.
+/// Wrap a `ChainGrower` in a [`LimitGrower`] to limit the growth of all regions.
+///
+/// Past the end of its buffer, each region keeps [`REGION_FOOT_SIZE`] bytes
+/// that aren't reported as grown.
+///
+/// # Example
+/// ```
+/// use rusty_malloc::RustyMalloc;
+/// use rusty_malloc::growers::{BrkGrower, ChainGrower};
+///
+/// static ALLOCATOR: RustyMalloc<ChainGrower<BrkGrower>> =
+///     unsafe { RustyMalloc::with_grower(ChainGrower::new(BrkGrower::new(4096), 64 << 20)) };
+/// ```
+#[derive(Debug)]
+pub struct ChainGrower<G: Grower> {
+    primary: G,
+    /// The current region, `None` while the primary grower is used.
+    region: Option<MmapGrower>,
+    region_size: usize,
+    /// Whether the current region has grown, and thus holds its foot.
+    footed: bool,
+    /// The size of the current region, without its foot.
+    size: usize,
+}
+
+impl<G: Grower> ChainGrower<G> {
+    /// Creates a grower that grows `primary` and then regions of at least `region_size` bytes.
+    pub const fn new(primary: G, region_size: usize) -> Self {
+        ChainGrower {
+            primary,
+            region: None,
+            region_size,
+            footed: false,
+            size: 0,
+        }
+    }
+
+    /// Returns whether the primary grower is exhausted, that is the buffer was moved
+    /// to a mapped region.
+    pub fn in_region(&self) -> bool {
+        self.region.is_some()
+    }
+
+    fn current(&mut self) -> &mut dyn Grower {
+        match &mut self.region {
+            Some(region) => region,
+            None => &mut self.primary,
+        }
+    }
+}
+
+unsafe impl<G: Grower> Grower for ChainGrower<G> {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        if self.footed {
+            let (end, growth) = self.current().grow(size)?;
+            self.size += growth;
+            Ok((end.sub(REGION_FOOT_SIZE), growth))
+        } else if size == 0 {
+            self.current().grow(0)
+        } else {
+            let (start, growth) = self.current().grow(with_foot(size)?)?;
+            self.footed = true;
+            self.size = growth - REGION_FOOT_SIZE;
+            Ok((start, self.size))
+        }
+    }
+
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        if size > self.size {
+            return Err(MallocError::InvalidSize);
+        }
+        let end = self.current().shrink(size)?;
+        self.size -= size;
+        Ok(end.sub(if self.footed { REGION_FOOT_SIZE } else { 0 }))
+    }
+
+    unsafe fn grow_region(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        let footed_size = with_foot(size)?;
+        let mut region = MmapGrower::new(footed_size.max(self.region_size), 0);
+        let (start, growth) = region.grow(footed_size)?;
+        self.region = Some(region);
+        self.footed = true;
+        self.size = growth - REGION_FOOT_SIZE;
+        Ok((start, self.size))
     }
 }
 
 unsafe impl<T: Grower + ?Sized> Grower for &mut T {
-    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), ()> {
+    unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
         (*self).grow(size)
     }
+
+    unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+        (*self).shrink(size)
+    }
+
+    unsafe fn grow_region(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+        (*self).grow_region(size)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use arena_grower::ArenaGrower;
+
+    #[repr(C, align(16))]
+    struct AlignedBuf<const N: usize>([u8; N]);
 
     #[test]
     fn test_arena_grower_1() {
-        let mut buf = [0_u8; 2048];
-        let mut arena = ArenaGrower::new(buf.as_mut_ptr(), buf.len(), 0);
-        let p = buf.as_mut_ptr();
+        let mut buf = AlignedBuf([0_u8; 2048]);
+        let p = buf.0.as_mut_ptr();
+        let mut arena = ArenaGrower::new(&mut buf.0, 0);
         unsafe {
             assert_eq!(p, arena.grow(0).unwrap().0.as_ptr());
             assert_eq!(p, arena.grow(20).unwrap().0.as_ptr());
@@ -148,15 +689,15 @@ mod tests {
             assert_eq!(p.add(40), arena.grow(24).unwrap().0.as_ptr());
             assert_eq!(p.add(64), arena.grow(2048 - 64).unwrap().0.as_ptr());
             assert_eq!(p.add(2048), arena.grow(0).unwrap().0.as_ptr());
-            assert!(arena.grow(1).is_err());
+            assert_eq!(arena.grow(1), Err(MallocError::OutOfMemory));
             assert!(arena.grow(8).is_err());
         }
     }
 
     #[test]
     fn test_arena_grower_2() {
-        let mut buf = [0_u8; 64];
-        let mut arena = ArenaGrower::new(buf.as_mut_ptr(), 0, 0);
+        let mut buf = AlignedBuf([0_u8; 64]);
+        let mut arena = ArenaGrower::new(&mut buf.0[..0], 0);
         unsafe {
             assert!(arena.grow(1).is_err());
             assert!(arena.grow(4).is_err());
@@ -166,9 +707,9 @@ mod tests {
 
     #[test]
     fn test_arena_grower_3() {
-        let mut buf = [0_u8; 128];
-        let mut arena = ArenaGrower::new(buf.as_mut_ptr(), 19, 5);
-        let p = NonNull::new(buf.as_mut_ptr()).unwrap();
+        let mut buf = AlignedBuf([0_u8; 128]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let mut arena = ArenaGrower::new(&mut buf.0[..19], 5);
         unsafe {
             assert_eq!((p, 5), arena.grow(1).unwrap());
             assert_eq!((p.add(5), 5), arena.grow(4).unwrap());
@@ -180,13 +721,297 @@ mod tests {
 
     #[test]
     fn test_arena_grower_4() {
-        let mut buf = [0_u8; 128];
-        let mut arena = ArenaGrower::new(buf.as_mut_ptr(), 42, 16);
-        let p = NonNull::new(buf.as_mut_ptr()).unwrap();
+        let mut buf = AlignedBuf([0_u8; 128]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let mut arena = ArenaGrower::new(&mut buf.0[..42], 16);
         unsafe {
             assert_eq!((p, 16), arena.grow(1).unwrap());
             assert_eq!((p.add(16), 16), arena.grow(4).unwrap());
             assert!(arena.grow(18).is_err());
         }
     }
+
+    #[test]
+    fn test_arena_grower_5() {
+        let mut buf = AlignedBuf([0_u8; 128]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        // The arena start is misaligned on purpose so that it has to be adjusted.
+        let mut arena = ArenaGrower::new(&mut buf.0[1..100], 0);
+        assert_eq!(arena.remaining(), 100 - HEADER_ALIGN);
+        unsafe {
+            assert_eq!((p.add(HEADER_ALIGN), 20), arena.grow(20).unwrap());
+            assert_eq!(arena.remaining(), 80 - HEADER_ALIGN);
+            assert!(arena.grow(81 - HEADER_ALIGN).is_err());
+            assert_eq!(arena.remaining(), 80 - HEADER_ALIGN);
+            assert!(arena.grow(80 - HEADER_ALIGN).is_ok());
+            assert_eq!(arena.remaining(), 0);
+        }
+    }
+
+    #[test]
+    fn test_arena_grower_6() {
+        let mut buf = AlignedBuf([0_u8; 16]);
+        let mut arena = ArenaGrower::new(&mut buf.0[1..4], 0);
+        assert_eq!(arena.remaining(), 0);
+        unsafe { assert!(arena.grow(0).is_err()) };
+    }
+    #[test]
+    fn test_arena_grower_shrink() {
+        let mut buf = AlignedBuf([0_u8; 128]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let mut arena = ArenaGrower::new(&mut buf.0, 0);
+        unsafe {
+            assert_eq!(
+                arena.shrink(0),
+                Err(MallocError::Unsupported),
+                "Uninitialized arenas can't shrink."
+            );
+            assert_eq!((p, 64), arena.grow(64).unwrap());
+            assert_eq!(p.add(64), arena.shrink(0).unwrap());
+            assert_eq!(p.add(40), arena.shrink(24).unwrap());
+            assert_eq!(arena.remaining(), 88);
+            assert_eq!(arena.shrink(41), Err(MallocError::InvalidSize));
+            assert_eq!(p, arena.shrink(40).unwrap());
+            assert_eq!((p, 128), arena.grow(128).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_mmap_grower_1() {
+        let mut grower = MmapGrower::new(1 << 20, 0);
+        unsafe {
+            let (p, _) = grower.grow(0).unwrap();
+            assert_eq!(p.as_ptr() as usize % HEADER_ALIGN, 0);
+            assert_eq!((p, 100), grower.grow(100).unwrap());
+            assert_eq!((p.add(100), 8192), grower.grow(8192).unwrap());
+            // Committed memory should be writable.
+            p.as_ptr().write_bytes(0xAB, 8292);
+            assert_eq!(*p.as_ptr().add(8291), 0xAB);
+            assert_eq!(grower.grow(1 << 20), Err(MallocError::OutOfMemory));
+            assert_eq!(grower.grow(usize::MAX), Err(MallocError::LayoutTooLarge));
+            assert_eq!((p.add(8292), 0), grower.grow(0).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_mmap_grower_2() {
+        let mut grower = MmapGrower::new(1 << 16, 4096);
+        unsafe {
+            let (p, _) = grower.grow(0).unwrap();
+            assert_eq!((p, 4096), grower.grow(1).unwrap());
+            assert_eq!((p.add(4096), 8000), grower.grow(8000).unwrap());
+            assert_eq!((p.add(12096), (1 << 16) - 12096), grower.grow((1 << 16) - 12096).unwrap());
+            assert!(grower.grow(1).is_err());
+        }
+        assert_eq!(
+            unsafe { MmapGrower::new(0, 0).grow(0) },
+            Err(MallocError::OutOfMemory)
+        );
+    }
+
+    #[test]
+    fn test_mmap_grower_shrink() {
+        let page_size = MmapGrower::page_size();
+        let mut grower = MmapGrower::new(page_size * 16, 0);
+        unsafe {
+            let (p, _) = grower.grow(page_size * 4).unwrap();
+            p.as_ptr().write_bytes(0xAB, page_size * 4);
+            assert_eq!(p.add(page_size), grower.shrink(page_size * 3).unwrap());
+            assert_eq!(grower.committed_end, p.as_ptr().add(page_size));
+            assert_eq!(p.add(page_size - 8), grower.shrink(8).unwrap());
+            assert_eq!(grower.committed_end, p.as_ptr().add(page_size));
+            assert_eq!(grower.shrink(page_size), Err(MallocError::InvalidSize));
+
+            // Decommitted pages should be usable again after growing.
+            assert_eq!((p.add(page_size - 8), page_size), grower.grow(page_size).unwrap());
+            assert_eq!(*p.as_ptr().add(page_size), 0);
+            assert_eq!(*p.as_ptr(), 0xAB);
+        }
+    }
+
+    #[test]
+    fn test_limit_grower() {
+        let mut buf = AlignedBuf([0_u8; 256]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let limit = AtomicUsize::new(64);
+        let mut grower = LimitGrower::new(ArenaGrower::new(&mut buf.0, 0), &limit);
+        unsafe {
+            assert_eq!((p, 0), grower.grow(0).unwrap());
+            assert_eq!((p, 48), grower.grow(48).unwrap());
+            assert_eq!(grower.grow(24), Err(MallocError::LimitExceeded));
+            assert_eq!((p.add(48), 16), grower.grow(16).unwrap());
+            assert_eq!(grower.grown(), 64);
+
+            limit.store(128, Ordering::Relaxed);
+            assert_eq!((p.add(64), 64), grower.grow(64).unwrap());
+            // Shrunk memory no longer counts against the limit.
+            assert_eq!(p.add(96), grower.shrink(32).unwrap());
+            assert_eq!((p.add(96), 32), grower.grow(32).unwrap());
+
+            // Lowering the limit only refuses further growth.
+            limit.store(16, Ordering::Relaxed);
+            assert_eq!(grower.grown(), 128);
+            assert_eq!(grower.grow(1), Err(MallocError::LimitExceeded));
+            assert_eq!((p.add(128), 0), grower.grow(0).unwrap());
+
+            // Errors of the inner grower are passed through.
+            limit.store(usize::MAX, Ordering::Relaxed);
+            assert_eq!(grower.grow(256), Err(MallocError::OutOfMemory));
+        }
+    }
+
+    #[test]
+    fn test_limit_grower_shrink() {
+        let mut buf = AlignedBuf([0_u8; 256]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let mut arena = ArenaGrower::new(&mut buf.0, 0);
+        let limit = AtomicUsize::new(128);
+        unsafe {
+            arena.grow(64).unwrap();
+            let mut grower = LimitGrower::new(arena, &limit);
+            assert_eq!((p.add(64), 32), grower.grow(32).unwrap());
+            assert_eq!(grower.shrink(40), Err(MallocError::InvalidSize));
+            assert_eq!(grower.grown(), 32);
+            assert_eq!(p.add(64), grower.shrink(32).unwrap());
+            assert_eq!(grower.grown(), 0);
+        }
+    }
+
+    #[test]
+    fn test_limit_grower_min_increment() {
+        let mut buf = AlignedBuf([0_u8; 256]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let limit = AtomicUsize::new(100);
+        let mut grower = LimitGrower::new(ArenaGrower::new(&mut buf.0, 64), &limit);
+        unsafe {
+            assert_eq!((p, 64), grower.grow(16).unwrap());
+            // The surplus of the minimum increment past the limit is given back.
+            assert_eq!((p.add(64), 32), grower.grow(32).unwrap());
+            assert_eq!(grower.grown(), 96);
+            assert_eq!(grower.limit(), 100);
+        }
+    }
+
+    /// An [`ArenaGrower`] which can only give back its last growth as a whole.
+    struct UndoOnlyGrower<'a> {
+        arena: ArenaGrower<'a>,
+        last_growth: usize,
+    }
+
+    unsafe impl Grower for UndoOnlyGrower<'_> {
+        unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+            let (start, growth) = self.arena.grow(size)?;
+            self.last_growth = growth;
+            Ok((start, growth))
+        }
+
+        unsafe fn shrink(&mut self, size: usize) -> Result<NonNull<u8>, MallocError> {
+            if size != self.last_growth {
+                return Err(MallocError::Unsupported);
+            }
+            self.last_growth = 0;
+            self.arena.shrink(size)
+        }
+    }
+
+    #[test]
+    fn test_limit_grower_undo() {
+        let mut buf = AlignedBuf([0_u8; 256]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let limit = AtomicUsize::new(100);
+        let inner = UndoOnlyGrower {
+            arena: ArenaGrower::new(&mut buf.0, 64),
+            last_growth: 0,
+        };
+        let mut grower = LimitGrower::new(inner, &limit);
+        unsafe {
+            assert_eq!((p, 64), grower.grow(16).unwrap());
+            // The surplus past the limit can't be given back, so the growth is undone.
+            assert_eq!(grower.grow(32), Err(MallocError::LimitExceeded));
+            assert_eq!(grower.grown(), 64);
+            assert_eq!((p.add(64), 0), grower.grow(0).unwrap());
+        }
+    }
+
+    /// An [`ArenaGrower`] which can't shrink.
+    struct NoShrinkGrower<'a>(ArenaGrower<'a>);
+
+    unsafe impl Grower for NoShrinkGrower<'_> {
+        unsafe fn grow(&mut self, size: usize) -> Result<(NonNull<u8>, usize), MallocError> {
+            self.0.grow(size)
+        }
+    }
+
+    #[test]
+    fn test_limit_grower_slack() {
+        let mut buf = AlignedBuf([0_u8; 256]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let limit = AtomicUsize::new(100);
+        let mut grower = LimitGrower::new(NoShrinkGrower(ArenaGrower::new(&mut buf.0, 64)), &limit);
+        unsafe {
+            assert_eq!((p, 64), grower.grow(16).unwrap());
+            // The growth past the limit can't be given back, so it's kept as slack.
+            assert_eq!(grower.grow(32), Err(MallocError::LimitExceeded));
+            assert_eq!(grower.grown(), 128);
+            assert_eq!((p.add(64), 0), grower.grow(0).unwrap());
+            assert_eq!((p.add(64), 32), grower.grow(32).unwrap());
+            assert_eq!((p.add(96), 32), grower.grow(32).unwrap());
+            assert_eq!(grower.grow(8), Err(MallocError::LimitExceeded));
+            assert_eq!((p.add(128), 0), grower.grow(0).unwrap());
+            assert_eq!(grower.grown(), 128);
+        }
+    }
+
+    #[test]
+    fn test_chain_grower() {
+        let mut buf = AlignedBuf([0_u8; 128]);
+        let p = NonNull::new(buf.0.as_mut_ptr()).unwrap();
+        let mut grower = ChainGrower::new(ArenaGrower::new(&mut buf.0, 0), 1 << 16);
+        unsafe {
+            assert_eq!((p, 96), grower.grow(96).unwrap());
+            assert_eq!(grower.grow(64), Err(MallocError::OutOfMemory));
+            let growth = 32 - REGION_FOOT_SIZE;
+            assert_eq!((p.add(96), growth), grower.grow(growth).unwrap());
+            assert_eq!(
+                grower.grow(REGION_FOOT_SIZE),
+                Err(MallocError::OutOfMemory),
+                "The foot stays past the end of the buffer."
+            );
+            assert!(!grower.in_region());
+
+            let (region, growth) = grower.grow_region(64).unwrap();
+            assert!(grower.in_region());
+            assert_eq!(growth, 64);
+            assert_eq!(region.as_ptr() as usize % HEADER_ALIGN, 0);
+            region.as_ptr().write_bytes(0xAB, 64);
+            assert_eq!((region.add(64), 100), grower.grow(100).unwrap());
+            assert_eq!(region.add(132), grower.shrink(32).unwrap());
+            assert_eq!(
+                grower.shrink(133),
+                Err(MallocError::InvalidSize),
+                "Regions can't shrink below their start."
+            );
+            assert_eq!(grower.grow(1 << 16), Err(MallocError::OutOfMemory));
+
+            // Regions are at least as large as requested.
+            let (region, growth) = grower.grow_region(1 << 17).unwrap();
+            assert_eq!(growth, 1 << 17);
+            assert_eq!((region.add(1 << 17), 0), grower.grow(0).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_limit_grower_region() {
+        let limit = AtomicUsize::new(4096);
+        let mut grower = LimitGrower::new(ChainGrower::new(BrkGrower::new(0), 1 << 16), &limit);
+        unsafe {
+            assert_eq!(grower.grow_region(8192), Err(MallocError::LimitExceeded));
+            assert_eq!(grower.grow_region(4096).unwrap().1, 4096);
+            assert_eq!(grower.grown(), 4096);
+        }
+        assert_eq!(
+            unsafe { MmapGrower::new(1 << 16, 0).grow_region(8) },
+            Err(MallocError::Unsupported)
+        );
+    }
 }
@@ -5,19 +5,39 @@ use std::mem::{align_of, size_of};
 pub const HEADER_SIZE: usize = size_of::<Header>();
 pub const HEADER_ALIGN: usize = align_of::<Header>();
 
+/// With the `footers` feature free blocks end with a footer, which is just a copy of their header.
+pub const FOOTER_SIZE: usize = match cfg!(feature = "footers") {
+    true => HEADER_SIZE,
+    false => 0,
+};
+
+const TAG_BIT: usize = 1;
+const PREV_FREE_BIT: usize = 2;
+const QUARANTINE_BIT: usize = 1 << (usize::BITS - 1);
+
 /// Stores information about a block.
-/// Currently this is the block content size (excludes the size of the header itself)
-/// and whether the block is free or occupied.
+/// Currently this is the block content size (excludes the size of the header itself),
+/// whether the block is free or occupied and whether the block preceding it (in memory) is free.
 ///
 /// # Tagging
 /// To reduce the memory footprint of headers the block free status is kept
 /// in the least significant bit of the `__content_size` field. This technique is called
 /// tagging, in our case a tagged header denotes a free block and an untagged
-/// header denotes an occupied block.
+/// header denotes an occupied block. The second least significant bit is used in the same
+/// manner to mark that the previous block is free.
+/// The most significant bit marks occupied blocks which are in quarantine, it is always
+/// free since no block can be larger than `isize::MAX` bytes.
 ///
 /// Relying on tagging is safe since [`BLOCK_CONTENT_MIN_ALIGN`]
-/// is guaranteed to be at least 2 bytes and thus the size of any block content would always be
-/// divisible by 2.
+/// is guaranteed to be at least 4 bytes and thus the size of any block content would always be
+/// divisible by 4.
+///
+/// # Footers
+/// With the `footers` feature free blocks store a copy of their header (without the previous
+/// block bit) in the last [`FOOTER_SIZE`] bytes of their content. Together with the previous
+/// block bit this allows the allocator to find the start of a free block from the block that
+/// follows it, which is what makes coalescing with preceding blocks possible.
+/// Without the feature the previous block bit is never set.
 ///
 /// [`BLOCK_CONTENT_MIN_ALIGN`]: crate::allocators::raw_malloc::BLOCK_CONTENT_MIN_ALIGN
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -32,10 +52,10 @@ impl Header {
     /// Creates a new header for a block with the specified content size and free status.
     ///
     /// # Safety
-    /// This function is unsafe since it assumes that `content_size` is even.
+    /// This function is unsafe since it assumes that `content_size` is divisible by 4.
     #[inline(always)]
     pub unsafe fn new_unchecked(content_size: usize, is_free: bool) -> Header {
-        debug_assert_eq!(content_size % 2, 0, "size should be even.");
+        debug_assert_eq!(content_size % 4, 0, "size should be divisible by 4.");
         match is_free {
             true => (Header { __content_size: content_size }).tagged(),
             false => Header { __content_size: content_size },
@@ -45,25 +65,55 @@ impl Header {
     /// Returns a tagged version of the header.
     #[inline(always)]
     pub fn tagged(&self) -> Header {
-        Header { __content_size: self.__content_size | 1 }
+        Header { __content_size: self.__content_size | TAG_BIT }
     }
 
     /// Returns an untagged version of the header.
     #[inline(always)]
     pub fn untagged(&self) -> Header {
-        Header { __content_size: self.__content_size & !1 }
+        Header { __content_size: self.__content_size & !TAG_BIT }
     }
 
     /// Returns whether the header is tagged.
     #[inline(always)]
     pub fn is_tagged(&self) -> bool {
-        self.__content_size & 1 != 0
+        self.__content_size & TAG_BIT != 0
+    }
+
+    /// Returns a version of the header with the previous block bit set to `prev_free`.
+    #[inline(always)]
+    pub fn with_prev_free(&self, prev_free: bool) -> Header {
+        match prev_free {
+            true => Header { __content_size: self.__content_size | PREV_FREE_BIT },
+            false => Header { __content_size: self.__content_size & !PREV_FREE_BIT },
+        }
+    }
+
+    /// Returns whether the block preceding this one is free.
+    #[inline(always)]
+    pub fn prev_free(&self) -> bool {
+        self.__content_size & PREV_FREE_BIT != 0
+    }
+
+    /// Returns a version of the header with the quarantine bit set to `quarantined`.
+    #[inline(always)]
+    pub fn with_quarantined(&self, quarantined: bool) -> Header {
+        match quarantined {
+            true => Header { __content_size: self.__content_size | QUARANTINE_BIT },
+            false => Header { __content_size: self.__content_size & !QUARANTINE_BIT },
+        }
+    }
+
+    /// Returns whether the block is in quarantine.
+    #[inline(always)]
+    pub fn is_quarantined(&self) -> bool {
+        self.__content_size & QUARANTINE_BIT != 0
     }
 
     /// Returns the size of the block contents.
     #[inline(always)]
     pub fn content_size(&self) -> usize {
-        self.untagged().__content_size
+        self.untagged().with_prev_free(false).with_quarantined(false).__content_size
     }
 }
 
@@ -128,4 +178,40 @@ mod tests {
         assert_eq!(h.tagged(), h);
         assert_eq!(h.untagged().tagged(), h);
     }
+
+    #[test]
+    fn test_prev_free() {
+        let h = unsafe { Header::new_unchecked(20, true) };
+        assert!(!h.prev_free());
+
+        let h = h.with_prev_free(true);
+        assert!(h.prev_free());
+        assert!(h.is_tagged());
+        assert_eq!(h.content_size(), 20);
+        assert_eq!(h.__content_size, 23);
+
+        let h = h.untagged();
+        assert!(h.prev_free());
+        assert_eq!(h.content_size(), 20);
+
+        let h = h.with_prev_free(false);
+        assert!(!h.prev_free());
+        assert_eq!(h.content_size(), h.__content_size);
+    }
+
+    #[test]
+    fn test_quarantined() {
+        let h = unsafe { Header::new_unchecked(20, false) };
+        assert!(!h.is_quarantined());
+
+        let h = h.with_quarantined(true).with_prev_free(true);
+        assert!(h.is_quarantined());
+        assert!(!h.is_tagged());
+        assert_eq!(h.content_size(), 20);
+
+        let h = h.with_quarantined(false);
+        assert!(!h.is_quarantined());
+        assert!(h.prev_free());
+        assert_eq!(h.content_size(), 20);
+    }
 }
@@ -27,19 +27,24 @@
 //! Two allocators are exported by this crate - [`RawMalloc`]
 //! and [`RustyMalloc`]. Both of them can be used as either global or local allocators.
 //! Use [`RawMalloc`] if you are looking for a single-threaded allocator,
-//! [`RustyMalloc`] is just a `Mutex` wrapper over it to allow for multithreading.
+//! [`RustyMalloc`] allows for multithreading by giving each thread its own
+//! `Mutex`-guarded [`RawMalloc`] arena.
 //!
 //! # Mode of operation
 //! The allocator uses a straightforward [freelist](#freelist) algorithm:
 //! - When an allocation is requested a search for a suitable free block is
-//!   started (this is done by traversing the freelist).
-//!   The search is greedy meaning that the chosen block is
-//!   always the first found and might not be the best fit. A merging algorithm is
-//!   also applied to combine adjacent free blocks and increase their content capacity.
+//!   started (this is done by traversing the freelists, starting from the one
+//!   matching the size of the allocation).
+//!   By default the search is greedy meaning that the chosen block is
+//!   always the first found and might not be the best fit. Other strategies can be
+//!   selected through the allocators' [placement policy](allocators::raw_malloc::placement).
+//!   A merging algorithm is also applied to combine adjacent free blocks
+//!   and increase their content capacity.
 //! - If no block is found a request is dispatched to the allocators underlying
 //!   [grower](#growers) to give out more memory so that the object can be stored.
 //! - Lastly, on deallocation the allocator transforms the to-be-freed block into a freelist
-//!   node and prepends it to the freelist.
+//!   node and prepends it to the freelist. With the `footers` feature the block is first
+//!   coalesced with its free neighbours.
 //!
 //! Bellow is a list of the abstractions used by the allocators for operating on the heap:
 //!
@@ -54,6 +59,8 @@
 //! ## Headers
 //! At the beginning of each block there is a header holding all the essential metadata for that
 //! block, i.e. the size of the block contents or whether the block is free or occupied.
+//! With the `footers` feature free blocks additionally end with a footer (a copy of their header)
+//! which lets the block that follows them find where they start.
 //!
 //! ## Objects
 //! An objects or also an allocation is a memory region on the heap that was given-out by the allocator.
@@ -65,6 +72,11 @@
 //! of the program. When an allocation is freed, the allocator transforms it into a freelist node,
 //! which is then prepended to the freelist.
 //!
+//! The allocators actually keep an array of freelists, segregated by block size.
+//! Small blocks get a freelist for each possible size while larger blocks are grouped into
+//! power-of-two size classes. This way a search for a block can skip all blocks that are
+//! too small to begin with, which makes small allocations fast even on fragmented heaps.
+//!
 //! ## Growers
 //! A grower is the allocators' internal storage buffer.
 //! The [`RustyMalloc`] and [`RawMalloc`] allocators are generic over their growers
@@ -74,6 +86,13 @@
 //! by implementing the [`Grower`] trait for a `StackBuffer` struct
 //! and passing that struct as a parameter to [`RustyMalloc`] to manage it.
 //!
+//! Out of the box the crate provides a [`BrkGrower`], which operates on the program break,
+//! a [`MmapGrower`], which commits pages from an address range reserved with `mmap`
+//! and therefore does not interfere with other users of `brk`, and an [`ArenaGrower`],
+//! which hands out a fixed buffer and can be used to build bounded heaps.
+//! A [`ChainGrower`] moves on to new, discontiguous regions once its primary grower
+//! can't grow anymore, which [`RawMalloc`] handles by treating each region end as a heap end.
+//!
 //! # Takeaways
 //! As a project wrap-up I decided to bench the allocator to see whether it was
 //! at all comparable to the default [`System`] allocator that Rust uses.
@@ -102,13 +121,21 @@
 //! [`RawMalloc`]: allocators::RawMalloc
 //! [`RustyMalloc`]: allocators::RustyMalloc
 //! [`Grower`]: growers::Grower
+//! [`BrkGrower`]: growers::BrkGrower
+//! [`MmapGrower`]: growers::MmapGrower
+//! [`ArenaGrower`]: growers::ArenaGrower
+//! [`ChainGrower`]: growers::ChainGrower
 //! [`System`]: std::alloc::System
 #![feature(allocator_api)]
 
 pub use crate::allocators::RawMalloc;
 pub use crate::allocators::RustyMalloc;
+pub use crate::error::MallocError;
 
 pub mod allocators;
+#[cfg(feature = "cabi")]
+pub mod cabi;
+pub mod error;
 mod freelist;
 pub mod growers;
 mod header;
@@ -0,0 +1,177 @@
+#![feature(allocator_api)]
+
+use std::alloc::{Allocator, GlobalAlloc, Layout};
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::thread;
+
+use rusty_malloc::allocators::raw_malloc::{TraceOp, TraceReader};
+use rusty_malloc::allocators::rusty_malloc::{ARENA_CHUNK_MAX_SIZE, ARENA_CHUNK_MIN_SIZE};
+use rusty_malloc::growers::MmapGrower;
+use rusty_malloc::RustyMalloc;
+
+#[test]
+fn test_exited_thread_arena() {
+    let allocator = unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 0)) };
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+
+    // The object outlives the thread which allocated it from its arena.
+    let p = thread::scope(|s| {
+        s.spawn(|| unsafe { allocator.alloc(layout) } as usize)
+            .join()
+            .unwrap()
+    }) as *mut u8;
+    assert!(!p.is_null());
+    let stats = allocator.stats();
+    assert!(stats.in_use_bytes >= 1000 && stats.in_use_bytes < ARENA_CHUNK_MIN_SIZE);
+    assert!(stats.free_bytes >= ARENA_CHUNK_MIN_SIZE - 2000);
+
+    // Freeing the last object of the arena gives its chunk back to the main heap.
+    unsafe { allocator.dealloc(p, layout) };
+    assert_eq!(allocator.stats().in_use_bytes, 0);
+    assert_eq!(allocator.validate(), Ok(()));
+
+    // New threads take over the arena.
+    let q = thread::scope(|s| {
+        s.spawn(|| unsafe { allocator.alloc(layout) } as usize)
+            .join()
+            .unwrap()
+    }) as *mut u8;
+    assert_eq!(q, p);
+    unsafe { allocator.dealloc(q, layout) };
+
+    let heap_bytes = allocator.stats().heap_bytes;
+    assert_eq!(allocator.trim(0), heap_bytes);
+    assert_eq!(allocator.stats().heap_bytes, 0);
+}
+
+#[test]
+fn test_large_objects() {
+    let allocator = unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 0)) };
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let large = Layout::from_size_align(ARENA_CHUNK_MAX_SIZE * 2, 4096).unwrap();
+
+    let objects = thread::scope(|s| {
+        s.spawn(|| unsafe { [allocator.alloc(small), allocator.alloc(large)].map(|p| p as usize) })
+            .join()
+            .unwrap()
+    });
+    assert!(objects.iter().all(|&p| p != 0));
+    assert_eq!(objects[1] % 4096, 0);
+    assert_eq!(allocator.validate(), Ok(()));
+
+    let stats = allocator.stats();
+    assert_eq!(
+        stats.heap_bytes,
+        stats.in_use_bytes + stats.header_bytes + stats.free_bytes
+    );
+    unsafe {
+        allocator.dealloc(objects[1] as *mut u8, large);
+        allocator.dealloc(objects[0] as *mut u8, small);
+    }
+    assert_eq!(allocator.stats().in_use_bytes, 0);
+    assert_eq!(allocator.validate(), Ok(()));
+}
+
+#[test]
+fn test_thread_cache_flushed_at_exit() {
+    let allocator =
+        unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 0)).with_thread_cache() };
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            let objects: Vec<_> = (0..8).map(|i| Box::new_in([i; 32], &allocator)).collect();
+            drop(objects);
+            // The objects are kept in the thread's cache, unless freed objects are checked.
+            let cached = !cfg!(any(feature = "hardened", feature = "redzones"));
+            assert_eq!(allocator.stats().in_use_bytes != 0, cached);
+        })
+        .join()
+        .unwrap();
+    });
+    // The exiting thread flushed its cache and the empty arena gave its chunk back.
+    assert_eq!(allocator.stats().in_use_bytes, 0);
+    assert_eq!(allocator.validate(), Ok(()));
+}
+
+#[test]
+fn test_thread_cache_traced() {
+    let allocator =
+        unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 0)).with_thread_cache() };
+    let path =
+        std::env::temp_dir().join(format!("rusty_malloc_tcache_trace_{}", std::process::id()));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    allocator.set_trace_fd(Some(file.as_raw_fd()));
+
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let objects = thread::scope(|s| {
+        s.spawn(|| unsafe {
+            // The second object is served from the thread cache.
+            let p1 = allocator.alloc(layout);
+            allocator.dealloc(p1, layout);
+            let p2 = allocator.alloc(layout);
+            allocator.dealloc(p2, layout);
+            [p1 as usize, p2 as usize]
+        })
+        .join()
+        .unwrap()
+    });
+    allocator.set_trace_fd(None);
+
+    if !cfg!(feature = "trace") {
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        return;
+    }
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut records: Vec<_> = TraceReader::new(&file)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    records.sort_by_key(|r| r.timestamp);
+    let summary: Vec<_> = records
+        .iter()
+        .filter(|r| objects.contains(&r.ptr))
+        .map(|r| (r.op, r.ptr))
+        .collect();
+    // Flushing the cache when the thread exits doesn't record the deallocation again.
+    assert_eq!(
+        summary,
+        [
+            (TraceOp::Alloc, objects[0]),
+            (TraceOp::Dealloc, objects[0]),
+            (TraceOp::Alloc, objects[1]),
+            (TraceOp::Dealloc, objects[1]),
+        ]
+    );
+}
+
+#[test]
+fn test_allocate_usable_size() {
+    let allocator =
+        unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 0)).with_thread_cache() };
+    let layout = Layout::from_size_align(20, 8).unwrap();
+
+    thread::scope(|s| {
+        s.spawn(|| unsafe {
+            // The second object is served from the thread cache.
+            for _ in 0..2 {
+                let object = allocator.allocate(layout).unwrap();
+                let usable = RustyMalloc::<MmapGrower>::usable_size(object.as_ptr().cast());
+                assert_eq!(object.len(), usable);
+                assert!(usable >= layout.size());
+                allocator.deallocate(object.cast(), layout);
+            }
+        })
+        .join()
+        .unwrap();
+    });
+    assert_eq!(allocator.stats().in_use_bytes, 0);
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "cabi")]
+
+use core::ffi::c_void;
+use core::ptr::null_mut;
+
+use rusty_malloc::cabi::{
+    aligned_alloc, calloc, free, malloc, malloc_usable_size, posix_memalign, realloc,
+};
+
+#[test]
+fn test_malloc_free() {
+    unsafe {
+        let p = malloc(100).cast::<u8>();
+        assert!(!p.is_null());
+        assert_eq!(p as usize % 16, 0);
+        assert!(malloc_usable_size(p.cast()) >= 100);
+        p.write_bytes(42, 100);
+
+        let p = realloc(p.cast(), 1000).cast::<u8>();
+        assert!(!p.is_null());
+        assert!((0..100).all(|i| *p.add(i) == 42));
+        free(p.cast());
+        free(null_mut());
+    }
+}
+
+#[test]
+fn test_calloc() {
+    unsafe {
+        let p = malloc(64).cast::<u8>();
+        p.write_bytes(0xff, 64);
+        free(p.cast());
+
+        let p = calloc(8, 8).cast::<u8>();
+        assert!(!p.is_null());
+        assert!((0..64).all(|i| *p.add(i) == 0));
+        free(p.cast());
+
+        assert!(calloc(usize::MAX, 2).is_null());
+    }
+}
+
+#[test]
+fn test_aligned() {
+    unsafe {
+        for align in (3..12).map(|i| 1 << i) {
+            let p = aligned_alloc(align, 24);
+            assert_eq!(p as usize % align, 0);
+            // The object is moved with the alignment it was allocated with.
+            let p = realloc(p, 10_000);
+            assert_eq!(p as usize % align, 0);
+            free(p);
+
+            let mut p: *mut c_void = null_mut();
+            assert_eq!(posix_memalign(&mut p, align, 24), 0);
+            assert_eq!(p as usize % align, 0);
+            free(p);
+        }
+        let mut p: *mut c_void = null_mut();
+        assert_eq!(posix_memalign(&mut p, 3, 24), libc::EINVAL);
+    }
+}
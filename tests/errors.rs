@@ -0,0 +1,74 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use rusty_malloc::growers::{LimitGrower, MmapGrower};
+use rusty_malloc::{MallocError, RustyMalloc};
+
+#[test]
+fn test_last_failure() {
+    let allocator = unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 20, 0)) };
+    assert_eq!(allocator.last_failure(), None);
+
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let p = allocator.try_alloc_detailed(small).unwrap();
+    assert_eq!(allocator.last_failure(), None);
+
+    let huge = Layout::from_size_align(2 << 20, 8).unwrap();
+    assert_eq!(
+        allocator.try_alloc_detailed(huge),
+        Err(MallocError::OutOfMemory)
+    );
+    assert_eq!(allocator.last_failure(), Some(MallocError::OutOfMemory));
+
+    let too_large = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+    assert!(unsafe { allocator.alloc(too_large) }.is_null());
+    assert_eq!(allocator.last_failure(), Some(MallocError::LayoutTooLarge));
+    assert_eq!(
+        MallocError::LayoutTooLarge.to_string(),
+        "the layout is too large"
+    );
+
+    unsafe { allocator.dealloc(p.as_ptr(), small) };
+}
+
+#[test]
+fn test_last_failure_of_thread_arena() {
+    let allocator =
+        unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 20, 0)).with_thread_cache() };
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let huge = Layout::from_size_align(2 << 20, 8).unwrap();
+
+    // Threads allocate from their arenas and thread caches, whose failures are kept too.
+    let failure = thread::scope(|s| {
+        s.spawn(|| unsafe {
+            let p = allocator.alloc(small);
+            allocator.dealloc(p, small);
+            assert_eq!(allocator.try_alloc_detailed(small).unwrap().as_ptr(), p);
+            allocator.dealloc(p, small);
+            allocator.try_alloc_detailed(huge).unwrap_err()
+        })
+        .join()
+        .unwrap()
+    });
+    assert_eq!(failure, MallocError::OutOfMemory);
+    assert_eq!(allocator.last_failure(), Some(failure));
+}
+
+#[test]
+fn test_limit_exceeded() {
+    let limit = AtomicUsize::new(1 << 20);
+    let grower = LimitGrower::new(MmapGrower::new(1 << 30, 0), &limit);
+    let allocator = unsafe { RustyMalloc::with_grower(grower) };
+
+    let layout = Layout::from_size_align(2 << 20, 8).unwrap();
+    assert_eq!(
+        allocator.try_alloc_detailed(layout),
+        Err(MallocError::LimitExceeded)
+    );
+    assert_eq!(allocator.last_failure(), Some(MallocError::LimitExceeded));
+
+    limit.store(16 << 20, Ordering::Relaxed);
+    let p = allocator.try_alloc_detailed(layout).unwrap();
+    unsafe { allocator.dealloc(p.as_ptr(), layout) };
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "hardened")]
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::process::Command;
+
+use rusty_malloc::allocators::RawMalloc;
+use rusty_malloc::growers::ArenaGrower;
+
+const CHILD_VAR: &str = "RUSTY_MALLOC_HARDENED_CHILD";
+
+/// Runs `test` in a child process, since aborting would take down the test harness,
+/// and returns the child's stderr after checking that it aborted.
+fn run_aborting(test: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_double_free_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_double_free_aborts");
+        assert!(stderr.contains("rusty_malloc: double free of"), "{stderr}");
+        assert!(stderr.contains("in dealloc"), "{stderr}");
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        allocator.dealloc(p, layout);
+        allocator.dealloc(p, layout);
+    }
+}
+
+#[test]
+fn test_invalid_realloc_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_invalid_realloc_aborts");
+        assert!(stderr.contains("pointer outside of the heap"), "{stderr}");
+        assert!(stderr.contains("in realloc"), "{stderr}");
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut object = [0_usize; 8];
+    unsafe {
+        allocator.dealloc(allocator.alloc(layout), layout);
+        allocator.realloc(object.as_mut_ptr().add(1).cast(), layout, 128);
+    }
+}
+
+#[test]
+fn test_layout_mismatch_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_layout_mismatch_aborts");
+        assert!(stderr.contains("layout mismatch of"), "{stderr}");
+        assert!(
+            stderr.contains("allocated with size 64 and align 8 but given size 32 and align 8"),
+            "{stderr}"
+        );
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        allocator.dealloc(p, Layout::from_size_align(32, 8).unwrap());
+    }
+}
+
+#[test]
+fn test_quarantined_double_free_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_quarantined_double_free_aborts");
+        assert!(stderr.contains("rusty_malloc: double free of"), "{stderr}");
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    allocator.set_quarantine_budget(1024);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        allocator.dealloc(p, layout);
+        allocator.dealloc(p, layout);
+    }
+}
@@ -0,0 +1,37 @@
+use std::env;
+use std::process::Command;
+
+use rusty_malloc::growers::MmapGrower;
+use rusty_malloc::RustyMalloc;
+
+#[global_allocator]
+static ALLOCATOR: RustyMalloc<MmapGrower> =
+    unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 4096 * 64)) };
+
+const CHILD_VAR: &str = "RUSTY_MALLOC_LEAKS_CHILD";
+
+#[test]
+fn test_leak_report_at_exit() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["test_leak_report_at_exit", "--exact", "--nocapture"])
+            .env(CHILD_VAR, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("rusty_malloc: "), "{stderr}");
+        assert!(stderr.contains("live allocations taking"), "{stderr}");
+        assert!(stderr.contains("  1 x 4096 bytes (4096 bytes)"), "{stderr}");
+        assert_eq!(
+            stderr.contains("    #0 0x"),
+            cfg!(feature = "callsites"),
+            "{stderr}"
+        );
+        return;
+    }
+
+    ALLOCATOR.report_leaks_at_exit();
+    Box::leak(vec![1_u8; 4096].into_boxed_slice());
+    std::process::exit(0);
+}
@@ -0,0 +1,60 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use rusty_malloc::allocators::raw_malloc::{AllocFailure, OomAction};
+use rusty_malloc::growers::MmapGrower;
+use rusty_malloc::{MallocError, RustyMalloc};
+
+const RESERVE_SIZE: usize = 8 << 20;
+
+static ALLOCATOR: RustyMalloc<MmapGrower> =
+    unsafe { RustyMalloc::with_grower(MmapGrower::new(16 << 20, 0)) }
+        .with_oom_handler(release_reserve);
+
+/// An object the handler frees to make room for failed allocations.
+static RESERVE: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+static HANDLER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn reserve_layout() -> Layout {
+    Layout::from_size_align(RESERVE_SIZE, 8).unwrap()
+}
+
+fn release_reserve(failure: &AllocFailure) -> OomAction {
+    HANDLER_CALLS.fetch_add(1, Ordering::Relaxed);
+    assert_eq!(failure.error, MallocError::OutOfMemory);
+    assert!(failure.stats.heap_bytes >= RESERVE_SIZE);
+    let reserve = RESERVE.swap(std::ptr::null_mut(), Ordering::Relaxed);
+    if reserve.is_null() {
+        return OomAction::Fail;
+    }
+    unsafe { ALLOCATOR.dealloc(reserve, reserve_layout()) };
+    OomAction::Retry
+}
+
+#[test]
+fn test_oom_handler() {
+    let reserve = unsafe { ALLOCATOR.alloc(reserve_layout()) };
+    assert!(!reserve.is_null());
+    RESERVE.store(reserve, Ordering::Relaxed);
+
+    // The allocation only fits once the handler has freed the reserve.
+    let layout = Layout::from_size_align(RESERVE_SIZE, 8).unwrap();
+    let p = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!p.is_null());
+    assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), 1);
+
+    // Without a reserve left the handler gives up.
+    assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+    assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), 2);
+    assert_eq!(ALLOCATOR.last_failure(), Some(MallocError::OutOfMemory));
+
+    // Handlers aren't called for allocations that can never succeed.
+    let too_large = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+    assert!(unsafe { ALLOCATOR.alloc(too_large) }.is_null());
+    assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), 2);
+
+    ALLOCATOR.set_oom_handler(None);
+    assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+    assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), 2);
+    unsafe { ALLOCATOR.dealloc(p, layout) };
+}
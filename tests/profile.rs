@@ -0,0 +1,80 @@
+#![feature(allocator_api)]
+
+use rusty_malloc::growers::MmapGrower;
+use rusty_malloc::RustyMalloc;
+
+fn profiled_allocator(sample_interval: usize) -> RustyMalloc<MmapGrower> {
+    unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 4096 * 64)) }
+        .with_profiling(sample_interval)
+}
+
+#[test]
+fn test_live_samples() {
+    let allocator = profiled_allocator(1);
+    assert_eq!(allocator.profile_sample_interval(), 1);
+
+    let mut kept: Vec<u8, _> = Vec::with_capacity_in(1000, &allocator);
+    let freed: Vec<u8, _> = Vec::with_capacity_in(2000, &allocator);
+    let freed_ptr = freed.as_ptr();
+    drop(freed);
+
+    allocator.with_profile_samples(|samples| {
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].ptr, samples[0].size), (kept.as_ptr(), 1000));
+        assert!(samples[0].frames().len() > 1);
+        assert!(samples.iter().all(|sample| sample.ptr != freed_ptr));
+    });
+
+    // Reallocated objects are sampled with their new size.
+    kept.reserve_exact(4000);
+    allocator.with_profile_samples(|samples| {
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].ptr, samples[0].size), (kept.as_ptr(), 4000));
+    });
+    drop(kept);
+    allocator.with_profile_samples(|samples| assert!(samples.is_empty()));
+}
+
+#[test]
+fn test_sampling_rate() {
+    let allocator = profiled_allocator(4096);
+    let objects: Vec<Vec<u8, _>> = (0..1000)
+        .map(|_| Vec::with_capacity_in(1024, &allocator))
+        .collect();
+
+    // 1000 KiB sampled every 4 KiB on average.
+    let samples = allocator.with_profile_samples(|samples| samples.len());
+    assert!((150..=400).contains(&samples), "{samples} samples");
+    drop(objects);
+    allocator.with_profile_samples(|samples| assert!(samples.is_empty()));
+}
+
+#[test]
+fn test_dump_profile() {
+    let allocator = profiled_allocator(1);
+    let object: Vec<u8, _> = Vec::with_capacity_in(1000, &allocator);
+
+    let mut dump = vec![];
+    allocator.dump_profile(&mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    let mut lines = dump.lines();
+    assert_eq!(
+        lines.next(),
+        Some("heap profile: 1: 1000 [1: 1000] @ heap_v2/1")
+    );
+    assert!(
+        lines.next().unwrap().starts_with("1: 1000 [1: 1000] @ 0x"),
+        "{dump}"
+    );
+    assert!(dump.contains("\nMAPPED_LIBRARIES:\n"), "{dump}");
+    drop(object);
+
+    // Profiling is disabled by default.
+    let allocator = unsafe { RustyMalloc::with_grower(MmapGrower::new(1 << 30, 4096 * 64)) };
+    let _object: Vec<u8, _> = Vec::with_capacity_in(1000, &allocator);
+    let mut dump = vec![];
+    allocator.dump_profile(&mut dump).unwrap();
+    assert!(String::from_utf8(dump)
+        .unwrap()
+        .starts_with("heap profile: 0: 0 [0: 0] @ heap_v2/0\n"));
+}
@@ -0,0 +1,44 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::process::Command;
+
+use rusty_malloc::allocators::RawMalloc;
+use rusty_malloc::growers::ArenaGrower;
+
+const CHILD_VAR: &str = "RUSTY_MALLOC_QUARANTINE_CHILD";
+
+/// Runs `test` in a child process, since aborting would take down the test harness,
+/// and returns the child's stderr after checking that it aborted.
+fn run_aborting(test: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_use_after_free_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_use_after_free_aborts");
+        assert!(
+            stderr.contains("rusty_malloc: use after free of block"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("on quarantine release"), "{stderr}");
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    allocator.set_quarantine_budget(1024);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        allocator.dealloc(p, layout);
+        *p.add(32) = 1;
+        allocator.set_quarantine_budget(0);
+    }
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "redzones")]
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::process::Command;
+
+use rusty_malloc::allocators::RawMalloc;
+use rusty_malloc::growers::ArenaGrower;
+
+const CHILD_VAR: &str = "RUSTY_MALLOC_REDZONES_CHILD";
+
+/// Runs `test` in a child process, since aborting would take down the test harness,
+/// and returns the child's stderr after checking that it aborted.
+fn run_aborting(test: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_overflow_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_overflow_aborts");
+        assert!(
+            stderr.contains("rusty_malloc: right redzone of block"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("in dealloc"), "{stderr}");
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    let layout = Layout::from_size_align(61, 8).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        *p.add(layout.size()) = 0;
+        allocator.dealloc(p, layout);
+    }
+}
+
+#[test]
+fn test_underflow_aborts() {
+    if env::var_os(CHILD_VAR).is_none() {
+        let stderr = run_aborting("test_underflow_aborts");
+        assert!(stderr.contains("rusty_malloc: left redzone of"), "{stderr}");
+        assert!(stderr.contains("in realloc"), "{stderr}");
+        return;
+    }
+
+    let mut buf = vec![0_u8; 4096];
+    let allocator = unsafe { RawMalloc::with_grower(ArenaGrower::new(&mut buf, 0)) };
+    let layout = Layout::from_size_align(64, 16).unwrap();
+    unsafe {
+        let p = allocator.alloc(layout);
+        *p.sub(1) = 0;
+        allocator.realloc(p, layout, 128);
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::process::Command;
+
+use rusty_malloc::allocators::raw_malloc::trace::trace_header;
+use rusty_malloc::allocators::raw_malloc::{TraceOp, TraceRecord};
+
+fn record(op: TraceOp, timestamp: u64, ptr: usize, size: usize) -> TraceRecord {
+    TraceRecord {
+        op,
+        align: 8,
+        thread: 1,
+        timestamp,
+        ptr,
+        size,
+        new_ptr: 0,
+        new_size: 0,
+    }
+}
+
+fn replay(name: &str, trace: &[u8], args: &[&str]) -> (bool, String, String) {
+    let path = std::env::temp_dir().join(format!("rusty_malloc_{name}_{}", std::process::id()));
+    fs::write(&path, trace).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_replay"))
+        .arg(&path)
+        .args(args)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_replay() {
+    let realloc = TraceRecord {
+        new_ptr: 0x3000,
+        new_size: 300,
+        ..record(TraceOp::Realloc, 3, 0x1000, 100)
+    };
+    // Records of different heaps are out of order.
+    let records = [
+        record(TraceOp::Alloc, 1, 0x1000, 100),
+        record(TraceOp::Dealloc, 5, 0x2000, 200),
+        record(TraceOp::Alloc, 2, 0x2000, 200),
+        realloc,
+        record(TraceOp::Dealloc, 4, 0x4000, 400),
+        record(TraceOp::Dealloc, 6, 0x3000, 300),
+    ];
+    let mut trace = trace_header().to_vec();
+    for record in &records {
+        trace.extend_from_slice(&record.to_bytes());
+    }
+
+    for placement in ["first", "next", "best"] {
+        let (success, stdout, stderr) = replay(
+            "replay",
+            &trace,
+            &["--placement", placement, "--arena-size", "65536"],
+        );
+        assert!(success, "{stderr}");
+        let mut lines = stdout.lines();
+        assert!(lines.next().unwrap().starts_with("placement: "), "{stdout}");
+        assert_eq!(
+            lines.next(),
+            Some("replayed 5 operations (0 failed, 1 skipped)")
+        );
+        let peak: usize = lines
+            .next()
+            .and_then(|line| line.strip_prefix("peak heap size: "))
+            .and_then(|line| line.strip_suffix(" bytes"))
+            .and_then(|peak| peak.parse().ok())
+            .unwrap();
+        assert!(peak >= 500, "{stdout}");
+        for op in ["alloc", "dealloc", "realloc"] {
+            assert!(stdout.contains(&format!("\n  {op} ")), "{stdout}");
+        }
+        assert!(stdout.contains("\nheap over time:\n"), "{stdout}");
+    }
+}
+
+#[test]
+fn test_replay_errors() {
+    let (success, _, stderr) = replay("invalid_trace", b"not a trace at all", &[]);
+    assert!(!success);
+    assert!(stderr.contains("not a rusty_malloc trace"), "{stderr}");
+
+    let (success, _, stderr) = replay("invalid_args", &trace_header(), &["--placement", "worst"]);
+    assert!(!success);
+    assert!(stderr.contains("unknown placement worst"), "{stderr}");
+}
@@ -77,3 +77,37 @@ fn stress_test_2() {
     }
     assert_ne!(acc, u64::MAX);
 }
+
+#[test]
+fn stress_test_3() {
+    let thread_count = 16;
+    let mut handles = vec![];
+
+    for _ in 0..thread_count {
+        handles.push(thread::spawn(|| {
+            // Objects are allocated by this thread's arena and freed by another thread.
+            (0..1000)
+                .map(|_| vec![random::<u32>(); 1 + random::<usize>() % 1025])
+                .collect::<Vec<_>>()
+        }));
+    }
+
+    let mut acc = 0;
+    for handle in handles {
+        let vecs = handle.join().expect("Thread panicked.");
+        acc += vecs.iter().map(|v| v.len() as u64).sum::<u64>();
+    }
+    assert_ne!(acc, 0);
+    assert_eq!(ALLOCATOR.validate(), Ok(()));
+
+    let mut occupied = 0;
+    ALLOCATOR.for_each_block(|block| occupied += !block.is_free as usize);
+    assert_ne!(occupied, 0);
+
+    let stats = ALLOCATOR.stats();
+    assert_eq!(
+        stats.heap_bytes,
+        stats.in_use_bytes + stats.header_bytes + stats.free_bytes
+    );
+    assert_ne!(stats.requested_bytes, 0);
+}
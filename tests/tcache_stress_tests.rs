@@ -0,0 +1,76 @@
+use std::sync::mpsc;
+use std::thread;
+
+use rand::random;
+
+use rusty_malloc::growers::BrkGrower;
+use rusty_malloc::RustyMalloc;
+
+#[global_allocator]
+static ALLOCATOR: RustyMalloc<BrkGrower> =
+    unsafe { RustyMalloc::with_grower(BrkGrower::new(4096 * 64)).with_thread_cache() };
+
+#[test]
+fn tcache_stress_test_1() {
+    let thread_count = 16;
+    let mut handles = vec![];
+
+    for _ in 0..thread_count {
+        handles.push(thread::spawn(|| {
+            let mut sum = 0_u64;
+            // Small objects are freed to and reused from the thread cache.
+            for _ in 0..10_000 {
+                let objects: Vec<_> = (0..1 + random::<usize>() % 64)
+                    .map(|_| vec![random::<u8>(); random::<usize>() % 256])
+                    .collect();
+                sum += objects.iter().map(|v| v.len() as u64).sum::<u64>();
+            }
+            sum
+        }));
+    }
+
+    let mut acc = 0;
+    for handle in handles {
+        acc += handle.join().expect("Thread panicked.");
+    }
+    assert_ne!(acc, 0);
+    assert_eq!(ALLOCATOR.validate(), Ok(()));
+}
+
+#[test]
+fn tcache_stress_test_2() {
+    let thread_count = 16;
+    let (sender, receiver) = mpsc::channel::<Vec<Box<[u8]>>>();
+    let mut handles = vec![];
+
+    for _ in 0..thread_count {
+        let sender = sender.clone();
+        handles.push(thread::spawn(move || {
+            // Objects are cached by the thread freeing them, which didn't allocate them.
+            for _ in 0..100 {
+                let objects = (0..100)
+                    .map(|_| vec![random::<u8>(); random::<usize>() % 256].into_boxed_slice())
+                    .collect();
+                sender.send(objects).unwrap();
+            }
+        }));
+    }
+    drop(sender);
+
+    let mut count = 0;
+    for objects in receiver {
+        count += objects.len();
+    }
+    for handle in handles {
+        handle.join().expect("Thread panicked.");
+    }
+    assert_eq!(count, thread_count * 100 * 100);
+
+    ALLOCATOR.flush_thread_cache();
+    assert_eq!(ALLOCATOR.validate(), Ok(()));
+    let stats = ALLOCATOR.stats();
+    assert_eq!(
+        stats.heap_bytes,
+        stats.in_use_bytes + stats.header_bytes + stats.free_bytes
+    );
+}